@@ -1,22 +1,90 @@
+use crate::error::StravaError;
+use crate::ratelimit::{RateLimitStatus, RateLimiter};
 use crate::utils::Config;
 use anyhow::Context;
 use reqwest::{Client, Method, Url};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
 use tracing::{error, info, warn};
 
+/// Refresh the access token when it expires within this many seconds.
+const REFRESH_MARGIN_SECS: i64 = 300;
+/// How often a `429` is waited out before the response is handed back.
+const MAX_THROTTLE_RETRIES: usize = 2;
+
+/// Bounded exponential backoff applied to transient Strava failures.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt
+    pub max_retries: usize,
+    /// Delay before the first retry, doubled for each further one
+    pub base_delay: Duration,
+    /// Upper bound for a single delay before jitter
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Backoff for the given retry (0-based) with up to 50% random jitter added.
+    pub fn delay(&self, attempt: usize) -> Duration {
+        let exp = self.base_delay.saturating_mul(1u32 << attempt.min(16));
+        let delay = exp.min(self.max_delay);
+        delay + delay.mul_f64(fastrand::f64() * 0.5)
+    }
+}
+
 #[derive(Clone)]
 pub struct Auth {
     client: Client,
     pub cfg: Config,
     token: Arc<Mutex<Option<Token>>>,
+    limiter: Arc<RateLimiter>,
+    retry: RetryPolicy,
+    /// Whether a missing or rejected refresh token opens the browser flow
+    interactive: bool,
 }
 
 impl Auth {
     pub fn new(cfg: Config) -> Self {
-        Self { client: Client::new(), cfg, token: Arc::new(Mutex::new(None)) }
+        Self {
+            client: Client::new(),
+            cfg,
+            token: Arc::new(Mutex::new(None)),
+            limiter: Arc::new(RateLimiter::default()),
+            retry: RetryPolicy::default(),
+            interactive: false,
+        }
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Open the browser and wait for the OAuth redirect on port 8080 when no
+    /// usable refresh token is stored. Only for command-line use: without it
+    /// such requests fail with [`StravaError::Unauthorized`]. Clones share
+    /// the token, so a clone made for the web server stays non-interactive.
+    pub fn with_interactive_authorization(mut self, interactive: bool) -> Self {
+        self.interactive = interactive;
+        self
+    }
+
+    /// Current Strava API budget shared by every request made through this client.
+    pub fn rate_limit(&self) -> RateLimitStatus {
+        self.limiter.status()
     }
 
     async fn load_token(&self) -> Option<Token> {
@@ -30,26 +98,152 @@ impl Auth {
     async fn save_token(&self, token: &Token) -> anyhow::Result<()> {
         let path = &self.cfg.strava.token_path;
         let data = serde_json::to_string(token)?;
-        tokio::fs::write(path, data).await?;
+        crate::utils::write_atomic(std::path::Path::new(path), data.as_bytes()).await?;
         Ok(())
     }
 
+    /// Forget the cached token, e.g. after the athlete revoked access.
+    pub async fn clear_token(&self) -> anyhow::Result<()> {
+        *self.token.lock().await = None;
+        match tokio::fs::remove_file(&self.cfg.strava.token_path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Id of the athlete who authorized the stored token as recorded with
+    /// it, without asking Strava; `None` for tokens stored before the id was
+    /// recorded or when no token is stored.
+    pub async fn stored_athlete_id(&self) -> Option<u64> {
+        let cached = self.token.lock().await.clone();
+        match cached {
+            Some(t) => t.athlete_id,
+            None => self.load_token().await.and_then(|t| t.athlete_id),
+        }
+    }
+
+    /// Id of the athlete who authorized the stored token. Tokens stored
+    /// before the id was recorded look it up once via `GET /athlete`.
+    pub async fn athlete_id(&self) -> anyhow::Result<u64> {
+        let cached = self.token.lock().await.clone();
+        let token = match cached {
+            Some(t) => t,
+            None => self.load_token().await.context("no Strava token stored")?,
+        };
+        if let Some(id) = token.athlete_id {
+            return Ok(id);
+        }
+        #[derive(Deserialize)]
+        struct Athlete { id: u64 }
+        let athlete: Athlete = self.get_json(&format!("{}/athlete", self.cfg.base_url)).await?;
+        // the request may have refreshed the token in the meantime
+        let mut token = self.token.lock().await.clone().unwrap_or(token);
+        token.athlete_id = Some(athlete.id);
+        self.store_token(&token).await?;
+        Ok(athlete.id)
+    }
+
+    async fn store_token(&self, token: &Token) -> anyhow::Result<()> {
+        *self.token.lock().await = Some(token.clone());
+        self.save_token(token).await
+    }
+
     async fn ensure_token(&self) -> anyhow::Result<String> {
-        if let Some(tok) = self.token.lock().await.clone() {
-            if tok.expires_at > chrono::Utc::now().timestamp() {
-                return Ok(tok.access_token.clone());
-            }
+        let now = chrono::Utc::now().timestamp();
+        // held across the refresh, so concurrent requests wait for it instead
+        // of each spending the same rotating refresh token
+        let mut cached = self.token.lock().await;
+        if let Some(tok) = cached.as_ref().filter(|t| t.expires_at > now + REFRESH_MARGIN_SECS) {
+            return Ok(tok.access_token.clone());
         }
+        let mut current = cached.clone();
         if let Some(tok) = self.load_token().await {
-            if tok.expires_at > chrono::Utc::now().timestamp() {
-                *self.token.lock().await = Some(tok.clone());
+            if tok.expires_at > now + REFRESH_MARGIN_SECS {
+                *cached = Some(tok.clone());
                 return Ok(tok.access_token);
             }
+            current = Some(tok);
         }
-        let tok = self.authorize().await?;
+        let refresh_token = current.and_then(|t| t.refresh_token);
+        let tok = self.refresh_or_authorize(refresh_token.as_deref()).await?;
+        *cached = Some(tok.clone());
         Ok(tok.access_token)
     }
 
+    /// Token to retry with after Strava answered `401` to `rejected`. Another
+    /// request may have renewed it already; otherwise it is refreshed under
+    /// the token lock like in [`Auth::ensure_token`].
+    async fn renew_rejected(&self, rejected: &str) -> anyhow::Result<Token> {
+        let mut cached = self.token.lock().await;
+        if let Some(tok) = cached.as_ref().filter(|t| t.access_token != rejected) {
+            return Ok(tok.clone());
+        }
+        let refresh_token = cached.as_ref().and_then(|t| t.refresh_token.clone());
+        let tok = self.refresh_or_authorize(refresh_token.as_deref()).await?;
+        *cached = Some(tok.clone());
+        Ok(tok)
+    }
+
+    /// Exchange `refresh_token` for a new access token. Only an interactive
+    /// client falls back to the browser flow when Strava rejects the refresh
+    /// token or none is stored. The caller holds the token lock.
+    async fn refresh_or_authorize(&self, refresh_token: Option<&str>) -> anyhow::Result<Token> {
+        if let Some(rt) = refresh_token {
+            if let Some(tok) = self.refresh(rt).await? {
+                return Ok(tok);
+            }
+            if !self.interactive {
+                anyhow::bail!("refresh token rejected; run the authorize binary to sign in again");
+            }
+            warn!("refresh token rejected, falling back to interactive authorization");
+        } else if !self.interactive {
+            anyhow::bail!("no refresh token stored; run the authorize binary to sign in");
+        }
+        self.authorize().await
+    }
+
+    /// Returns `Ok(None)` when the refresh token itself is rejected.
+    async fn refresh(&self, refresh_token: &str) -> anyhow::Result<Option<Token>> {
+        info!("Refreshing access token");
+        let resp = self
+            .client
+            .post(self.token_url())
+            .form(&[
+                ("client_id", self.cfg.strava.client_id.as_str()),
+                ("client_secret", self.cfg.strava.client_secret.as_str()),
+                ("refresh_token", refresh_token),
+                ("grant_type", "refresh_token"),
+            ])
+            .send()
+            .await
+            .context("failed to send refresh request")?;
+        let status = resp.status();
+        if status == reqwest::StatusCode::BAD_REQUEST || status == reqwest::StatusCode::UNAUTHORIZED {
+            error!(%status, "refresh token rejected");
+            return Ok(None);
+        }
+        if !status.is_success() {
+            error!(%status, "refresh request failed");
+            anyhow::bail!("token refresh failed with status {}", status)
+        }
+        let mut token = resp.json::<TokenResponse>().await.context("failed to parse refresh response")?.into_token();
+        // Strava may omit the refresh token when it has not rotated
+        if token.refresh_token.is_none() {
+            token.refresh_token = Some(refresh_token.to_string());
+        }
+        if token.athlete_id.is_none() {
+            token.athlete_id = self.load_token().await.and_then(|t| t.athlete_id);
+        }
+        info!("Token refresh successful");
+        info!("Expires at (unix): {}", token.expires_at);
+        self.save_token(&token).await?;
+        Ok(Some(token))
+    }
+
+    fn token_url(&self) -> String {
+        format!("{}/oauth/token", self.cfg.base_url)
+    }
+
     async fn authorize(&self) -> anyhow::Result<Token> {
         let url = format!(
             "https://www.strava.com/oauth/authorize?client_id={}&response_type=code&redirect_uri=http://localhost:8080&approval_prompt=auto&scope=activity:read_all",
@@ -77,11 +271,9 @@ impl Auth {
         response.add_header(tiny_http::Header::from_bytes("Content-Type", "text/html").unwrap());
         let _ = request.respond(response);
 
-        #[derive(Deserialize)]
-        struct Resp { access_token: String, expires_at: i64 }
         let resp = self
             .client
-            .post("https://www.strava.com/api/v3/oauth/token")
+            .post(self.token_url())
             .form(&[
                 ("client_id", self.cfg.strava.client_id.as_str()),
                 ("client_secret", self.cfg.strava.client_secret.as_str()),
@@ -95,41 +287,79 @@ impl Auth {
             error!(%status, "token request failed");
             anyhow::bail!("token exchange failed")
         }
-        let tok = resp.json::<Resp>().await?;
+        let token = resp.json::<TokenResponse>().await?.into_token();
         info!("Token exchange successful");
-        info!("Access token: {}", tok.access_token);
-        info!("Expires at (unix): {}", tok.expires_at);
-        let token = Token {
-            access_token: tok.access_token,
-            expires_at: tok.expires_at,
-        };
-        *self.token.lock().await = Some(token.clone());
+        info!("Access token: {}", token.access_token);
+        info!("Expires at (unix): {}", token.expires_at);
         self.save_token(&token).await?;
         Ok(token)
     }
 
-    pub async fn request(&self, method: Method, url: &str) -> anyhow::Result<reqwest::Response> {
-        let token = self.ensure_token().await?;
+    pub async fn request(&self, method: Method, url: &str) -> Result<reqwest::Response, StravaError> {
+        let token = self
+            .ensure_token()
+            .await
+            .map_err(|e| StravaError::Unauthorized(format!("{:#}", e)))?;
         info!("HTTP {} {} with bearer {}", method.as_str(), url, token);
-        let req = self.client.request(method.clone(), url).bearer_auth(&token);
-        let mut resp = req.try_clone().unwrap().send().await?;
+        let mut resp = self.send(method.clone(), url, &token).await?;
         if resp.status() == reqwest::StatusCode::UNAUTHORIZED {
-            warn!("401 from Strava, requesting authorization");
-            let token = self.authorize().await?;
+            warn!("401 from Strava, refreshing access token");
+            let token = self
+                .renew_rejected(&token)
+                .await
+                .map_err(|e| StravaError::Unauthorized(format!("{:#}", e)))?;
             info!("Retrying {} {} with bearer {}", method.as_str(), url, token.access_token);
-            resp = self
-                .client
-                .request(method, url)
-                .bearer_auth(&token.access_token)
-                .send()
-                .await?;
+            resp = self.send(method, url, &token.access_token).await?;
         }
         Ok(resp)
     }
 
-    pub async fn get_json<T: for<'de> serde::Deserialize<'de>>(&self, url: &str) -> anyhow::Result<T> {
+    /// Send a request once the rate-limit budget allows it, waiting out `429`s.
+    async fn send(&self, method: Method, url: &str, token: &str) -> Result<reqwest::Response, reqwest::Error> {
+        let mut throttled = 0;
+        loop {
+            if let Some(wait) = self.limiter.wait_duration(chrono::Utc::now()) {
+                warn!(seconds = wait.as_secs(), "Strava rate limit exhausted, pausing requests");
+                tokio::time::sleep(wait).await;
+            }
+            let resp = self.client.request(method.clone(), url).bearer_auth(token).send().await?;
+            let now = chrono::Utc::now();
+            if resp.status() == reqwest::StatusCode::TOO_MANY_REQUESTS && throttled < MAX_THROTTLE_RETRIES {
+                warn!("429 from Strava, waiting for the next rate-limit window");
+                self.limiter.throttled(resp.headers(), now);
+                throttled += 1;
+                continue;
+            }
+            self.limiter.update(resp.headers(), now);
+            return Ok(resp);
+        }
+    }
+
+    /// GET `url` and decode the JSON body, retrying transient failures
+    /// according to the [`RetryPolicy`].
+    pub async fn get_json<T: for<'de> serde::Deserialize<'de>>(&self, url: &str) -> Result<T, StravaError> {
+        let mut attempt = 0;
+        loop {
+            match self.try_get_json(url).await {
+                Err(e) if e.is_transient() && attempt < self.retry.max_retries => {
+                    let delay = self.retry.delay(attempt);
+                    warn!(%e, attempt = attempt + 1, ?delay, "transient Strava error, retrying {}", url);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    async fn try_get_json<T: for<'de> serde::Deserialize<'de>>(&self, url: &str) -> Result<T, StravaError> {
         let resp = self.request(Method::GET, url).await?;
-        Ok(resp.json::<T>().await?)
+        let status = resp.status();
+        if !status.is_success() {
+            return Err(StravaError::from_status(status, self.limiter.status().paused_until));
+        }
+        let body = resp.bytes().await?;
+        serde_json::from_slice(&body).map_err(|e| StravaError::Decode(e.to_string()))
     }
 }
 
@@ -137,4 +367,35 @@ impl Auth {
 struct Token {
     access_token: String,
     expires_at: i64,
+    /// Older token files written before refresh support lack this field
+    #[serde(default)]
+    refresh_token: Option<String>,
+    /// Athlete who authorized the token, checked against webhook events
+    #[serde(default)]
+    athlete_id: Option<u64>,
+}
+
+/// Body of `POST /oauth/token`; the athlete is only included when an
+/// authorization code is exchanged.
+#[derive(Deserialize)]
+struct TokenResponse {
+    #[serde(flatten)]
+    token: Token,
+    #[serde(default)]
+    athlete: Option<TokenAthlete>,
+}
+
+#[derive(Deserialize)]
+struct TokenAthlete {
+    id: u64,
+}
+
+impl TokenResponse {
+    fn into_token(self) -> Token {
+        let mut token = self.token;
+        if let Some(athlete) = self.athlete {
+            token.athlete_id = Some(athlete.id);
+        }
+        token
+    }
 }
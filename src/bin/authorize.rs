@@ -4,12 +4,14 @@ use serde::Deserialize;
 use std::collections::HashMap;
 use tracing::{error, info};
 
-use abcy_data::utils::Config;
+use abcy_data::utils::{write_atomic, Config};
 
 #[derive(Deserialize, serde::Serialize)]
 struct Token {
     access_token: String,
     expires_at: i64,
+    #[serde(default)]
+    refresh_token: Option<String>,
 }
 
 #[tokio::main]
@@ -76,7 +78,7 @@ async fn main() -> anyhow::Result<()> {
     info!("Expires at (unix): {}", token.expires_at);
 
     let sanitized = serde_json::to_vec(&token)?;
-    tokio::fs::write(token_path, &sanitized).await?;
+    write_atomic(std::path::Path::new(token_path), &sanitized).await?;
     info!("Token saved to {}", token_path);
 
     Ok(())
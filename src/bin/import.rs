@@ -0,0 +1,36 @@
+use anyhow::Context;
+use tracing::{error, info};
+
+use abcy_data::import::import_file;
+use abcy_data::storage::Storage;
+use abcy_data::utils::Config;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let cfg = Config::load("config.toml")?;
+    let storage = Storage::from_config(&cfg).await?;
+    let files: Vec<String> = std::env::args().skip(1).collect();
+    if files.is_empty() {
+        anyhow::bail!("usage: import <file.fit|file.gpx|file.tcx>...");
+    }
+
+    let mut failed = 0;
+    for path in &files {
+        let data = tokio::fs::read(path)
+            .await
+            .with_context(|| format!("failed to read {}", path))?;
+        match import_file(&storage, path, &data).await {
+            Ok(id) => info!(id, "imported {}", path),
+            Err(e) => {
+                error!(?e, "failed to import {}", path);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{} of {} file(s) failed to import", failed, files.len());
+    }
+    Ok(())
+}
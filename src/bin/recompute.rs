@@ -0,0 +1,38 @@
+use anyhow::Context;
+use chrono::NaiveDate;
+
+use abcy_data::recompute::{recompute, RecomputeOptions};
+use abcy_data::storage::Storage;
+use abcy_data::utils::Config;
+
+const USAGE: &str = "usage: recompute [--from YYYY-MM-DD] [--to YYYY-MM-DD] [--dry-run]";
+
+fn parse_date(value: Option<String>) -> anyhow::Result<NaiveDate> {
+    let value = value.context(USAGE)?;
+    NaiveDate::parse_from_str(&value, "%Y-%m-%d").with_context(|| format!("invalid date '{}'", value))
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt().with_writer(std::io::stderr).init();
+
+    let mut opts = RecomputeOptions::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--from" => opts.from = Some(parse_date(args.next())?),
+            "--to" => opts.to = Some(parse_date(args.next())?),
+            "--dry-run" => opts.dry_run = true,
+            _ => anyhow::bail!(USAGE),
+        }
+    }
+
+    let cfg = Config::load("config.toml")?;
+    let storage = Storage::from_config(&cfg).await?;
+    let report = recompute(&storage, &opts).await?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    if !report.failed.is_empty() {
+        anyhow::bail!("{} activities could not be recomputed", report.failed.len());
+    }
+    Ok(())
+}
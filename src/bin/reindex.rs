@@ -0,0 +1,15 @@
+use tracing::info;
+
+use abcy_data::storage::Storage;
+use abcy_data::utils::Config;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let cfg = Config::load("config.toml")?;
+    let storage = Storage::from_config(&cfg).await?;
+    let count = storage.rebuild_index().await?;
+    info!(count, "activity index rebuilt");
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use anyhow::Context;
+use reqwest::Client;
+use tracing::info;
+
+use abcy_data::storage::Storage;
+use abcy_data::utils::Config;
+use abcy_data::webhook::{create_subscription, delete_subscription, list_subscriptions};
+
+const USAGE: &str = "usage: subscriptions <create [callback_url] | list | delete <id>>";
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let cfg = Config::load("config.toml")?;
+    let client = Client::new();
+    let storage = Storage::from_config(&cfg).await?;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match args.first().map(String::as_str) {
+        Some("create") => {
+            let callback_url = args
+                .get(1)
+                .cloned()
+                .or_else(|| cfg.webhook.callback_url.clone())
+                .context("no callback URL given and webhook.callback_url is not configured")?;
+            let id = create_subscription(&client, &cfg, &callback_url).await?;
+            storage.save_subscription_id(Some(id)).await?;
+            info!("Created subscription {} for {}", id, callback_url);
+            println!("{}", id);
+        }
+        Some("list") => {
+            let subs = list_subscriptions(&client, &cfg).await?;
+            info!("Found {} subscription(s)", subs.len());
+            println!("{}", serde_json::to_string_pretty(&subs)?);
+        }
+        Some("delete") => {
+            let id: u64 = args
+                .get(1)
+                .context(USAGE)?
+                .parse()
+                .context("subscription id must be a number")?;
+            delete_subscription(&client, &cfg, id).await?;
+            if storage.subscription_id().await? == Some(id) {
+                storage.save_subscription_id(None).await?;
+            }
+            info!("Deleted subscription {}", id);
+        }
+        _ => anyhow::bail!(USAGE),
+    }
+    Ok(())
+}
@@ -0,0 +1,383 @@
+//! Blob storage for activity files and the small JSON documents next to them.
+//!
+//! Keys are `/`-separated paths relative to the user directory, e.g.
+//! `2024/123/meta.json.zst` or `ftp.json`. [`FsBlobStore`] maps them onto
+//! `data_dir/user`, [`S3BlobStore`] onto objects in an S3-compatible bucket.
+//! Both replace a key atomically: the file store writes a temporary file and
+//! renames it, S3 only exposes an object once its upload completed.
+
+use anyhow::Context;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use tokio::fs;
+
+/// Immediate child of a key prefix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlobEntry {
+    pub name: String,
+    /// Whether the entry holds further keys rather than data
+    pub is_dir: bool,
+}
+
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    /// Contents of `key`, or `None` if it does not exist.
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>>;
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> anyhow::Result<()>;
+
+    async fn exists(&self, key: &str) -> anyhow::Result<bool>;
+
+    /// Entries directly below `prefix` (`""` for the top level).
+    async fn children(&self, prefix: &str) -> anyhow::Result<Vec<BlobEntry>>;
+
+    /// Remove `prefix` and every key below it.
+    async fn delete_prefix(&self, prefix: &str) -> anyhow::Result<()>;
+}
+
+/// Blobs stored as files below a base directory.
+pub struct FsBlobStore {
+    base: PathBuf,
+}
+
+impl FsBlobStore {
+    pub fn new(base: impl Into<PathBuf>) -> Self {
+        Self { base: base.into() }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.base.join(key)
+    }
+}
+
+#[async_trait]
+impl BlobStore for FsBlobStore {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        match fs::read(self.path(key)).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> anyhow::Result<()> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        crate::utils::write_atomic(&path, &data).await?;
+        Ok(())
+    }
+
+    async fn exists(&self, key: &str) -> anyhow::Result<bool> {
+        Ok(fs::metadata(self.path(key)).await.is_ok())
+    }
+
+    async fn children(&self, prefix: &str) -> anyhow::Result<Vec<BlobEntry>> {
+        let Ok(mut dir) = fs::read_dir(self.path(prefix)).await else {
+            return Ok(Vec::new());
+        };
+        let mut out = Vec::new();
+        while let Some(entry) = dir.next_entry().await? {
+            let name: String = entry.file_name().to_string_lossy().into();
+            if crate::utils::is_temp_file(&name) {
+                continue;
+            }
+            out.push(BlobEntry {
+                name,
+                is_dir: entry.file_type().await?.is_dir(),
+            });
+        }
+        Ok(out)
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> anyhow::Result<()> {
+        let path = self.path(prefix);
+        match fs::metadata(&path).await {
+            Ok(m) if m.is_dir() => fs::remove_dir_all(path).await?,
+            Ok(_) => fs::remove_file(path).await?,
+            Err(_) => {}
+        }
+        Ok(())
+    }
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Hex SHA-256 of `data`, as sent in `x-amz-content-sha256`.
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// Percent-encode everything but RFC 3986 unreserved characters, keeping `/`
+/// when `keep_slash` is set.
+fn uri_encode(s: &str, keep_slash: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => out.push(b as char),
+            b'/' if keep_slash => out.push('/'),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+/// Access key pair and region used for AWS Signature Version 4.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub access_key: String,
+    pub secret_key: String,
+    pub region: String,
+}
+
+/// Parts of an HTTP request covered by the signature. `path` and `query`
+/// are unencoded; `headers` must include `host`, `x-amz-date` and
+/// `x-amz-content-sha256`.
+pub struct SignedRequest<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub query: &'a [(&'a str, &'a str)],
+    pub headers: &'a [(&'a str, &'a str)],
+    pub payload_hash: &'a str,
+}
+
+impl Credentials {
+    /// `Authorization` header value for an S3 request sent at `now`.
+    pub fn authorization(&self, req: &SignedRequest, now: DateTime<Utc>) -> String {
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+
+        let mut query: Vec<(String, String)> = req.query.iter().map(|(k, v)| (uri_encode(k, false), uri_encode(v, false))).collect();
+        query.sort();
+        let canonical_query = query.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join("&");
+        let mut headers: Vec<(String, String)> = req
+            .headers
+            .iter()
+            .map(|(k, v)| (k.to_ascii_lowercase(), v.trim().to_string()))
+            .collect();
+        headers.sort();
+        let canonical_headers: String = headers.iter().map(|(k, v)| format!("{}:{}\n", k, v)).collect();
+        let signed_headers = headers.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>().join(";");
+        let canonical = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            req.method,
+            uri_encode(req.path, true),
+            canonical_query,
+            canonical_headers,
+            signed_headers,
+            req.payload_hash
+        );
+
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", amz_date, scope, sha256_hex(canonical.as_bytes()));
+        let mut key = hmac(format!("AWS4{}", self.secret_key).as_bytes(), &date);
+        for part in [self.region.as_str(), "s3", "aws4_request"] {
+            key = hmac(&key, part);
+        }
+        let signature = hex::encode(hmac(&key, &string_to_sign));
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key, scope, signed_headers, signature
+        )
+    }
+}
+
+/// Blobs stored as objects of an S3-compatible bucket (AWS, MinIO, R2…),
+/// addressed path-style as `{endpoint}/{bucket}/{root}/{key}`.
+pub struct S3BlobStore {
+    client: reqwest::Client,
+    endpoint: url::Url,
+    bucket: String,
+    root: String,
+    creds: Credentials,
+}
+
+impl S3BlobStore {
+    /// `root` is the key prefix all blobs are stored under, usually the user name.
+    pub fn new(endpoint: &str, bucket: &str, root: &str, creds: Credentials) -> anyhow::Result<Self> {
+        let endpoint = url::Url::parse(endpoint).context("invalid S3 endpoint")?;
+        if endpoint.host_str().is_none() {
+            anyhow::bail!("S3 endpoint has no host");
+        }
+        Ok(Self {
+            client: reqwest::Client::new(),
+            endpoint,
+            bucket: bucket.to_string(),
+            root: root.trim_matches('/').to_string(),
+            creds,
+        })
+    }
+
+    fn object_key(&self, key: &str) -> String {
+        let key = key.trim_matches('/');
+        match (self.root.is_empty(), key.is_empty()) {
+            (true, _) => key.to_string(),
+            (false, true) => self.root.clone(),
+            (false, false) => format!("{}/{}", self.root, key),
+        }
+    }
+
+    fn host(&self) -> String {
+        let host = self.endpoint.host_str().unwrap_or_default();
+        match self.endpoint.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        }
+    }
+
+    async fn send(&self, method: reqwest::Method, key: Option<&str>, query: &[(&str, &str)], body: Vec<u8>) -> anyhow::Result<reqwest::Response> {
+        let base = self.endpoint.path().trim_end_matches('/');
+        let path = match key {
+            Some(key) => format!("{}/{}/{}", base, self.bucket, key),
+            None => format!("{}/{}", base, self.bucket),
+        };
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let payload_hash = sha256_hex(&body);
+        let host = self.host();
+        let headers = [("host", host.as_str()), ("x-amz-content-sha256", payload_hash.as_str()), ("x-amz-date", amz_date.as_str())];
+        let auth = self.creds.authorization(
+            &SignedRequest { method: method.as_str(), path: &path, query, headers: &headers, payload_hash: &payload_hash },
+            now,
+        );
+
+        let mut url = self.endpoint.clone();
+        url.set_path(&uri_encode(&path, true));
+        url.set_query(None);
+        if !query.is_empty() {
+            let q = query
+                .iter()
+                .map(|(k, v)| format!("{}={}", uri_encode(k, false), uri_encode(v, false)))
+                .collect::<Vec<_>>()
+                .join("&");
+            url.set_query(Some(&q));
+        }
+        Ok(self
+            .client
+            .request(method, url)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", amz_date)
+            .header("authorization", auth)
+            .body(body)
+            .send()
+            .await?)
+    }
+
+    /// One page of `ListObjectsV2` below `prefix`: keys, common prefixes and
+    /// the continuation token of the next page.
+    async fn list_page(&self, prefix: &str, delimiter: bool, token: Option<&str>) -> anyhow::Result<(Vec<String>, Vec<String>, Option<String>)> {
+        let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+        if delimiter {
+            query.push(("delimiter", "/"));
+        }
+        if let Some(token) = token {
+            query.push(("continuation-token", token));
+        }
+        let resp = self.send(reqwest::Method::GET, None, &query, Vec::new()).await?;
+        let status = resp.status();
+        let text = resp.text().await?;
+        if !status.is_success() {
+            anyhow::bail!("S3 list {} failed with status {}", prefix, status);
+        }
+        let doc = roxmltree::Document::parse(&text)?;
+        let child_text = |node: roxmltree::Node, name: &str| {
+            node.children()
+                .find(|n| n.tag_name().name() == name)
+                .and_then(|n| n.text())
+                .map(str::to_string)
+        };
+        let mut keys = Vec::new();
+        let mut prefixes = Vec::new();
+        for node in doc.root_element().children() {
+            match node.tag_name().name() {
+                "Contents" => keys.extend(child_text(node, "Key")),
+                "CommonPrefixes" => prefixes.extend(child_text(node, "Prefix")),
+                _ => {}
+            }
+        }
+        let truncated = child_text(doc.root_element(), "IsTruncated").as_deref() == Some("true");
+        let next = if truncated { child_text(doc.root_element(), "NextContinuationToken") } else { None };
+        Ok((keys, prefixes, next))
+    }
+
+    async fn list_all(&self, prefix: &str, delimiter: bool) -> anyhow::Result<(Vec<String>, Vec<String>)> {
+        let (mut keys, mut prefixes, mut token) = self.list_page(prefix, delimiter, None).await?;
+        while let Some(t) = token {
+            let (k, p, next) = self.list_page(prefix, delimiter, Some(&t)).await?;
+            keys.extend(k);
+            prefixes.extend(p);
+            token = next;
+        }
+        Ok((keys, prefixes))
+    }
+
+    fn dir_prefix(&self, prefix: &str) -> String {
+        let key = self.object_key(prefix);
+        if key.is_empty() { key } else { format!("{}/", key) }
+    }
+}
+
+#[async_trait]
+impl BlobStore for S3BlobStore {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let resp = self.send(reqwest::Method::GET, Some(&self.object_key(key)), &[], Vec::new()).await?;
+        match resp.status().as_u16() {
+            404 => Ok(None),
+            s if (200..300).contains(&s) => Ok(Some(resp.bytes().await?.to_vec())),
+            s => anyhow::bail!("S3 get {} failed with status {}", key, s),
+        }
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> anyhow::Result<()> {
+        let resp = self.send(reqwest::Method::PUT, Some(&self.object_key(key)), &[], data).await?;
+        if !resp.status().is_success() {
+            anyhow::bail!("S3 put {} failed with status {}", key, resp.status());
+        }
+        Ok(())
+    }
+
+    async fn exists(&self, key: &str) -> anyhow::Result<bool> {
+        let resp = self.send(reqwest::Method::HEAD, Some(&self.object_key(key)), &[], Vec::new()).await?;
+        match resp.status().as_u16() {
+            404 => Ok(false),
+            s if (200..300).contains(&s) => Ok(true),
+            s => anyhow::bail!("S3 head {} failed with status {}", key, s),
+        }
+    }
+
+    async fn children(&self, prefix: &str) -> anyhow::Result<Vec<BlobEntry>> {
+        let dir = self.dir_prefix(prefix);
+        let (keys, prefixes) = self.list_all(&dir, true).await?;
+        let name = |s: &str| s[dir.len()..].trim_end_matches('/').to_string();
+        Ok(prefixes
+            .iter()
+            .map(|p| BlobEntry { name: name(p), is_dir: true })
+            .chain(keys.iter().map(|k| BlobEntry { name: name(k), is_dir: false }))
+            .filter(|e| !e.name.is_empty())
+            .collect())
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> anyhow::Result<()> {
+        let (mut keys, _) = self.list_all(&self.dir_prefix(prefix), false).await?;
+        if !prefix.trim_matches('/').is_empty() {
+            keys.push(self.object_key(prefix));
+        }
+        for key in &keys {
+            let resp = self.send(reqwest::Method::DELETE, Some(key), &[], Vec::new()).await?;
+            if !resp.status().is_success() && resp.status().as_u16() != 404 {
+                anyhow::bail!("S3 delete {} failed with status {}", key, resp.status());
+            }
+        }
+        Ok(())
+    }
+}
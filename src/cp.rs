@@ -0,0 +1,220 @@
+//! Critical Power models fitted to the best efforts of a power curve.
+//!
+//! - `2p`: Monod and Scherrer, `P = CP + W'/t`, fitted as the linear
+//!   work-time relation `P·t = W' + CP·t` over efforts from 2 to 20 minutes.
+//! - `3p`: Morton, `P = CP + W'/(t + k)` with `k = W'/(Pmax - CP)`, over
+//!   efforts up to 20 minutes.
+//! - `exp`: exponential decay `P = CP + (Pmax - CP)·e^(-t/τ)` with
+//!   `W' = (Pmax - CP)·τ`, over efforts up to 20 minutes.
+
+use chrono::{NaiveDate, Utc};
+use serde::Serialize;
+
+use crate::power::{BestEffort, DURATIONS};
+use crate::storage::Storage;
+
+/// Longest effort used for fitting; beyond it fatigue other than W'
+/// depletion dominates.
+const MAX_FIT_SECS: usize = 1200;
+/// Shortest effort of the two-parameter fit, which overestimates sprints.
+const MIN_FIT_SECS_2P: usize = 120;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum CpModelKind {
+    #[serde(rename = "2p")]
+    TwoParameter,
+    #[serde(rename = "3p")]
+    ThreeParameter,
+    #[serde(rename = "exp")]
+    Exponential,
+}
+
+impl CpModelKind {
+    /// Parse the `model` query value (`2p`, `3p` or `exp`).
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "2p" => Some(CpModelKind::TwoParameter),
+            "3p" => Some(CpModelKind::ThreeParameter),
+            "exp" => Some(CpModelKind::Exponential),
+            _ => None,
+        }
+    }
+}
+
+/// Fitted model parameters and goodness of fit.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CpFit {
+    pub model: CpModelKind,
+    /// Critical power in watts
+    pub cp: f64,
+    /// Work capacity above CP in joules
+    pub w_prime: f64,
+    /// Modelled instantaneous power; unbounded in the two-parameter model
+    pub pmax: Option<f64>,
+    /// Coefficient of determination of the modelled power
+    pub r_squared: f64,
+    /// Root-mean-square error of the modelled power in watts
+    pub rmse: f64,
+    /// Efforts the model was fitted to
+    pub points: usize,
+    #[serde(skip)]
+    shape: f64,
+}
+
+impl CpFit {
+    /// Modelled power sustainable for `secs` seconds.
+    pub fn power_at(&self, secs: f64) -> f64 {
+        match self.model {
+            CpModelKind::TwoParameter => self.cp + self.w_prime / secs,
+            CpModelKind::ThreeParameter => self.cp + self.w_prime / (secs + self.shape),
+            CpModelKind::Exponential => self.cp + self.w_prime / self.shape * (-secs / self.shape).exp(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CpCurvePoint {
+    pub duration: usize,
+    /// Modelled power
+    pub watts: f64,
+    /// Best effort in the range, if there is one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actual: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CpModel {
+    #[serde(flatten)]
+    pub fit: CpFit,
+    /// FTP in effect at the end of the range, for comparison with CP
+    pub ftp: f64,
+    pub curve: Vec<CpCurvePoint>,
+}
+
+/// Least-squares line `y = a + b·x`, returned as `(a, b)`.
+fn linear_fit(points: &[(f64, f64)]) -> Option<(f64, f64)> {
+    let n = points.len() as f64;
+    let mx = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let my = points.iter().map(|p| p.1).sum::<f64>() / n;
+    let sxx: f64 = points.iter().map(|p| (p.0 - mx).powi(2)).sum();
+    let sxy: f64 = points.iter().map(|p| (p.0 - mx) * (p.1 - my)).sum();
+    if sxx <= 0.0 {
+        return None;
+    }
+    let b = sxy / sxx;
+    Some((my - b * mx, b))
+}
+
+/// Minimum of `f` on `lo..=hi`: a coarse scan followed by golden-section
+/// search around the best sample.
+fn minimize(f: impl Fn(f64) -> f64, lo: f64, hi: f64) -> f64 {
+    const SAMPLES: usize = 200;
+    let step = (hi - lo) / SAMPLES as f64;
+    let best = (0..=SAMPLES)
+        .map(|i| lo + step * i as f64)
+        .min_by(|a, b| f(*a).total_cmp(&f(*b)))
+        .unwrap_or(lo);
+    let (mut a, mut b) = ((best - step).max(lo), (best + step).min(hi));
+    let ratio = (5f64.sqrt() - 1.0) / 2.0;
+    for _ in 0..100 {
+        let c = b - ratio * (b - a);
+        let d = a + ratio * (b - a);
+        if f(c) < f(d) {
+            b = d;
+        } else {
+            a = c;
+        }
+    }
+    (a + b) / 2.0
+}
+
+/// `(CP, W', shape)` of a model whose power is linear in CP and one more
+/// coefficient once the shape parameter is fixed.
+fn shaped_fit(efforts: &[(f64, f64)], x: impl Fn(f64, f64) -> f64, lo: f64, hi: f64) -> Option<(f64, f64, f64)> {
+    let fit = |shape: f64| {
+        let points: Vec<_> = efforts.iter().map(|&(t, p)| (x(t, shape), p)).collect();
+        linear_fit(&points)
+    };
+    let sse = |shape: f64| match fit(shape) {
+        Some((a, b)) => efforts.iter().map(|&(t, p)| (p - a - b * x(t, shape)).powi(2)).sum(),
+        None => f64::INFINITY,
+    };
+    let shape = minimize(sse, lo, hi);
+    fit(shape).map(|(a, b)| (a, b, shape))
+}
+
+/// Fit `model` to `(seconds, watts)` efforts. `None` without enough efforts
+/// in the model's duration range or when the fit is not physiological.
+pub fn fit(model: CpModelKind, efforts: &[(usize, f64)]) -> Option<CpFit> {
+    let min = match model {
+        CpModelKind::TwoParameter => MIN_FIT_SECS_2P,
+        _ => 1,
+    };
+    let used: Vec<(f64, f64)> = efforts
+        .iter()
+        .filter(|(t, p)| (min..=MAX_FIT_SECS).contains(t) && *p > 0.0)
+        .map(|&(t, p)| (t as f64, p))
+        .collect();
+    let needed = if model == CpModelKind::TwoParameter { 2 } else { 3 };
+    if used.len() < needed {
+        return None;
+    }
+    let (cp, w_prime, pmax, shape) = match model {
+        CpModelKind::TwoParameter => {
+            let work: Vec<_> = used.iter().map(|&(t, p)| (t, p * t)).collect();
+            let (w_prime, cp) = linear_fit(&work)?;
+            (cp, w_prime, None, 0.0)
+        }
+        CpModelKind::ThreeParameter => {
+            let (cp, w_prime, k) = shaped_fit(&used, |t, k| 1.0 / (t + k), 0.0, 600.0)?;
+            (cp, w_prime, (k > 0.0).then(|| cp + w_prime / k), k)
+        }
+        CpModelKind::Exponential => {
+            let (cp, amplitude, tau) = shaped_fit(&used, |t, tau| (-t / tau).exp(), 1.0, 3600.0)?;
+            (cp, amplitude * tau, Some(cp + amplitude), tau)
+        }
+    };
+    if !(cp > 0.0 && w_prime > 0.0) {
+        return None;
+    }
+    let mut fit = CpFit { model, cp, w_prime, pmax, r_squared: 0.0, rmse: 0.0, points: used.len(), shape };
+    let mean = used.iter().map(|p| p.1).sum::<f64>() / used.len() as f64;
+    let sse: f64 = used.iter().map(|&(t, p)| (p - fit.power_at(t)).powi(2)).sum();
+    let sst: f64 = used.iter().map(|&(_, p)| (p - mean).powi(2)).sum();
+    fit.r_squared = if sst > 0.0 { 1.0 - sse / sst } else { 1.0 };
+    fit.rmse = (sse / used.len() as f64).sqrt();
+    Some(fit)
+}
+
+/// Modelled power at every duration in [`DURATIONS`] next to the best
+/// efforts it was fitted to.
+pub fn model_curve(fit: &CpFit, efforts: &[BestEffort]) -> Vec<CpCurvePoint> {
+    DURATIONS
+        .iter()
+        .map(|&d| CpCurvePoint {
+            duration: d,
+            watts: fit.power_at(d as f64),
+            actual: efforts.iter().find(|e| e.duration == d).map(|e| e.watts),
+        })
+        .collect()
+}
+
+impl Storage {
+    /// Fit `model` to the power curve of `from..=to`, see
+    /// [`Storage::power_curve`]. `None` when the efforts do not support a fit.
+    pub async fn cp_model(
+        &self,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+        model: CpModelKind,
+    ) -> anyhow::Result<Option<CpModel>> {
+        let efforts = self.power_curve(from, to).await?;
+        let points: Vec<_> = efforts.iter().map(|e| (e.duration, e.watts)).collect();
+        let Some(fit) = fit(model, &points) else {
+            return Ok(None);
+        };
+        let ftp = self.ftp_at(&to.unwrap_or_else(|| Utc::now().date_naive()).to_string()).await?;
+        let curve = model_curve(&fit, &efforts);
+        Ok(Some(CpModel { fit, ftp, curve }))
+    }
+}
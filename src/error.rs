@@ -0,0 +1,64 @@
+use chrono::{DateTime, Utc};
+use std::fmt;
+
+/// Failure of a single Strava API call.
+#[derive(Debug)]
+pub enum StravaError {
+    /// The request could not be sent or the body could not be read
+    Network(String),
+    /// Strava kept answering `429 Too Many Requests` after the rate-limit
+    /// windows were waited out
+    RateLimited { until: Option<DateTime<Utc>> },
+    /// No valid access token could be obtained or Strava refused it (401)
+    Unauthorized(String),
+    /// The requested resource does not exist (or is private)
+    NotFound,
+    /// Strava answered with a 5xx status
+    Server(u16),
+    /// Any other unexpected 4xx status, e.g. 403 for a resource the token
+    /// may not read
+    Client(u16),
+    /// The response body was not the expected JSON
+    Decode(String),
+}
+
+impl StravaError {
+    /// Whether retrying the same request later may succeed. `429`s are
+    /// already waited out when sending, so a `RateLimited` error is final.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, StravaError::Network(_) | StravaError::Server(_))
+    }
+
+    pub(crate) fn from_status(status: reqwest::StatusCode, until: Option<DateTime<Utc>>) -> Self {
+        match status.as_u16() {
+            401 => StravaError::Unauthorized(format!("status {}", status)),
+            404 => StravaError::NotFound,
+            429 => StravaError::RateLimited { until },
+            s if s >= 500 => StravaError::Server(s),
+            s => StravaError::Client(s),
+        }
+    }
+}
+
+impl fmt::Display for StravaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StravaError::Network(e) => write!(f, "network error: {}", e),
+            StravaError::RateLimited { until: Some(t) } => write!(f, "rate limited until {}", t),
+            StravaError::RateLimited { until: None } => write!(f, "rate limited"),
+            StravaError::Unauthorized(e) => write!(f, "unauthorized: {}", e),
+            StravaError::NotFound => write!(f, "not found"),
+            StravaError::Server(s) => write!(f, "server error (status {})", s),
+            StravaError::Client(s) => write!(f, "unexpected status {}", s),
+            StravaError::Decode(e) => write!(f, "failed to decode response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StravaError {}
+
+impl From<reqwest::Error> for StravaError {
+    fn from(e: reqwest::Error) -> Self {
+        StravaError::Network(e.to_string())
+    }
+}
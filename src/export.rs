@@ -0,0 +1,269 @@
+//! Export of stored activities as GPX, TCX or FIT files.
+//!
+//! Files are rebuilt from the parsed streams and the meta start time, so
+//! activities synced from Strava and imported ones export alike.
+
+use crate::fit::{self, FitActivity, FitRecord, FitSession};
+use crate::schema::ActivityDetail;
+use chrono::{DateTime, Utc};
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Gpx,
+    Tcx,
+    Fit,
+}
+
+impl ExportFormat {
+    /// Parse the `format` query value (`gpx`, `tcx` or `fit`).
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "gpx" => Some(ExportFormat::Gpx),
+            "tcx" => Some(ExportFormat::Tcx),
+            "fit" => Some(ExportFormat::Fit),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Gpx => "gpx",
+            ExportFormat::Tcx => "tcx",
+            ExportFormat::Fit => "fit",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Gpx => "application/gpx+xml",
+            ExportFormat::Tcx => "application/vnd.garmin.tcx+xml",
+            ExportFormat::Fit => "application/vnd.ant.fit",
+        }
+    }
+}
+
+/// One exported sample; heart rate 0 is treated as a dropout.
+struct Sample {
+    time: i64,
+    latlng: Option<[f64; 2]>,
+    altitude: Option<f64>,
+    distance: Option<f64>,
+    speed: Option<f64>,
+    heartrate: Option<i64>,
+    cadence: Option<i64>,
+    power: Option<i64>,
+    temp: Option<i64>,
+}
+
+struct Export<'a> {
+    name: &'a str,
+    activity_type: &'a str,
+    start: i64,
+    samples: Vec<Sample>,
+}
+
+fn start_time(detail: &ActivityDetail) -> anyhow::Result<i64> {
+    let start = detail.meta["start_date"]
+        .as_str()
+        .ok_or_else(|| anyhow::anyhow!("activity has no start date"))?;
+    Ok(DateTime::parse_from_rfc3339(start)?.timestamp())
+}
+
+fn prepare(detail: &ActivityDetail) -> anyhow::Result<Export<'_>> {
+    let s = &detail.streams;
+    if s.time.is_empty() {
+        anyhow::bail!("activity has no time stream");
+    }
+    let start = start_time(detail)?;
+    let at = |v: &Option<Vec<f64>>, i: usize| v.as_ref().and_then(|v| v.get(i).copied());
+    let samples = s
+        .time
+        .iter()
+        .enumerate()
+        .map(|(i, t)| Sample {
+            time: start + t,
+            latlng: s.latlng.as_ref().and_then(|v| v.get(i).copied()),
+            altitude: at(&s.altitude, i),
+            distance: at(&s.distance, i),
+            speed: at(&s.velocity_smooth, i),
+            heartrate: s.heartrate.get(i).copied().filter(|hr| *hr > 0),
+            cadence: s.cadence.as_ref().and_then(|v| v.get(i).copied()),
+            power: s.power.get(i).copied(),
+            temp: s.temp.as_ref().and_then(|v| v.get(i).copied()),
+        })
+        .collect();
+    Ok(Export {
+        name: detail.meta["name"].as_str().unwrap_or("Activity"),
+        activity_type: detail.meta["type"].as_str().unwrap_or("Ride"),
+        start,
+        samples,
+    })
+}
+
+fn iso(ts: i64) -> String {
+    DateTime::<Utc>::from_timestamp(ts, 0)
+        .unwrap_or_default()
+        .format("%Y-%m-%dT%H:%M:%SZ")
+        .to_string()
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn gpx(e: &Export) -> String {
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str("<gpx version=\"1.1\" creator=\"abcy-data\" xmlns=\"http://www.topografix.com/GPX/1/1\" xmlns:gpxtpx=\"http://www.garmin.com/xmlschemas/TrackPointExtension/v1\">\n");
+    let _ = writeln!(out, "  <metadata><time>{}</time></metadata>", iso(e.start));
+    let _ = writeln!(out, "  <trk>\n    <name>{}</name>\n    <type>{}</type>\n    <trkseg>", escape(e.name), escape(e.activity_type));
+    // GPX trackpoints require a position, so samples without one are dropped.
+    for s in &e.samples {
+        let Some([lat, lng]) = s.latlng else { continue };
+        let _ = write!(out, "      <trkpt lat=\"{:.7}\" lon=\"{:.7}\">", lat, lng);
+        if let Some(alt) = s.altitude {
+            let _ = write!(out, "<ele>{:.1}</ele>", alt);
+        }
+        let _ = write!(out, "<time>{}</time>", iso(s.time));
+        if s.power.is_some() || s.heartrate.is_some() || s.cadence.is_some() || s.temp.is_some() {
+            out.push_str("<extensions>");
+            if let Some(p) = s.power {
+                let _ = write!(out, "<power>{}</power>", p);
+            }
+            out.push_str("<gpxtpx:TrackPointExtension>");
+            if let Some(t) = s.temp {
+                let _ = write!(out, "<gpxtpx:atemp>{}</gpxtpx:atemp>", t);
+            }
+            if let Some(hr) = s.heartrate {
+                let _ = write!(out, "<gpxtpx:hr>{}</gpxtpx:hr>", hr);
+            }
+            if let Some(cad) = s.cadence {
+                let _ = write!(out, "<gpxtpx:cad>{}</gpxtpx:cad>", cad);
+            }
+            out.push_str("</gpxtpx:TrackPointExtension></extensions>");
+        }
+        out.push_str("</trkpt>\n");
+    }
+    out.push_str("    </trkseg>\n  </trk>\n</gpx>\n");
+    out
+}
+
+fn tcx_sport(activity_type: &str) -> &'static str {
+    match activity_type {
+        t if t.contains("Ride") => "Biking",
+        t if t.contains("Run") => "Running",
+        _ => "Other",
+    }
+}
+
+fn tcx(e: &Export) -> String {
+    let start = iso(e.start);
+    let elapsed = e.samples.last().map(|s| s.time - e.start).unwrap_or(0);
+    let distance = e.samples.iter().rev().find_map(|s| s.distance).unwrap_or(0.0);
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str("<TrainingCenterDatabase xmlns=\"http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2\" xmlns:ns3=\"http://www.garmin.com/xmlschemas/ActivityExtension/v2\">\n");
+    let _ = writeln!(out, "  <Activities>\n    <Activity Sport=\"{}\">\n      <Id>{}</Id>", tcx_sport(e.activity_type), start);
+    let _ = writeln!(out, "      <Lap StartTime=\"{}\">", start);
+    let _ = writeln!(out, "        <TotalTimeSeconds>{}</TotalTimeSeconds>\n        <DistanceMeters>{:.1}</DistanceMeters>", elapsed, distance);
+    out.push_str("        <Intensity>Active</Intensity>\n        <TriggerMethod>Manual</TriggerMethod>\n        <Track>\n");
+    for s in &e.samples {
+        let _ = write!(out, "          <Trackpoint><Time>{}</Time>", iso(s.time));
+        if let Some([lat, lng]) = s.latlng {
+            let _ = write!(out, "<Position><LatitudeDegrees>{:.7}</LatitudeDegrees><LongitudeDegrees>{:.7}</LongitudeDegrees></Position>", lat, lng);
+        }
+        if let Some(alt) = s.altitude {
+            let _ = write!(out, "<AltitudeMeters>{:.1}</AltitudeMeters>", alt);
+        }
+        if let Some(d) = s.distance {
+            let _ = write!(out, "<DistanceMeters>{:.1}</DistanceMeters>", d);
+        }
+        if let Some(hr) = s.heartrate {
+            let _ = write!(out, "<HeartRateBpm><Value>{}</Value></HeartRateBpm>", hr);
+        }
+        if let Some(cad) = s.cadence {
+            let _ = write!(out, "<Cadence>{}</Cadence>", cad);
+        }
+        if s.speed.is_some() || s.power.is_some() {
+            out.push_str("<Extensions><ns3:TPX>");
+            if let Some(v) = s.speed {
+                let _ = write!(out, "<ns3:Speed>{:.3}</ns3:Speed>", v);
+            }
+            if let Some(p) = s.power {
+                let _ = write!(out, "<ns3:Watts>{}</ns3:Watts>", p);
+            }
+            out.push_str("</ns3:TPX></Extensions>");
+        }
+        out.push_str("</Trackpoint>\n");
+    }
+    out.push_str("        </Track>\n      </Lap>\n    </Activity>\n  </Activities>\n</TrainingCenterDatabase>\n");
+    out
+}
+
+/// FIT `sport` and `sub_sport` for a Strava activity type.
+fn fit_sport(activity_type: &str) -> (u8, u8) {
+    match activity_type {
+        "VirtualRide" => (2, 58),
+        t if t.contains("Ride") => (2, 0),
+        t if t.contains("Run") => (1, 0),
+        "Walk" | "Hike" => (11, 0),
+        "Swim" => (5, 0),
+        _ => (0, 0),
+    }
+}
+
+fn fit(e: &Export, detail: &ActivityDetail) -> Vec<u8> {
+    let meta = &detail.meta;
+    let (sport, sub_sport) = fit_sport(e.activity_type);
+    let elapsed = e.samples.last().map(|s| (s.time - e.start) as f64);
+    let records = e
+        .samples
+        .iter()
+        .map(|s| FitRecord {
+            timestamp: s.time,
+            lat: s.latlng.map(|l| l[0]),
+            lng: s.latlng.map(|l| l[1]),
+            altitude: s.altitude,
+            heart_rate: s.heartrate,
+            cadence: s.cadence,
+            distance: s.distance,
+            speed: s.speed,
+            power: s.power,
+            temperature: s.temp,
+        })
+        .collect();
+    let activity = FitActivity {
+        time_created: Some(e.start),
+        session: Some(FitSession {
+            start_time: Some(e.start),
+            sport: Some(sport),
+            sub_sport: Some(sub_sport),
+            total_elapsed_time: meta["elapsed_time"].as_f64().or(elapsed),
+            total_timer_time: meta["moving_time"].as_f64().or(elapsed),
+            total_distance: meta["distance"].as_f64(),
+            total_ascent: meta["total_elevation_gain"].as_f64(),
+            avg_heart_rate: meta["average_heartrate"].as_f64().map(|v| v.round() as i64),
+            max_speed: meta["max_speed"].as_f64(),
+            avg_power: meta["average_watts"].as_f64().map(|v| v.round() as i64),
+        }),
+        records,
+    };
+    fit::encode(&activity)
+}
+
+/// Rebuild a stored activity as a file in `format`.
+pub fn export(detail: &ActivityDetail, format: ExportFormat) -> anyhow::Result<Vec<u8>> {
+    let e = prepare(detail)?;
+    Ok(match format {
+        ExportFormat::Gpx if e.samples.iter().all(|s| s.latlng.is_none()) => {
+            anyhow::bail!("activity has no positions for GPX; export it as TCX or FIT")
+        }
+        ExportFormat::Gpx => gpx(&e).into_bytes(),
+        ExportFormat::Tcx => tcx(&e).into_bytes(),
+        ExportFormat::Fit => fit(&e, detail),
+    })
+}
@@ -1,12 +1,111 @@
 use crate::auth::Auth;
-use crate::schema::ActivityHeader;
-use crate::storage::Storage;
-use tracing::{error, info};
+use crate::error::StravaError;
+use crate::schema::{ActivityHeader, STREAM_KEYS};
+use crate::storage::{FailureKind, Storage};
+use crate::utils::PER_PAGE;
+use std::collections::HashSet;
+use tracing::{error, info, warn};
 
-pub async fn download_latest(auth: &Auth, storage: &Storage, count: usize) -> anyhow::Result<()> {
-    let url = format!("{}/athlete/activities?per_page={}", auth.cfg.base_url, count);
-    info!("Requesting activity list: {}", url);
-    let acts: Vec<ActivityHeader> = auth.get_json(&url).await?;
+/// Outcome of downloading a batch of activities.
+#[derive(Debug, Default, Clone, serde::Serialize)]
+pub struct SyncReport {
+    /// Activities fetched and stored
+    pub downloaded: usize,
+    /// Activities skipped because they could not be fetched or stored
+    pub failed: Vec<u64>,
+}
+
+/// Fetch metadata and streams of one activity.
+async fn fetch_activity(auth: &Auth, id: u64) -> Result<(serde_json::Value, serde_json::Value), StravaError> {
+    let meta_url = format!("{}/activities/{}", auth.cfg.base_url, id);
+    info!("Requesting activity metadata: {}", meta_url);
+    let meta: serde_json::Value = auth.get_json(&meta_url).await?;
+    let streams_url = format!("{}/activities/{}/streams?keys={}&key_by_type=true", auth.cfg.base_url, id, STREAM_KEYS);
+    info!("Requesting activity streams: {}", streams_url);
+    let streams: serde_json::Value = auth.get_json(&streams_url).await?;
+    Ok((meta, streams))
+}
+
+/// Raise the FTP to the estimate from stored rides when configured. A failure
+/// is logged and does not fail the sync.
+async fn auto_update_ftp(auth: &Auth, storage: &Storage) {
+    if let Err(e) = storage.auto_update_ftp(&auth.cfg.ftp_estimate).await {
+        warn!(?e, "failed to update FTP from estimate");
+    }
+}
+
+/// Download one activity by id, overwriting any stored copy so edits or crops
+/// made on Strava replace the local meta and streams. NP, IF and TSS are
+/// recomputed when the activity is saved.
+pub async fn download_activity(auth: &Auth, storage: &Storage, id: u64) -> anyhow::Result<()> {
+    info!(id, "download activity");
+    let (meta, streams) = fetch_activity(auth, id).await?;
+    storage.replace(&meta, &streams).await?;
+    storage.clear_sync_failure(id).await?;
+    auto_update_ftp(auth, storage).await;
+    Ok(())
+}
+
+/// Re-fetch only the metadata of a stored activity (name, type, privacy…),
+/// downloading the whole activity when it is not stored yet.
+pub async fn refresh_meta(auth: &Auth, storage: &Storage, id: u64) -> anyhow::Result<()> {
+    if storage.load_raw_activity(id).await.is_err() {
+        return download_activity(auth, storage, id).await;
+    }
+    let meta_url = format!("{}/activities/{}", auth.cfg.base_url, id);
+    info!("Requesting activity metadata: {}", meta_url);
+    let meta: serde_json::Value = auth.get_json(&meta_url).await?;
+    storage.update_meta(&meta).await
+}
+
+/// Whether a failed download is retried on the next sync. A `429` that was
+/// waited out already is worth retrying later, unlike [`StravaError::is_transient`].
+fn failure_kind(e: &StravaError) -> FailureKind {
+    match e {
+        StravaError::Network(_) | StravaError::Server(_) | StravaError::RateLimited { .. } => FailureKind::Transient,
+        _ => FailureKind::Permanent,
+    }
+}
+
+/// Fetch and store one activity. A failure is recorded in the storage and
+/// added to `report` instead of returned; only authorization failures are
+/// returned since every further request would fail the same way. Activities
+/// Strava no longer finds are skipped without being recorded.
+async fn download_recorded(auth: &Auth, storage: &Storage, id: u64, report: &mut SyncReport) -> anyhow::Result<()> {
+    let res = match fetch_activity(auth, id).await {
+        Ok((meta, streams)) => storage
+            .save(&meta, &streams)
+            .await
+            .map_err(|e| (FailureKind::Transient, format!("failed to save: {:#}", e))),
+        Err(e @ StravaError::Unauthorized(_)) => return Err(e.into()),
+        Err(StravaError::NotFound) => {
+            warn!(id, "activity not found, skipping");
+            storage.clear_sync_failure(id).await?;
+            report.failed.push(id);
+            return Ok(());
+        }
+        Err(e) => Err((failure_kind(&e), e.to_string())),
+    };
+    match res {
+        Ok(()) => {
+            storage.clear_sync_failure(id).await?;
+            report.downloaded += 1;
+        }
+        Err((kind, e)) => {
+            error!(id, error = %e, ?kind, "skipping activity");
+            storage.record_sync_failure(id, kind, &e).await?;
+            report.failed.push(id);
+        }
+    }
+    Ok(())
+}
+
+/// Download activities from `acts` that are not stored yet.
+///
+/// Individual failures are recorded in the storage and skipped so one broken
+/// activity does not abort the batch. Authorization failures still abort.
+async fn download_missing(auth: &Auth, storage: &Storage, acts: &[ActivityHeader]) -> anyhow::Result<SyncReport> {
+    let mut report = SyncReport::default();
     for summary in acts {
         let year = &summary.start_date[..4];
         if storage.activity_exists(year, summary.id).await {
@@ -14,15 +113,101 @@ pub async fn download_latest(auth: &Auth, storage: &Storage, count: usize) -> an
             continue;
         }
         info!(id = summary.id, name = %summary.name, "download activity");
-        let meta_url = format!("{}/activities/{}", auth.cfg.base_url, summary.id);
-        info!("Requesting activity metadata: {}", meta_url);
-        let meta: serde_json::Value = auth.get_json(&meta_url).await?;
-        let streams_url = format!("{}/activities/{}/streams?keys=latlng,time,altitude,heartrate,watts&key_by_type=true", auth.cfg.base_url, summary.id);
-        info!("Requesting activity streams: {}", streams_url);
-        let streams: serde_json::Value = auth.get_json(&streams_url).await?;
-        if let Err(e) = storage.save(&meta, &streams).await {
-            error!(?e, "failed to save activity");
+        download_recorded(auth, storage, summary.id, &mut report).await?;
+    }
+    Ok(report)
+}
+
+/// Download the transient failures recorded in `sync_failures.json` again.
+/// Those that succeed are removed from the list, the others keep their latest
+/// error. Permanent failures stay listed without being requested again.
+async fn retry_failures(auth: &Auth, storage: &Storage) -> anyhow::Result<SyncReport> {
+    let mut report = SyncReport::default();
+    for failure in storage.sync_failures().await? {
+        if failure.kind == FailureKind::Permanent {
+            continue;
+        }
+        if storage.load_raw_activity(failure.id).await.is_ok() {
+            storage.clear_sync_failure(failure.id).await?;
+            continue;
         }
+        info!(id = failure.id, error = %failure.error, "retry failed activity");
+        download_recorded(auth, storage, failure.id, &mut report).await?;
+    }
+    Ok(report)
+}
+
+/// Download the `count` most recent activities that are not stored yet,
+/// after retrying the activities earlier syncs failed to download.
+pub async fn download_latest(auth: &Auth, storage: &Storage, count: usize) -> anyhow::Result<SyncReport> {
+    let mut report = retry_failures(auth, storage).await?;
+    let url = format!("{}/athlete/activities?per_page={}", auth.cfg.base_url, count);
+    info!("Requesting activity list: {}", url);
+    let acts: Vec<ActivityHeader> = auth.get_json(&url).await?;
+    let latest = download_missing(auth, storage, &acts).await?;
+    report.downloaded += latest.downloaded;
+    report.failed.extend(latest.failed);
+    if report.downloaded > 0 {
+        auto_update_ftp(auth, storage).await;
+    }
+    Ok(report)
+}
+
+/// Mirror the athlete's whole history by walking the activity list backwards
+/// with a `before=` cursor until Strava returns no further activities.
+///
+/// The cursor is one second past the oldest activity listed, so rides sharing
+/// that second which spill onto the next page are listed again instead of
+/// skipped; stored ones are not downloaded twice. Progress is stored after
+/// every page so an interrupted backfill resumes from the oldest activity
+/// already listed. Activities that failed to download along the way are
+/// retried once the walk completes. Once complete, further calls return
+/// immediately; new activities are picked up by [`download_latest`].
+pub async fn backfill(auth: &Auth, storage: &Storage, per_page: usize) -> anyhow::Result<()> {
+    anyhow::ensure!(PER_PAGE.contains(&per_page), "per_page must be between {} and {}", PER_PAGE.start(), PER_PAGE.end());
+    let mut state = storage.backfill_state().await?;
+    if state.complete {
+        info!(pages = state.pages, "backfill already complete");
+        return Ok(());
+    }
+    let mut listed = HashSet::new();
+    loop {
+        let mut url = format!("{}/athlete/activities?per_page={}", auth.cfg.base_url, per_page);
+        if let Some(before) = state.before {
+            url.push_str(&format!("&before={}", before));
+        }
+        info!("Requesting activity page: {}", url);
+        let acts: Vec<ActivityHeader> = auth.get_json(&url).await?;
+        let new = acts.iter().filter(|a| listed.insert(a.id)).count();
+        if new == 0 {
+            // a full page of activities listed before cannot move the cursor
+            if acts.len() >= per_page {
+                anyhow::bail!("activity page did not advance the backfill cursor: all {} activities were listed before", acts.len());
+            }
+            let retried = retry_failures(auth, storage).await?;
+            if retried.downloaded > 0 {
+                auto_update_ftp(auth, storage).await;
+            }
+            state.downloaded += retried.downloaded;
+            state.complete = true;
+            storage.save_backfill_state(&state).await?;
+            info!(pages = state.pages, downloaded = state.downloaded, "backfill complete");
+            return Ok(());
+        }
+        let page = download_missing(auth, storage, &acts).await?;
+        if page.downloaded > 0 {
+            auto_update_ftp(auth, storage).await;
+        }
+        state.downloaded += page.downloaded;
+        let oldest = acts
+            .iter()
+            .filter_map(|a| chrono::DateTime::parse_from_rfc3339(&a.start_date).ok())
+            .map(|d| d.timestamp())
+            .min()
+            .ok_or_else(|| anyhow::anyhow!("activity page has no valid start dates"))?;
+        state.before = Some(oldest + 1);
+        state.pages += 1;
+        storage.save_backfill_state(&state).await?;
+        info!(pages = state.pages, before = ?state.before, "backfill page stored");
     }
-    Ok(())
 }
@@ -0,0 +1,396 @@
+//! Minimal reader and writer for Garmin FIT activity files.
+//!
+//! Only the messages needed to rebuild an activity are decoded: `file_id`,
+//! `session` and `record`. Everything else, including developer fields, is
+//! skipped. [`encode`] writes the same messages plus the `activity` summary
+//! other tools expect at the end of an activity file.
+
+use anyhow::Context;
+use std::collections::HashMap;
+
+/// Seconds between the Unix epoch and the FIT epoch (1989-12-31T00:00:00Z).
+pub const FIT_EPOCH_OFFSET: i64 = 631_065_600;
+
+const MESG_FILE_ID: u16 = 0;
+const MESG_SESSION: u16 = 18;
+const MESG_RECORD: u16 = 20;
+const MESG_ACTIVITY: u16 = 34;
+
+const CRC_TABLE: [u16; 16] = [
+    0x0000, 0xCC01, 0xD801, 0x1400, 0xF001, 0x3C00, 0x2800, 0xE401,
+    0xA001, 0x6C00, 0x7800, 0xB401, 0x5000, 0x9C01, 0x8801, 0x4400,
+];
+
+/// FIT CRC-16 over `data`.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        let tmp = CRC_TABLE[(crc & 0xF) as usize];
+        crc = (crc >> 4) & 0x0FFF;
+        crc = crc ^ tmp ^ CRC_TABLE[(byte & 0xF) as usize];
+        let tmp = CRC_TABLE[(crc & 0xF) as usize];
+        crc = (crc >> 4) & 0x0FFF;
+        crc = crc ^ tmp ^ CRC_TABLE[((byte >> 4) & 0xF) as usize];
+    }
+    crc
+}
+
+/// One `record` message. Positions are in degrees, distance in meters,
+/// speed in m/s and altitude in meters; timestamps are Unix seconds.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FitRecord {
+    pub timestamp: i64,
+    pub lat: Option<f64>,
+    pub lng: Option<f64>,
+    pub altitude: Option<f64>,
+    pub heart_rate: Option<i64>,
+    pub cadence: Option<i64>,
+    pub distance: Option<f64>,
+    pub speed: Option<f64>,
+    pub power: Option<i64>,
+    pub temperature: Option<i64>,
+}
+
+/// Totals from the `session` message.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FitSession {
+    pub start_time: Option<i64>,
+    pub sport: Option<u8>,
+    pub sub_sport: Option<u8>,
+    pub total_elapsed_time: Option<f64>,
+    pub total_timer_time: Option<f64>,
+    pub total_distance: Option<f64>,
+    pub total_ascent: Option<f64>,
+    pub avg_heart_rate: Option<i64>,
+    pub max_speed: Option<f64>,
+    pub avg_power: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FitActivity {
+    /// `time_created` from `file_id`, Unix seconds
+    pub time_created: Option<i64>,
+    pub session: Option<FitSession>,
+    pub records: Vec<FitRecord>,
+}
+
+struct FieldDef {
+    num: u8,
+    size: usize,
+    base_type: u8,
+}
+
+struct Definition {
+    big_endian: bool,
+    global: u16,
+    fields: Vec<FieldDef>,
+    dev_size: usize,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> anyhow::Result<&'a [u8]> {
+        let end = self.pos.checked_add(n).filter(|e| *e <= self.data.len()).context("truncated FIT file")?;
+        let slice = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+}
+
+/// Decode a raw field value, returning `None` for the base type's invalid value.
+fn read_value(bytes: &[u8], base_type: u8, big_endian: bool) -> Option<i64> {
+    let mut buf = [0u8; 8];
+    let n = bytes.len();
+    if !matches!(n, 1 | 2 | 4 | 8) {
+        return None;
+    }
+    if big_endian {
+        buf[..n].copy_from_slice(bytes);
+        buf[..n].reverse();
+    } else {
+        buf[..n].copy_from_slice(bytes);
+    }
+    let raw = u64::from_le_bytes(buf);
+    let signed = matches!(base_type & 0x1F, 0x01 | 0x03 | 0x05 | 0x0E);
+    let zero_invalid = matches!(base_type & 0x1F, 0x0A | 0x0B | 0x0C | 0x10);
+    let bits = n * 8;
+    let mask = if bits == 64 { u64::MAX } else { (1u64 << bits) - 1 };
+    let invalid = if zero_invalid {
+        0
+    } else if signed {
+        mask >> 1
+    } else {
+        mask
+    };
+    if raw == invalid {
+        return None;
+    }
+    if signed {
+        let shift = 64 - bits;
+        Some(((raw << shift) as i64) >> shift)
+    } else {
+        Some(raw as i64)
+    }
+}
+
+fn semicircles_to_degrees(v: i64) -> f64 {
+    v as f64 * (180.0 / 2f64.powi(31))
+}
+
+/// Parse a FIT file, verifying its header and CRC.
+pub fn decode(data: &[u8]) -> anyhow::Result<FitActivity> {
+    let header_size = *data.first().context("empty FIT file")? as usize;
+    if header_size < 12 || data.len() < header_size || &data[8..12] != b".FIT" {
+        anyhow::bail!("not a FIT file");
+    }
+    let data_size = u32::from_le_bytes(data[4..8].try_into()?) as usize;
+    let end = header_size + data_size;
+    if data.len() < end + 2 {
+        anyhow::bail!("truncated FIT file");
+    }
+    if crc16(&data[..end + 2]) != 0 {
+        anyhow::bail!("FIT file CRC mismatch");
+    }
+
+    let mut reader = Reader { data: &data[..end], pos: header_size };
+    let mut defs: HashMap<u8, Definition> = HashMap::new();
+    let mut activity = FitActivity::default();
+    let mut last_timestamp: Option<u32> = None;
+
+    while reader.pos < end {
+        let header = reader.u8()?;
+        let (local, compressed_ts) = if header & 0x80 != 0 {
+            ((header >> 5) & 0x03, Some((header & 0x1F) as u32))
+        } else if header & 0x40 != 0 {
+            let local = header & 0x0F;
+            let has_dev = header & 0x20 != 0;
+            reader.u8()?; // reserved
+            let big_endian = reader.u8()? == 1;
+            let g = reader.take(2)?;
+            let global = if big_endian { u16::from_be_bytes([g[0], g[1]]) } else { u16::from_le_bytes([g[0], g[1]]) };
+            let count = reader.u8()?;
+            let mut fields = Vec::with_capacity(count as usize);
+            for _ in 0..count {
+                let f = reader.take(3)?;
+                fields.push(FieldDef { num: f[0], size: f[1] as usize, base_type: f[2] });
+            }
+            let mut dev_size = 0;
+            if has_dev {
+                let dev_count = reader.u8()?;
+                for _ in 0..dev_count {
+                    dev_size += reader.take(3)?[1] as usize;
+                }
+            }
+            defs.insert(local, Definition { big_endian, global, fields, dev_size });
+            continue;
+        } else {
+            (header & 0x0F, None)
+        };
+
+        let def = defs.get(&local).with_context(|| format!("data message for undefined local type {}", local))?;
+        let mut values: HashMap<u8, i64> = HashMap::new();
+        for f in &def.fields {
+            let bytes = reader.take(f.size)?;
+            if let Some(v) = read_value(bytes, f.base_type, def.big_endian) {
+                values.insert(f.num, v);
+            }
+        }
+        reader.take(def.dev_size)?;
+
+        if let Some(offset) = compressed_ts {
+            if let Some(last) = last_timestamp {
+                let mut ts = (last & !0x1F) + offset;
+                if offset < (last & 0x1F) {
+                    ts += 0x20;
+                }
+                values.insert(253, ts as i64);
+            }
+        }
+        if let Some(ts) = values.get(&253) {
+            last_timestamp = Some(*ts as u32);
+        }
+
+        let get = |n: u8| values.get(&n).copied();
+        let time = |n: u8| get(n).map(|v| v + FIT_EPOCH_OFFSET);
+        match def.global {
+            MESG_FILE_ID => activity.time_created = time(4),
+            MESG_SESSION => {
+                activity.session = Some(FitSession {
+                    start_time: time(2),
+                    sport: get(5).map(|v| v as u8),
+                    sub_sport: get(6).map(|v| v as u8),
+                    total_elapsed_time: get(7).map(|v| v as f64 / 1000.0),
+                    total_timer_time: get(8).map(|v| v as f64 / 1000.0),
+                    total_distance: get(9).map(|v| v as f64 / 100.0),
+                    total_ascent: get(22).map(|v| v as f64),
+                    avg_heart_rate: get(16),
+                    max_speed: get(15).map(|v| v as f64 / 1000.0),
+                    avg_power: get(20),
+                })
+            }
+            MESG_RECORD => {
+                let Some(timestamp) = time(253) else { continue };
+                activity.records.push(FitRecord {
+                    timestamp,
+                    lat: get(0).map(semicircles_to_degrees),
+                    lng: get(1).map(semicircles_to_degrees),
+                    altitude: get(78).or(get(2)).map(|v| v as f64 / 5.0 - 500.0),
+                    heart_rate: get(3),
+                    cadence: get(4),
+                    distance: get(5).map(|v| v as f64 / 100.0),
+                    speed: get(73).or(get(6)).map(|v| v as f64 / 1000.0),
+                    power: get(7),
+                    temperature: get(13),
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(activity)
+}
+
+fn degrees_to_semicircles(v: f64) -> i32 {
+    (v * (2f64.powi(31) / 180.0)).round() as i32
+}
+
+fn fit_time(ts: i64) -> u32 {
+    (ts - FIT_EPOCH_OFFSET).max(0) as u32
+}
+
+/// Writes little-endian definition and data messages.
+#[derive(Default)]
+struct Writer {
+    data: Vec<u8>,
+}
+
+impl Writer {
+    /// Definition message for `local`; fields are `(number, size, base type)`.
+    fn define(&mut self, local: u8, global: u16, fields: &[(u8, u8, u8)]) {
+        self.data.extend_from_slice(&[0x40 | local, 0, 0]);
+        self.data.extend_from_slice(&global.to_le_bytes());
+        self.data.push(fields.len() as u8);
+        for (num, size, base) in fields {
+            self.data.extend_from_slice(&[*num, *size, *base]);
+        }
+    }
+
+    fn header(&mut self, local: u8) {
+        self.data.push(local);
+    }
+
+    fn u8(&mut self, v: Option<u8>) {
+        self.data.push(v.unwrap_or(0xFF));
+    }
+
+    fn i8(&mut self, v: Option<i8>) {
+        self.data.push(v.unwrap_or(0x7F) as u8);
+    }
+
+    fn u16(&mut self, v: Option<u16>) {
+        self.data.extend_from_slice(&v.unwrap_or(0xFFFF).to_le_bytes());
+    }
+
+    fn u32(&mut self, v: Option<u32>) {
+        self.data.extend_from_slice(&v.unwrap_or(0xFFFF_FFFF).to_le_bytes());
+    }
+
+    fn i32(&mut self, v: Option<i32>) {
+        self.data.extend_from_slice(&v.unwrap_or(0x7FFF_FFFF).to_le_bytes());
+    }
+}
+
+/// Serialize an activity as a FIT file with header and CRC.
+///
+/// Values outside a field's range are clamped; missing values are written as
+/// the base type's invalid value so [`decode`] returns them as `None`.
+pub fn encode(activity: &FitActivity) -> Vec<u8> {
+    let scaled = |v: Option<f64>, scale: f64, offset: f64, max: f64| v.map(|v| ((v + offset) * scale).round().clamp(0.0, max));
+    let small = |v: Option<i64>, max: i64| v.map(|v| v.clamp(0, max));
+    let mut w = Writer::default();
+
+    w.define(0, MESG_FILE_ID, &[(0, 1, 0x00), (1, 2, 0x84), (4, 4, 0x86)]);
+    w.header(0);
+    w.data.push(4); // type: activity
+    w.u16(Some(255)); // manufacturer: development
+    w.u32(activity.time_created.map(fit_time));
+
+    w.define(1, MESG_RECORD, &[
+        (253, 4, 0x86),
+        (0, 4, 0x85),
+        (1, 4, 0x85),
+        (2, 2, 0x84),
+        (3, 1, 0x02),
+        (4, 1, 0x02),
+        (5, 4, 0x86),
+        (6, 2, 0x84),
+        (7, 2, 0x84),
+        (13, 1, 0x01),
+    ]);
+    for r in &activity.records {
+        w.header(1);
+        w.u32(Some(fit_time(r.timestamp)));
+        w.i32(r.lat.map(degrees_to_semicircles));
+        w.i32(r.lng.map(degrees_to_semicircles));
+        w.u16(scaled(r.altitude, 5.0, 500.0, 65534.0).map(|v| v as u16));
+        w.u8(small(r.heart_rate, 254).map(|v| v as u8));
+        w.u8(small(r.cadence, 254).map(|v| v as u8));
+        w.u32(scaled(r.distance, 100.0, 0.0, 4_294_967_294.0).map(|v| v as u32));
+        w.u16(scaled(r.speed, 1000.0, 0.0, 65534.0).map(|v| v as u16));
+        w.u16(small(r.power, 65534).map(|v| v as u16));
+        w.i8(r.temperature.map(|v| v.clamp(-127, 126) as i8));
+    }
+
+    let last = activity.records.last().map(|r| r.timestamp);
+    if let Some(s) = &activity.session {
+        w.define(2, MESG_SESSION, &[
+            (253, 4, 0x86),
+            (2, 4, 0x86),
+            (5, 1, 0x00),
+            (6, 1, 0x00),
+            (7, 4, 0x86),
+            (8, 4, 0x86),
+            (9, 4, 0x86),
+            (15, 2, 0x84),
+            (16, 1, 0x02),
+            (20, 2, 0x84),
+            (22, 2, 0x84),
+        ]);
+        w.header(2);
+        w.u32(last.or(s.start_time).map(fit_time));
+        w.u32(s.start_time.map(fit_time));
+        w.u8(s.sport);
+        w.u8(s.sub_sport);
+        w.u32(scaled(s.total_elapsed_time, 1000.0, 0.0, 4_294_967_294.0).map(|v| v as u32));
+        w.u32(scaled(s.total_timer_time, 1000.0, 0.0, 4_294_967_294.0).map(|v| v as u32));
+        w.u32(scaled(s.total_distance, 100.0, 0.0, 4_294_967_294.0).map(|v| v as u32));
+        w.u16(scaled(s.max_speed, 1000.0, 0.0, 65534.0).map(|v| v as u16));
+        w.u8(small(s.avg_heart_rate, 254).map(|v| v as u8));
+        w.u16(small(s.avg_power, 65534).map(|v| v as u16));
+        w.u16(scaled(s.total_ascent, 1.0, 0.0, 65534.0).map(|v| v as u16));
+    }
+
+    w.define(3, MESG_ACTIVITY, &[(253, 4, 0x86), (1, 2, 0x84), (2, 1, 0x00), (3, 1, 0x00), (4, 1, 0x00)]);
+    w.header(3);
+    w.u32(last.or(activity.time_created).map(fit_time));
+    w.u16(Some(activity.session.is_some() as u16));
+    w.data.extend_from_slice(&[0, 26, 1]); // type: manual, event: activity, event_type: stop
+
+    let mut out = vec![14, 0x20];
+    out.extend_from_slice(&2140u16.to_le_bytes());
+    out.extend_from_slice(&(w.data.len() as u32).to_le_bytes());
+    out.extend_from_slice(b".FIT");
+    let header_crc = crc16(&out);
+    out.extend_from_slice(&header_crc.to_le_bytes());
+    out.extend_from_slice(&w.data);
+    let crc = crc16(&out);
+    out.extend_from_slice(&crc.to_le_bytes());
+    out
+}
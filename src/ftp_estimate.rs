@@ -0,0 +1,93 @@
+//! FTP estimated from the best efforts of recent rides, either as 95% of the
+//! best 20-minute power or as the critical power of a two-parameter fit.
+
+use chrono::{Duration, Utc};
+use serde::Serialize;
+use tracing::info;
+
+use crate::cp::{fit, CpModelKind};
+use crate::power::BestEffort;
+use crate::storage::Storage;
+use crate::utils::{FtpEstimate, FtpMethod, MAX_ESTIMATE_DAYS};
+
+const TWENTY_MINUTES: usize = 1200;
+const TWENTY_MINUTE_FACTOR: f64 = 0.95;
+/// Efforts of the CP fit, matching the `2p` model's 2–20 minute range.
+const CP_EFFORTS: std::ops::RangeInclusive<usize> = 120..=1200;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Estimate {
+    pub method: FtpMethod,
+    /// Estimated FTP, rounded to whole watts
+    pub ftp: f64,
+    /// FTP in effect today
+    pub current_ftp: f64,
+    /// First day of the rides considered
+    pub from: String,
+    /// Ride that set the 20-minute best, or the most recent ride contributing
+    /// to the CP fit
+    pub activity_id: u64,
+    pub start_date: String,
+    /// Best efforts the estimate was derived from
+    pub efforts: Vec<BestEffort>,
+}
+
+/// `(ftp, efforts used)` from the best efforts of a power curve; `None` when
+/// they do not support the method.
+pub fn estimate(method: FtpMethod, efforts: &[BestEffort]) -> Option<(f64, Vec<BestEffort>)> {
+    match method {
+        FtpMethod::TwentyMinute => {
+            let best = efforts.iter().find(|e| e.duration == TWENTY_MINUTES)?;
+            Some(((best.watts * TWENTY_MINUTE_FACTOR).round(), vec![best.clone()]))
+        }
+        FtpMethod::Cp => {
+            let used: Vec<_> = efforts.iter().filter(|e| CP_EFFORTS.contains(&e.duration)).cloned().collect();
+            let points: Vec<_> = used.iter().map(|e| (e.duration, e.watts)).collect();
+            let model = fit(CpModelKind::TwoParameter, &points)?;
+            Some((model.cp.round(), used))
+        }
+    }
+}
+
+impl Storage {
+    /// Estimate FTP from the rides of the last `cfg.days` days.
+    pub async fn estimate_ftp(&self, cfg: &FtpEstimate) -> anyhow::Result<Option<Estimate>> {
+        let today = Utc::now().date_naive();
+        let from = today - Duration::days(cfg.days.clamp(1, MAX_ESTIMATE_DAYS) - 1);
+        let curve = self.power_curve(Some(from), Some(today)).await?;
+        let Some((ftp, efforts)) = estimate(cfg.method, &curve) else {
+            return Ok(None);
+        };
+        let Some(source) = efforts.iter().max_by(|a, b| a.start_date.cmp(&b.start_date)) else {
+            return Ok(None);
+        };
+        let (activity_id, start_date) = (source.activity_id, source.start_date.clone());
+        Ok(Some(Estimate {
+            method: cfg.method,
+            ftp,
+            current_ftp: self.current_ftp().await?,
+            from: from.to_string(),
+            activity_id,
+            start_date,
+            efforts,
+        }))
+    }
+
+    /// Record the estimate as today's FTP when automatic updates are enabled
+    /// and it beats the current FTP by more than the margin. Returns the new
+    /// FTP if one was recorded.
+    pub async fn auto_update_ftp(&self, cfg: &FtpEstimate) -> anyhow::Result<Option<f64>> {
+        if !cfg.auto_update {
+            return Ok(None);
+        }
+        let Some(est) = self.estimate_ftp(cfg).await? else {
+            return Ok(None);
+        };
+        if est.ftp <= est.current_ftp + cfg.margin {
+            return Ok(None);
+        }
+        info!(ftp = est.ftp, previous = est.current_ftp, activity_id = est.activity_id, "raising FTP to estimate");
+        self.set_ftp(est.ftp).await?;
+        Ok(Some(est.ftp))
+    }
+}
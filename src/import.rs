@@ -0,0 +1,470 @@
+//! Import of activity files recorded outside Strava.
+//!
+//! Files are converted into the same meta and `key_by_type` stream layout
+//! Strava returns, so they go through [`Storage::save`] like any synced ride.
+
+use crate::fit;
+use crate::storage::Storage;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Map, Value};
+use std::path::Path;
+
+/// Lowest id handed to imported activities. Strava ids are far below this
+/// and the range stays below 2^53 so ids survive JSON number parsing.
+pub const SYNTHETIC_ID_BASE: u64 = 1 << 52;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    Fit,
+    Gpx,
+    Tcx,
+}
+
+impl ImportFormat {
+    /// Detect the format from a file name extension.
+    pub fn from_path(name: &str) -> Option<Self> {
+        let ext = Path::new(name).extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "fit" => Some(ImportFormat::Fit),
+            "gpx" => Some(ImportFormat::Gpx),
+            "tcx" => Some(ImportFormat::Tcx),
+            _ => None,
+        }
+    }
+
+    fn tag(self) -> u64 {
+        match self {
+            ImportFormat::Fit => 1,
+            ImportFormat::Gpx => 2,
+            ImportFormat::Tcx => 3,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            ImportFormat::Fit => "fit",
+            ImportFormat::Gpx => "gpx",
+            ImportFormat::Tcx => "tcx",
+        }
+    }
+}
+
+/// Stable id for an imported activity derived from its start time and
+/// format, so importing the same file twice overwrites instead of duplicating.
+///
+/// The id is `SYNTHETIC_ID_BASE + start * 16 + tag` with a per-format tag,
+/// so files of different formats starting in the same second stay distinct.
+pub fn synthetic_id(format: ImportFormat, start: i64) -> u64 {
+    SYNTHETIC_ID_BASE + start.max(0) as u64 * 16 + format.tag()
+}
+
+/// Whether `id` was assigned by [`synthetic_id`] rather than by Strava.
+pub fn is_synthetic_id(id: u64) -> bool {
+    id >= SYNTHETIC_ID_BASE
+}
+
+/// One sample of an imported track. Times are Unix seconds.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackPoint {
+    pub time: i64,
+    pub lat: Option<f64>,
+    pub lng: Option<f64>,
+    pub altitude: Option<f64>,
+    pub heartrate: Option<i64>,
+    pub cadence: Option<i64>,
+    pub distance: Option<f64>,
+    pub speed: Option<f64>,
+    pub power: Option<i64>,
+    pub temp: Option<i64>,
+}
+
+/// Activity decoded from a file, before conversion to meta and streams.
+/// Totals left empty are computed from the trackpoints.
+#[derive(Debug, Clone)]
+pub struct Track {
+    pub format: ImportFormat,
+    pub name: Option<String>,
+    pub activity_type: String,
+    pub start: i64,
+    pub points: Vec<TrackPoint>,
+    pub elapsed_time: Option<f64>,
+    pub moving_time: Option<f64>,
+    pub distance: Option<f64>,
+    pub elevation_gain: Option<f64>,
+    pub max_speed: Option<f64>,
+}
+
+fn haversine(a: (f64, f64), b: (f64, f64)) -> f64 {
+    const R: f64 = 6_371_000.0;
+    let (lat1, lat2) = (a.0.to_radians(), b.0.to_radians());
+    let dlat = lat2 - lat1;
+    let dlng = (b.1 - a.1).to_radians();
+    let h = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlng / 2.0).sin().powi(2);
+    2.0 * R * h.sqrt().asin()
+}
+
+/// Cumulative distance along the track, taken from recorded distances when
+/// present and from the GPS positions otherwise.
+fn cumulative_distance(points: &[TrackPoint]) -> Option<Vec<f64>> {
+    if points.iter().any(|p| p.distance.is_some()) {
+        return Some(fill_forward(points.iter().map(|p| p.distance)));
+    }
+    if !points.iter().any(|p| p.lat.is_some() && p.lng.is_some()) {
+        return None;
+    }
+    let mut total = 0.0;
+    let mut last: Option<(f64, f64)> = None;
+    let mut out = Vec::with_capacity(points.len());
+    for p in points {
+        if let (Some(lat), Some(lng)) = (p.lat, p.lng) {
+            if let Some(prev) = last {
+                total += haversine(prev, (lat, lng));
+            }
+            last = Some((lat, lng));
+        }
+        out.push(total);
+    }
+    Some(out)
+}
+
+/// Altitude change in metres that starts or ends a climb.
+const CLIMB_THRESHOLD: f64 = 3.0;
+
+/// Total ascent with [`CLIMB_THRESHOLD`] of hysteresis: a climb counts from
+/// the lowest point once it rose that far and ends after dropping that far
+/// below its top, so GPS and barometer noise does not add up.
+fn elevation_gain(points: &[TrackPoint]) -> Option<f64> {
+    let mut alts = points.iter().filter_map(|p| p.altitude);
+    let first = alts.next()?;
+    let (mut gain, mut low, mut high, mut climbing) = (0.0, first, first, false);
+    for alt in alts {
+        if climbing {
+            if alt > high {
+                gain += alt - high;
+                high = alt;
+            } else if high - alt >= CLIMB_THRESHOLD {
+                climbing = false;
+                low = alt;
+            }
+        } else if alt < low {
+            low = alt;
+        } else if alt - low >= CLIMB_THRESHOLD {
+            climbing = true;
+            gain += alt - low;
+            high = alt;
+        }
+    }
+    Some(gain)
+}
+
+/// Replace gaps with the previous (or first known) value.
+fn fill_forward<T: Copy + Default>(values: impl Iterator<Item = Option<T>> + Clone) -> Vec<T> {
+    let first = values.clone().flatten().next().unwrap_or_default();
+    let mut last = first;
+    values
+        .map(|v| {
+            if let Some(v) = v {
+                last = v;
+            }
+            last
+        })
+        .collect()
+}
+
+fn stream(data: impl Into<Value>) -> Value {
+    json!({ "data": data.into() })
+}
+
+fn iso(ts: i64) -> String {
+    DateTime::<Utc>::from_timestamp(ts, 0)
+        .unwrap_or_default()
+        .format("%Y-%m-%dT%H:%M:%SZ")
+        .to_string()
+}
+
+/// Convert a decoded track into Strava-compatible meta and streams.
+pub fn to_activity(track: &Track) -> (Value, Value) {
+    let points = &track.points;
+    let id = synthetic_id(track.format, track.start);
+
+    let mut streams = Map::new();
+    streams.insert("time".into(), stream(points.iter().map(|p| p.time - track.start).collect::<Vec<_>>()));
+    let distance = cumulative_distance(points);
+    if let Some(d) = &distance {
+        streams.insert("distance".into(), stream(d.clone()));
+    }
+    if points.iter().any(|p| p.lat.is_some() && p.lng.is_some()) {
+        let latlng = fill_forward(points.iter().map(|p| p.lat.zip(p.lng)));
+        streams.insert("latlng".into(), stream(latlng.iter().map(|(a, b)| json!([a, b])).collect::<Vec<_>>()));
+    }
+    if points.iter().any(|p| p.altitude.is_some()) {
+        streams.insert("altitude".into(), stream(fill_forward(points.iter().map(|p| p.altitude))));
+    }
+    if points.iter().any(|p| p.speed.is_some()) {
+        streams.insert("velocity_smooth".into(), stream(points.iter().map(|p| p.speed.unwrap_or(0.0)).collect::<Vec<_>>()));
+    }
+    for (key, values) in [
+        ("watts", points.iter().map(|p| p.power).collect::<Vec<_>>()),
+        ("heartrate", points.iter().map(|p| p.heartrate).collect()),
+        ("cadence", points.iter().map(|p| p.cadence).collect()),
+        ("temp", points.iter().map(|p| p.temp).collect()),
+    ] {
+        if values.iter().any(|v| v.is_some()) {
+            streams.insert(key.into(), stream(values.iter().map(|v| v.unwrap_or(0)).collect::<Vec<_>>()));
+        }
+    }
+
+    let elapsed = track
+        .elapsed_time
+        .unwrap_or_else(|| points.last().map(|p| (p.time - track.start) as f64).unwrap_or(0.0));
+    let total_distance = track
+        .distance
+        .or_else(|| distance.as_ref().and_then(|d| d.last().copied()))
+        .unwrap_or(0.0);
+    let moving = track.moving_time.unwrap_or(elapsed);
+    let start_date = iso(track.start);
+    let mut meta = json!({
+        "id": id,
+        "name": track.name.clone().unwrap_or_else(|| format!("{} {}", track.activity_type, &start_date[..10])),
+        "type": track.activity_type,
+        "start_date": start_date,
+        "distance": total_distance,
+        "elapsed_time": elapsed.round() as i64,
+        "moving_time": moving.round() as i64,
+        "external_id": format!("{}-{}", track.format.as_str(), track.start),
+        "import_format": track.format.as_str(),
+    });
+    let obj = meta.as_object_mut().unwrap();
+    if let Some(gain) = track.elevation_gain.or_else(|| elevation_gain(points)) {
+        obj.insert("total_elevation_gain".into(), json!(gain));
+    }
+    if moving > 0.0 {
+        obj.insert("average_speed".into(), json!(total_distance / moving));
+    }
+    let max_speed = track
+        .max_speed
+        .or_else(|| points.iter().filter_map(|p| p.speed).reduce(f64::max));
+    if let Some(max) = max_speed {
+        obj.insert("max_speed".into(), json!(max));
+    }
+    let hr: Vec<i64> = points.iter().filter_map(|p| p.heartrate).collect();
+    if !hr.is_empty() {
+        obj.insert("average_heartrate".into(), json!(hr.iter().sum::<i64>() as f64 / hr.len() as f64));
+    }
+    let power: Vec<i64> = points.iter().filter_map(|p| p.power).collect();
+    if !power.is_empty() {
+        obj.insert("average_watts".into(), json!(power.iter().sum::<i64>() as f64 / power.len() as f64));
+        obj.insert("device_watts".into(), json!(true));
+    }
+    (meta, Value::Object(streams))
+}
+
+fn fit_activity_type(sport: Option<u8>, sub_sport: Option<u8>) -> &'static str {
+    match (sport, sub_sport) {
+        (Some(2), Some(6 | 58)) => "VirtualRide",
+        (Some(2), _) => "Ride",
+        (Some(1), _) => "Run",
+        (Some(11), _) => "Walk",
+        (Some(5), _) => "Swim",
+        _ => "Workout",
+    }
+}
+
+/// Decode a FIT file into a [`Track`].
+pub fn parse_fit(data: &[u8]) -> anyhow::Result<Track> {
+    let activity = fit::decode(data)?;
+    let session = activity.session.clone().unwrap_or_default();
+    let start = session
+        .start_time
+        .or_else(|| activity.records.first().map(|r| r.timestamp))
+        .or(activity.time_created)
+        .ok_or_else(|| anyhow::anyhow!("FIT file has no start time"))?;
+    let points = activity
+        .records
+        .iter()
+        .map(|r| TrackPoint {
+            time: r.timestamp,
+            lat: r.lat,
+            lng: r.lng,
+            altitude: r.altitude,
+            heartrate: r.heart_rate,
+            cadence: r.cadence,
+            distance: r.distance,
+            speed: r.speed,
+            power: r.power,
+            temp: r.temperature,
+        })
+        .collect();
+    Ok(Track {
+        format: ImportFormat::Fit,
+        name: None,
+        activity_type: fit_activity_type(session.sport, session.sub_sport).into(),
+        start,
+        points,
+        elapsed_time: session.total_elapsed_time,
+        moving_time: session.total_timer_time,
+        distance: session.total_distance,
+        elevation_gain: session.total_ascent,
+        max_speed: session.max_speed,
+    })
+}
+
+fn parse_time(s: &str) -> Option<i64> {
+    DateTime::parse_from_rfc3339(s.trim()).ok().map(|d| d.timestamp())
+}
+
+/// Text of the first descendant of `node` whose local tag name is one of
+/// `names`, ignoring XML namespaces (GPX extensions use several prefixes).
+fn child_text<'a>(node: roxmltree::Node<'a, 'a>, names: &[&str]) -> Option<&'a str> {
+    node.descendants()
+        .find(|n| n.is_element() && names.contains(&n.tag_name().name()))
+        .and_then(|n| n.text())
+        .map(str::trim)
+}
+
+fn child_f64(node: roxmltree::Node, names: &[&str]) -> Option<f64> {
+    child_text(node, names)?.parse().ok()
+}
+
+fn child_i64(node: roxmltree::Node, names: &[&str]) -> Option<i64> {
+    child_f64(node, names).map(|v| v.round() as i64)
+}
+
+fn xml_text(data: &[u8]) -> anyhow::Result<&str> {
+    let text = std::str::from_utf8(data)?;
+    Ok(text.trim_start_matches('\u{feff}'))
+}
+
+fn gpx_activity_type(kind: Option<&str>) -> &'static str {
+    let kind = kind.unwrap_or("").to_ascii_lowercase();
+    if kind.contains("run") || kind == "9" {
+        "Run"
+    } else if kind.contains("walk") || kind.contains("hik") {
+        "Walk"
+    } else if kind.contains("virtual") {
+        "VirtualRide"
+    } else {
+        "Ride"
+    }
+}
+
+/// Decode a GPX file, reading heart rate, cadence and temperature from the
+/// Garmin `TrackPointExtension` and power from `<power>` or `PowerInWatts`.
+pub fn parse_gpx(data: &[u8]) -> anyhow::Result<Track> {
+    let doc = roxmltree::Document::parse(xml_text(data)?)?;
+    let root = doc.root_element();
+    if root.tag_name().name() != "gpx" {
+        anyhow::bail!("not a GPX file");
+    }
+    let trk = root
+        .children()
+        .find(|n| n.tag_name().name() == "trk");
+    let name = trk.and_then(|t| t.children().find(|n| n.tag_name().name() == "name")).and_then(|n| n.text());
+    let kind = trk.and_then(|t| t.children().find(|n| n.tag_name().name() == "type")).and_then(|n| n.text());
+    let mut points = Vec::new();
+    for pt in root.descendants().filter(|n| n.tag_name().name() == "trkpt") {
+        let Some(time) = child_text(pt, &["time"]).and_then(parse_time) else { continue };
+        points.push(TrackPoint {
+            time,
+            lat: pt.attribute("lat").and_then(|v| v.parse().ok()),
+            lng: pt.attribute("lon").and_then(|v| v.parse().ok()),
+            altitude: child_f64(pt, &["ele"]),
+            heartrate: child_i64(pt, &["hr"]),
+            cadence: child_i64(pt, &["cad"]),
+            distance: None,
+            speed: child_f64(pt, &["speed"]),
+            power: child_i64(pt, &["power", "PowerInWatts"]),
+            temp: child_i64(pt, &["atemp", "wtemp"]),
+        });
+    }
+    let start = points.first().map(|p| p.time).ok_or_else(|| anyhow::anyhow!("GPX file has no timed trackpoints"))?;
+    Ok(Track {
+        format: ImportFormat::Gpx,
+        name: name.map(|n| n.trim().to_string()).filter(|n| !n.is_empty()),
+        activity_type: gpx_activity_type(kind).into(),
+        start,
+        points,
+        elapsed_time: None,
+        moving_time: None,
+        distance: None,
+        elevation_gain: None,
+        max_speed: None,
+    })
+}
+
+/// Decode a TCX file, reading power and speed from the `TPX` extension.
+pub fn parse_tcx(data: &[u8]) -> anyhow::Result<Track> {
+    let doc = roxmltree::Document::parse(xml_text(data)?)?;
+    let activity = doc
+        .descendants()
+        .find(|n| n.tag_name().name() == "Activity")
+        .ok_or_else(|| anyhow::anyhow!("TCX file has no activity"))?;
+    let activity_type = match activity.attribute("Sport") {
+        Some("Running") => "Run",
+        Some("Biking") => "Ride",
+        _ => "Workout",
+    };
+    let mut points = Vec::new();
+    for pt in activity.descendants().filter(|n| n.tag_name().name() == "Trackpoint") {
+        let Some(time) = child_text(pt, &["Time"]).and_then(parse_time) else { continue };
+        points.push(TrackPoint {
+            time,
+            lat: child_f64(pt, &["LatitudeDegrees"]),
+            lng: child_f64(pt, &["LongitudeDegrees"]),
+            altitude: child_f64(pt, &["AltitudeMeters"]),
+            heartrate: pt
+                .children()
+                .find(|n| n.tag_name().name() == "HeartRateBpm")
+                .and_then(|n| child_i64(n, &["Value"])),
+            cadence: child_i64(pt, &["Cadence", "RunCadence"]),
+            distance: child_f64(pt, &["DistanceMeters"]),
+            speed: child_f64(pt, &["Speed"]),
+            power: child_i64(pt, &["Watts"]),
+            temp: None,
+        });
+    }
+    let start = child_text(activity, &["Id"])
+        .and_then(parse_time)
+        .or_else(|| points.first().map(|p| p.time))
+        .ok_or_else(|| anyhow::anyhow!("TCX file has no start time"))?;
+    Ok(Track {
+        format: ImportFormat::Tcx,
+        name: child_text(activity, &["Notes"]).map(str::to_string),
+        activity_type: activity_type.into(),
+        start,
+        points,
+        elapsed_time: None,
+        moving_time: None,
+        distance: None,
+        elevation_gain: None,
+        max_speed: None,
+    })
+}
+
+/// Parse a file of the given format into a [`Track`].
+pub fn parse(format: ImportFormat, data: &[u8]) -> anyhow::Result<Track> {
+    match format {
+        ImportFormat::Fit => parse_fit(data),
+        ImportFormat::Gpx => parse_gpx(data),
+        ImportFormat::Tcx => parse_tcx(data),
+    }
+}
+
+/// Decode an activity file, detecting its format from `name`, into the meta
+/// and streams stored for it.
+pub fn decode_file(name: &str, data: &[u8]) -> anyhow::Result<(Value, Value)> {
+    let format = ImportFormat::from_path(name)
+        .ok_or_else(|| anyhow::anyhow!("unsupported file type: {}", name))?;
+    let track = parse(format, data)?;
+    if track.points.is_empty() {
+        anyhow::bail!("{} contains no trackpoints", name);
+    }
+    Ok(to_activity(&track))
+}
+
+/// Import an activity file, detecting its format from `name`, and return the stored id.
+pub async fn import_file(storage: &Storage, name: &str, data: &[u8]) -> anyhow::Result<u64> {
+    let (meta, streams) = decode_file(name, data)?;
+    storage.replace(&meta, &streams).await?;
+    Ok(meta["id"].as_u64().unwrap_or_default())
+}
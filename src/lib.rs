@@ -5,3 +5,18 @@ pub mod storage;
 pub mod web;
 pub mod schema;
 pub mod stats;
+pub mod ratelimit;
+pub mod error;
+pub mod webhook;
+pub mod fit;
+pub mod import;
+pub mod export;
+pub mod store;
+pub mod blob;
+pub mod recompute;
+pub mod pmc;
+pub mod power;
+pub mod cp;
+pub mod wbal;
+pub mod ftp_estimate;
+pub mod zones;
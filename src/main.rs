@@ -1,15 +1,31 @@
 use abcy_data::{utils::Config, auth::Auth, storage::Storage, fetch, web};
-use tracing::info;
+use tracing::{error, info, warn};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
     let cfg = Config::load("config.toml")?;
     let auth = Auth::new(cfg.clone());
-    let storage = Storage::new(&cfg.storage);
-    // initial download
+    let storage = Storage::from_config(&cfg).await?;
+    // initial download, the only request allowed to open the browser to sign in
     info!("downloading latest activities");
-    let _ = fetch::download_latest(&auth, &storage, cfg.storage.download_count).await;
+    let login = auth.clone().with_interactive_authorization(true);
+    let _ = fetch::download_latest(&login, &storage, cfg.storage.download_count).await;
+    // webhook events are matched against the athlete id stored with the token
+    if let Err(e) = login.athlete_id().await {
+        warn!(?e, "failed to look up the athlete of the stored token");
+    }
+    if cfg.backfill.enabled {
+        let auth = auth.clone();
+        let storage = storage.clone();
+        let per_page = cfg.backfill.per_page;
+        tokio::spawn(async move {
+            info!("backfilling activity history");
+            if let Err(e) = fetch::backfill(&auth, &storage, per_page).await {
+                error!(?e, "backfill stopped");
+            }
+        });
+    }
     web::run(cfg, auth, storage).await?;
     Ok(())
 }
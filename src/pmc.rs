@@ -0,0 +1,68 @@
+//! Performance Management Chart: chronic (CTL) and acute (ATL) training load
+//! as exponentially weighted averages of the daily TSS, and the training
+//! stress balance TSB = CTL - ATL.
+
+use chrono::{Duration, NaiveDate, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::storage::Storage;
+use crate::utils::Pmc;
+
+/// Days shown when no start date is requested.
+const DEFAULT_DAYS: i64 = 90;
+
+/// Longest range a PMC may be requested for, ten years.
+pub const MAX_PMC_DAYS: i64 = 3650;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PmcDay {
+    pub date: String,
+    /// Summed TSS of the day's activities, zero on rest days
+    pub tss: f64,
+    pub ctl: f64,
+    pub atl: f64,
+    pub tsb: f64,
+}
+
+/// Daily PMC values for `from..=to`. The loads start at zero on the first
+/// day with TSS, so days before `from` still count towards the result.
+pub fn pmc_series(daily_tss: &BTreeMap<NaiveDate, f64>, from: NaiveDate, to: NaiveDate, cfg: &Pmc) -> Vec<PmcDay> {
+    let start = daily_tss.keys().next().map_or(from, |first| (*first).min(from));
+    let (ctl_days, atl_days) = (cfg.ctl_days.max(1.0), cfg.atl_days.max(1.0));
+    let (mut ctl, mut atl) = (0.0, 0.0);
+    let mut out = Vec::new();
+    for day in start.iter_days().take_while(|d| *d <= to) {
+        let tss = daily_tss.get(&day).copied().unwrap_or(0.0);
+        ctl += (tss - ctl) / ctl_days;
+        atl += (tss - atl) / atl_days;
+        if day >= from {
+            out.push(PmcDay { date: day.to_string(), tss, ctl, atl, tsb: ctl - atl });
+        }
+    }
+    out
+}
+
+/// The `from..=to` range a PMC request covers once the defaults of
+/// [`Storage::pmc`] are applied.
+pub fn pmc_range(from: Option<NaiveDate>, to: Option<NaiveDate>) -> (NaiveDate, NaiveDate) {
+    let to = to.unwrap_or_else(|| Utc::now().date_naive());
+    (from.unwrap_or(to - Duration::days(DEFAULT_DAYS - 1)), to)
+}
+
+impl Storage {
+    /// PMC from `from` to `to`, both inclusive. `to` defaults to today and
+    /// `from` to 90 days before `to`.
+    pub async fn pmc(&self, from: Option<NaiveDate>, to: Option<NaiveDate>, cfg: &Pmc) -> anyhow::Result<Vec<PmcDay>> {
+        let (from, to) = pmc_range(from, to);
+        let end = to.succ_opt().map(|d| d.to_string());
+        let mut daily = BTreeMap::new();
+        for summary in self.activity_summaries_between(None, end.as_deref()).await? {
+            let Some(tss) = summary.training_stress_score else { continue };
+            let date = summary.start_date.get(..10).unwrap_or_default();
+            let Ok(date) = NaiveDate::parse_from_str(date, "%Y-%m-%d") else { continue };
+            *daily.entry(date).or_insert(0.0) += tss;
+        }
+        Ok(pmc_series(&daily, from, to, cfg))
+    }
+}
@@ -0,0 +1,153 @@
+//! Mean-maximal power curves: the best average power an activity, or every
+//! activity in a date range, sustained for standard durations from 1 s to 5 h.
+//!
+//! The curve of each activity is cached as `power_curve.json.zst` next to its
+//! streams. [`Storage::save`] rewrites it, older activities get it on first use.
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::schema::ParsedStreams;
+use crate::storage::Storage;
+
+/// Durations in seconds the curve is evaluated at.
+pub const DURATIONS: [usize; 25] = [
+    1, 2, 5, 10, 15, 20, 30, 45, 60, 90, 120, 180, 300, 420, 600, 900, 1200, 1800, 2700, 3600, 5400, 7200,
+    10800, 14400, 18000,
+];
+/// Recording gaps up to this many seconds repeat the previous sample (smart
+/// recording); longer gaps are pauses and count as zero watts.
+pub(crate) const MAX_HOLD_SECS: i64 = 5;
+/// Bump when the calculation changes so cached curves are rebuilt.
+const CACHE_VERSION: u32 = 1;
+const CACHE_FILE: &str = "power_curve.json.zst";
+
+/// Best average power of one activity over `duration` seconds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MeanMax {
+    pub duration: usize,
+    pub watts: f64,
+}
+
+/// Best average power over `duration` seconds within a date range and the
+/// activity it was set in.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BestEffort {
+    pub duration: usize,
+    pub watts: f64,
+    pub activity_id: u64,
+    pub start_date: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedCurve {
+    version: u32,
+    curve: Vec<MeanMax>,
+}
+
+/// Power at one-second resolution, filling recording gaps as described at
+/// [`MAX_HOLD_SECS`]. Without a usable time stream samples are taken as 1 s
+/// apart.
+fn resample(time: &[i64], power: &[i64]) -> Vec<f64> {
+    let longest = DURATIONS[DURATIONS.len() - 1] as i64;
+    let mut out = Vec::with_capacity(power.len());
+    for (i, &p) in power.iter().enumerate() {
+        let p = p.max(0) as f64;
+        out.push(p);
+        if i + 1 == power.len() {
+            break;
+        }
+        let gap = match (time.get(i), time.get(i + 1)) {
+            (Some(t), Some(next)) => next - t,
+            _ => 1,
+        };
+        if gap > 1 {
+            // a window never spans more than the longest duration
+            let fill = if gap <= MAX_HOLD_SECS { p } else { 0.0 };
+            out.extend(std::iter::repeat_n(fill, (gap - 1).min(longest) as usize));
+        }
+    }
+    out
+}
+
+/// Mean-maximal power at every duration in [`DURATIONS`] that fits into the
+/// activity; empty without a power stream.
+pub fn mean_max(streams: &ParsedStreams) -> Vec<MeanMax> {
+    let watts = resample(&streams.time, &streams.power);
+    let mut prefix = Vec::with_capacity(watts.len() + 1);
+    prefix.push(0.0);
+    for w in &watts {
+        prefix.push(prefix[prefix.len() - 1] + w);
+    }
+    DURATIONS
+        .iter()
+        .take_while(|d| **d <= watts.len())
+        .map(|&d| {
+            let best = (d..prefix.len()).map(|end| prefix[end] - prefix[end - d]).fold(0.0, f64::max);
+            MeanMax { duration: d, watts: best / d as f64 }
+        })
+        .collect()
+}
+
+impl Storage {
+    /// Compute the curve of the activity stored in `dir` and cache it.
+    pub(crate) async fn cache_power_curve(&self, dir: &str, streams: &ParsedStreams) -> anyhow::Result<Vec<MeanMax>> {
+        let curve = mean_max(streams);
+        let cached = CachedCurve { version: CACHE_VERSION, curve };
+        self.write_zstd(&format!("{}/{}", dir, CACHE_FILE), &serde_json::to_value(&cached)?).await?;
+        Ok(cached.curve)
+    }
+
+    /// Mean-maximal power curve of one activity.
+    pub async fn activity_power_curve(&self, id: u64) -> anyhow::Result<Vec<MeanMax>> {
+        let Some(dir) = self.find_activity_dir(id).await? else {
+            anyhow::bail!("not found")
+        };
+        self.dir_power_curve(&dir).await
+    }
+
+    /// Cached curve of the activity stored in `dir`, computed on a miss.
+    async fn dir_power_curve(&self, dir: &str) -> anyhow::Result<Vec<MeanMax>> {
+        if let Ok(value) = self.read_zstd(&format!("{}/{}", dir, CACHE_FILE)).await {
+            if let Ok(cached) = serde_json::from_value::<CachedCurve>(value) {
+                if cached.version == CACHE_VERSION {
+                    return Ok(cached.curve);
+                }
+            }
+        }
+        let raw_streams = self.read_zstd(&format!("{}/streams.json.zst", dir)).await?;
+        let streams = crate::schema::parse_streams(&raw_streams).unwrap_or_default();
+        self.cache_power_curve(dir, &streams).await
+    }
+
+    /// Best efforts across all activities starting within `from..=to`; either
+    /// bound may be open.
+    pub async fn power_curve(&self, from: Option<NaiveDate>, to: Option<NaiveDate>) -> anyhow::Result<Vec<BestEffort>> {
+        let from = from.map(|d| d.to_string());
+        let to = to.and_then(|d| d.succ_opt()).map(|d| d.to_string());
+        let mut best: Vec<Option<BestEffort>> = vec![None; DURATIONS.len()];
+        for summary in self.activity_summaries_between(from.as_deref(), to.as_deref()).await? {
+            // indexed activities are stored below the year they start in
+            let dir = self.activity_dir(summary.start_date.get(..4).unwrap_or_default(), summary.id);
+            let curve = match self.dir_power_curve(&dir).await {
+                Ok(curve) => curve,
+                Err(e) => {
+                    warn!(id = summary.id, ?e, "skipping activity in power curve");
+                    continue;
+                }
+            };
+            for (slot, point) in best.iter_mut().zip(curve) {
+                if slot.as_ref().is_none_or(|b| point.watts > b.watts) {
+                    *slot = Some(BestEffort {
+                        duration: point.duration,
+                        watts: point.watts,
+                        activity_id: summary.id,
+                        start_date: summary.start_date.clone(),
+                    });
+                }
+            }
+        }
+        Ok(best.into_iter().flatten().collect())
+    }
+}
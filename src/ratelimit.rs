@@ -0,0 +1,111 @@
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use reqwest::header::HeaderMap;
+use serde::Serialize;
+use std::sync::Mutex;
+
+/// Snapshot of the Strava API budget as reported by the last response.
+///
+/// Strava returns `X-RateLimit-Limit` and `X-RateLimit-Usage` headers holding
+/// two comma-separated values: the 15-minute window and the daily window.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RateLimitStatus {
+    /// Requests allowed per 15-minute window
+    pub short_limit: Option<u32>,
+    /// Requests used in the current 15-minute window
+    pub short_usage: Option<u32>,
+    /// Requests allowed per day
+    pub daily_limit: Option<u32>,
+    /// Requests used today
+    pub daily_usage: Option<u32>,
+    /// Requests are held back until this time when a budget is exhausted
+    pub paused_until: Option<DateTime<Utc>>,
+    /// Which budget caused the pause (`short`, `daily` or `throttled`)
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    state: Mutex<RateLimitStatus>,
+}
+
+/// Start of the next natural 15-minute window, when Strava resets the short budget.
+pub fn next_short_window(now: DateTime<Utc>) -> DateTime<Utc> {
+    let start = now.duration_trunc(Duration::minutes(15)).unwrap_or(now);
+    start + Duration::minutes(15)
+}
+
+/// Next midnight UTC, when Strava resets the daily budget.
+pub fn next_daily_window(now: DateTime<Utc>) -> DateTime<Utc> {
+    let start = now.duration_trunc(Duration::days(1)).unwrap_or(now);
+    start + Duration::days(1)
+}
+
+fn parse_pair(headers: &HeaderMap, name: &str) -> Option<(u32, u32)> {
+    let value = headers.get(name)?.to_str().ok()?;
+    let mut parts = value.split(',').map(|p| p.trim().parse::<u32>());
+    match (parts.next(), parts.next()) {
+        (Some(Ok(a)), Some(Ok(b))) => Some((a, b)),
+        _ => None,
+    }
+}
+
+impl RateLimiter {
+    pub fn status(&self) -> RateLimitStatus {
+        self.state.lock().unwrap().clone()
+    }
+
+    /// Record the budget from response headers, pausing when a window is used up.
+    ///
+    /// Responses without budget headers (errors, proxies) never pause; the
+    /// usage of a window that has rolled over since is forgotten instead.
+    pub fn update(&self, headers: &HeaderMap, now: DateTime<Utc>) {
+        let limit = parse_pair(headers, "x-ratelimit-limit");
+        let usage = parse_pair(headers, "x-ratelimit-usage");
+        let mut state = self.state.lock().unwrap();
+        if let Some((short, daily)) = limit {
+            state.short_limit = Some(short);
+            state.daily_limit = Some(daily);
+        }
+        if state.paused_until.is_some_and(|t| t <= now) {
+            // a new day also starts a new 15-minute window
+            if state.reason.as_deref() == Some("daily") {
+                state.daily_usage = None;
+            }
+            state.short_usage = None;
+            state.paused_until = None;
+            state.reason = None;
+        }
+        let Some((short, daily)) = usage else {
+            return;
+        };
+        state.short_usage = Some(short);
+        state.daily_usage = Some(daily);
+        let exhausted = |used: Option<u32>, limit: Option<u32>| matches!((used, limit), (Some(u), Some(l)) if u >= l);
+        if exhausted(state.daily_usage, state.daily_limit) {
+            state.paused_until = Some(next_daily_window(now));
+            state.reason = Some("daily".into());
+        } else if exhausted(state.short_usage, state.short_limit) {
+            state.paused_until = Some(next_short_window(now));
+            state.reason = Some("short".into());
+        }
+    }
+
+    /// Handle a `429 Too Many Requests`, pausing at least until the next short window.
+    pub fn throttled(&self, headers: &HeaderMap, now: DateTime<Utc>) {
+        self.update(headers, now);
+        let mut state = self.state.lock().unwrap();
+        if state.paused_until.is_none() {
+            state.paused_until = Some(next_short_window(now));
+            state.reason = Some("throttled".into());
+        }
+    }
+
+    /// Time to wait before the next request may be sent.
+    pub fn wait_duration(&self, now: DateTime<Utc>) -> Option<std::time::Duration> {
+        let state = self.state.lock().unwrap();
+        state
+            .paused_until
+            .filter(|t| *t > now)
+            .and_then(|t| (t - now).to_std().ok())
+    }
+}
@@ -0,0 +1,168 @@
+//! Re-derivation of NP, IF, TSS and W'bal metrics across stored activities.
+//!
+//! [`Storage::save`] writes NP, IF and TSS into `meta.json.zst` once. After an
+//! FTP history edit or a change to the formulas the recompute job brings them
+//! in line with the FTP effective on each activity's start date. It also
+//! stores `min_wbal` and `matches_burned` against the CP and W' fitted to the
+//! rides before each activity, which only settle once the earlier rides are
+//! stored.
+
+use crate::schema::ActivityDetail;
+use crate::storage::{ftp_on, power_metrics, Storage};
+use chrono::NaiveDate;
+use serde::Serialize;
+use tracing::{info, warn};
+
+/// Log progress after this many activities.
+const PROGRESS_EVERY: usize = 100;
+/// Differences below this are rounding noise rather than a change.
+const TOLERANCE: f64 = 1e-6;
+
+#[derive(Debug, Clone, Default)]
+pub struct RecomputeOptions {
+    /// First start date to include
+    pub from: Option<NaiveDate>,
+    /// Last start date to include
+    pub to: Option<NaiveDate>,
+    /// Report what would change without rewriting any activity
+    pub dry_run: bool,
+}
+
+/// Stored and freshly derived value of one metric.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MetricChange {
+    pub before: Option<f64>,
+    pub after: f64,
+}
+
+/// Metrics of one activity that changed; unchanged metrics are omitted.
+#[derive(Debug, Clone, Serialize)]
+pub struct ActivityChange {
+    pub id: u64,
+    pub name: String,
+    pub start_date: String,
+    /// FTP effective on the start date
+    pub ftp: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub normalized_power: Option<MetricChange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub intensity_factor: Option<MetricChange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub training_stress_score: Option<MetricChange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_wbal: Option<MetricChange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matches_burned: Option<MetricChange>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecomputeFailure {
+    pub id: u64,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RecomputeReport {
+    pub dry_run: bool,
+    /// Activities in the selected range
+    pub scanned: usize,
+    /// Activities whose metrics changed, rewritten unless `dry_run` is set
+    pub updated: usize,
+    pub unchanged: usize,
+    /// Activities without a power stream
+    pub skipped: usize,
+    pub failed: Vec<RecomputeFailure>,
+    pub changes: Vec<ActivityChange>,
+}
+
+enum Outcome {
+    Changed(ActivityChange),
+    Unchanged,
+    NoPower,
+}
+
+fn change(meta: &serde_json::Value, key: &str, after: f64) -> Option<MetricChange> {
+    let before = meta.get(key).and_then(|v| v.as_f64());
+    match before {
+        Some(b) if (b - after).abs() <= TOLERANCE => None,
+        _ => Some(MetricChange { before, after }),
+    }
+}
+
+async fn recompute_activity(storage: &Storage, id: u64, ftp: f64, dry_run: bool) -> anyhow::Result<Outcome> {
+    let (meta, raw_streams) = storage.load_raw_activity(id).await?;
+    let detail = ActivityDetail { meta, streams: crate::schema::parse_streams(&raw_streams).unwrap_or_default() };
+    let Some(m) = power_metrics(&detail.meta, &detail.streams, ftp) else {
+        return Ok(Outcome::NoPower);
+    };
+    let wbal = storage.activity_wbal_metrics(&detail).await?;
+    let mut meta = detail.meta;
+    let c = ActivityChange {
+        id,
+        name: meta["name"].as_str().unwrap_or_default().to_string(),
+        start_date: meta["start_date"].as_str().unwrap_or_default().to_string(),
+        ftp,
+        normalized_power: change(&meta, "normalized_power", m.normalized_power),
+        intensity_factor: change(&meta, "intensity_factor", m.intensity_factor),
+        training_stress_score: change(&meta, "training_stress_score", m.training_stress_score),
+        min_wbal: wbal.and_then(|(min, _)| change(&meta, "min_wbal", min)),
+        matches_burned: wbal.and_then(|(_, matches)| change(&meta, "matches_burned", matches as f64)),
+    };
+    if c.normalized_power.is_none()
+        && c.intensity_factor.is_none()
+        && c.training_stress_score.is_none()
+        && c.min_wbal.is_none()
+        && c.matches_burned.is_none()
+    {
+        return Ok(Outcome::Unchanged);
+    }
+    if let (Some((min, matches)), Some(obj)) = (wbal, meta.as_object_mut()) {
+        obj.insert("min_wbal".into(), serde_json::Value::from(min));
+        obj.insert("matches_burned".into(), serde_json::Value::from(matches));
+    }
+    if !dry_run {
+        // save derives the same NP, IF and TSS again and refreshes the index entry
+        storage.save(&meta, &raw_streams).await?;
+    }
+    Ok(Outcome::Changed(c))
+}
+
+/// Re-derive NP, IF, TSS and the W'bal metrics of every activity starting
+/// within `from..=to`, oldest first, using the FTP effective on its start date.
+pub async fn recompute(storage: &Storage, opts: &RecomputeOptions) -> anyhow::Result<RecomputeReport> {
+    let from = opts.from.map(|d| d.to_string());
+    let to = opts.to.and_then(|d| d.succ_opt()).map(|d| d.to_string());
+    let mut summaries = storage.activity_summaries_between(from.as_deref(), to.as_deref()).await?;
+    summaries.reverse();
+    let ftp_history = storage.get_ftp_history().await?;
+
+    let total = summaries.len();
+    let mut report = RecomputeReport { dry_run: opts.dry_run, scanned: total, ..Default::default() };
+    info!(total, dry_run = opts.dry_run, "recomputing derived metrics");
+    for (i, summary) in summaries.iter().enumerate() {
+        let ftp = ftp_on(&ftp_history, &summary.start_date).unwrap_or(240.0);
+        match recompute_activity(storage, summary.id, ftp, opts.dry_run).await {
+            Ok(Outcome::Changed(c)) => {
+                report.updated += 1;
+                report.changes.push(c);
+            }
+            Ok(Outcome::Unchanged) => report.unchanged += 1,
+            Ok(Outcome::NoPower) => report.skipped += 1,
+            Err(e) => {
+                warn!(id = summary.id, ?e, "failed to recompute activity");
+                report.failed.push(RecomputeFailure { id: summary.id, error: format!("{:#}", e) });
+            }
+        }
+        if (i + 1) % PROGRESS_EVERY == 0 {
+            info!(done = i + 1, total, updated = report.updated, "recompute progress");
+        }
+    }
+    info!(
+        total,
+        updated = report.updated,
+        skipped = report.skipped,
+        failed = report.failed.len(),
+        "recompute complete"
+    );
+    Ok(report)
+}
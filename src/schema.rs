@@ -43,6 +43,15 @@ pub struct ActivitySummary {
     pub training_stress_score: Option<f64>,
     /// Activity type such as Ride or Run if available
     pub activity_type: Option<String>,
+    /// Lowest W' balance in joules once stored by the recompute job
+    pub min_wbal: Option<f64>,
+    /// Efforts that each used at least 2 kJ of W', stored with `min_wbal`
+    pub matches_burned: Option<u32>,
+    /// Seconds spent in each power zone if power is available
+    pub time_in_power_zones: Option<Vec<i64>>,
+    /// Seconds spent in each heart-rate zone if heart rate and an LTHR or
+    /// max HR are available
+    pub time_in_hr_zones: Option<Vec<i64>>,
     /// Performance trend classification comparing recent rides
     pub trend: Option<TrendSummary>,
 }
@@ -56,38 +65,81 @@ pub struct TrendSummary {
     pub power: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 pub struct ParsedStreams {
     pub time: Vec<i64>,
     /// Power data in watts if available
     pub power: Vec<i64>,
     /// Heart rate data in bpm if available
     pub heartrate: Vec<i64>,
+    /// Cadence in rpm if available
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cadence: Option<Vec<i64>>,
+    /// Smoothed speed in meters per second if available
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub velocity_smooth: Option<Vec<f64>>,
+    /// Cumulative distance in meters if available
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub distance: Option<Vec<f64>>,
+    /// Smoothed grade in percent if available
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grade_smooth: Option<Vec<f64>>,
+    /// Temperature in degrees Celsius if available
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temp: Option<Vec<i64>>,
+    /// Whether the athlete was moving at each sample if available
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub moving: Option<Vec<bool>>,
+    /// Altitude in meters if available
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub altitude: Option<Vec<f64>>,
+    /// Latitude/longitude pairs in degrees if available
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latlng: Option<Vec<[f64; 2]>>,
+}
+
+/// Stream types requested from Strava for every activity.
+pub const STREAM_KEYS: &str = "time,latlng,distance,altitude,velocity_smooth,heartrate,cadence,watts,temp,moving,grade_smooth";
+
+/// Samples of stream `key`, accepting both Strava's `key_by_type` objects
+/// (`{"data": [...]}`) and plain arrays.
+fn stream_data<'a>(v: &'a serde_json::Value, key: &str) -> Option<&'a Vec<serde_json::Value>> {
+    let s = v.get(key)?;
+    if s.is_object() { s.get("data")?.as_array() } else { s.as_array() }
+}
+
+fn parse_i64(v: &serde_json::Value, key: &str) -> Option<Vec<i64>> {
+    stream_data(v, key).map(|arr| arr.iter().map(|x| x.as_i64().unwrap_or(0)).collect())
+}
+
+fn parse_f64(v: &serde_json::Value, key: &str) -> Option<Vec<f64>> {
+    stream_data(v, key).map(|arr| arr.iter().map(|x| x.as_f64().unwrap_or(0.0)).collect())
 }
 
 pub fn parse_streams(v: &serde_json::Value) -> Option<ParsedStreams> {
-    let time_val = v.get("time")?;
-    let time = if time_val.is_object() {
-        time_val.get("data")?.as_array()?
-    } else {
-        time_val.as_array()?
-    };
-    let power = v
-        .get("watts")
-        .or_else(|| v.get("power"))
-        .and_then(|p| if p.is_object() { p.get("data") } else { Some(p) })
-        .and_then(|d| d.as_array())
-        .map(|arr| arr.iter().map(|x| x.as_i64().unwrap_or(0)).collect())
-        .unwrap_or_else(Vec::new);
-    let heartrate = v
-        .get("heartrate")
-        .and_then(|p| p.get("data"))
-        .and_then(|d| d.as_array())
-        .map(|arr| arr.iter().map(|x| x.as_i64().unwrap_or(0)).collect())
-        .unwrap_or_else(Vec::new);
+    let time = stream_data(v, "time")?;
+    let power = parse_i64(v, "watts")
+        .or_else(|| parse_i64(v, "power"))
+        .unwrap_or_default();
+    let heartrate = parse_i64(v, "heartrate").unwrap_or_default();
+    let moving = stream_data(v, "moving")
+        .map(|arr| arr.iter().map(|x| x.as_bool().unwrap_or(false)).collect());
+    let latlng = stream_data(v, "latlng").map(|arr| {
+        arr.iter()
+            .map(|p| [p[0].as_f64().unwrap_or(0.0), p[1].as_f64().unwrap_or(0.0)])
+            .collect()
+    });
     Some(ParsedStreams {
         time: time.iter().map(|x| x.as_i64().unwrap_or(0)).collect(),
         power,
         heartrate,
+        cadence: parse_i64(v, "cadence"),
+        velocity_smooth: parse_f64(v, "velocity_smooth"),
+        distance: parse_f64(v, "distance"),
+        grade_smooth: parse_f64(v, "grade_smooth"),
+        temp: parse_i64(v, "temp"),
+        moving,
+        altitude: parse_f64(v, "altitude"),
+        latlng,
     })
 }
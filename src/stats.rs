@@ -23,6 +23,8 @@ struct Acc {
     tss_sum: f64,
     spd_sum: f64,
     spd_count: usize,
+    power_zones: Vec<i64>,
+    hr_zones: Vec<i64>,
 }
 
 #[derive(Debug, Serialize)]
@@ -34,6 +36,20 @@ pub struct StatsEntry {
     pub intensity_factor: Option<f64>,
     pub training_stress: Option<f64>,
     pub average_speed: Option<f64>,
+    /// Seconds per power zone summed over the rides with power
+    pub time_in_power_zones: Option<Vec<i64>>,
+    /// Seconds per heart-rate zone summed over the rides with heart rate
+    pub time_in_hr_zones: Option<Vec<i64>>,
+}
+
+/// Add `zones` to `total` zone by zone.
+fn add_zones(total: &mut Vec<i64>, zones: &[i64]) {
+    if total.len() < zones.len() {
+        total.resize(zones.len(), 0);
+    }
+    for (t, z) in total.iter_mut().zip(zones) {
+        *t += z;
+    }
 }
 
 fn period_key(date: NaiveDate, p: Period) -> String {
@@ -55,17 +71,16 @@ impl Storage {
         ids: Option<&[u64]>,
         types: Option<&[String]>,
     ) -> anyhow::Result<Vec<StatsEntry>> {
-        let acts = self.list_activities(None).await?;
+        let acts = self.activity_summaries().await?;
         let filter: Option<HashSet<u64>> = ids.map(|l| l.iter().copied().collect());
         let type_filter: Option<HashSet<String>> = types.map(|l| l.iter().cloned().collect());
         let mut map: BTreeMap<String, Acc> = BTreeMap::new();
-        for a in acts {
+        for summary in acts {
             if let Some(ref f) = filter {
-                if !f.contains(&a.id) {
+                if !f.contains(&summary.id) {
                     continue;
                 }
             }
-            let summary = self.load_activity_summary(a.id).await?;
             if let Some(ref tf) = type_filter {
                 if let Some(ref t) = summary.activity_type {
                     if !tf.contains(t) {
@@ -95,6 +110,12 @@ impl Storage {
                 entry.spd_sum += spd;
                 entry.spd_count += 1;
             }
+            if let Some(z) = &summary.time_in_power_zones {
+                add_zones(&mut entry.power_zones, z);
+            }
+            if let Some(z) = &summary.time_in_hr_zones {
+                add_zones(&mut entry.hr_zones, z);
+            }
         }
         let mut out = Vec::new();
         for (period, acc) in map {
@@ -106,6 +127,8 @@ impl Storage {
                 intensity_factor: if acc.if_count > 0 { Some(acc.if_sum / acc.if_count as f64) } else { None },
                 training_stress: if acc.count > 0 { Some(acc.tss_sum) } else { None },
                 average_speed: if acc.spd_count > 0 { Some(acc.spd_sum / acc.spd_count as f64) } else { None },
+                time_in_power_zones: if acc.power_zones.is_empty() { None } else { Some(acc.power_zones) },
+                time_in_hr_zones: if acc.hr_zones.is_empty() { None } else { Some(acc.hr_zones) },
             });
         }
         Ok(out)
@@ -1,11 +1,18 @@
-use crate::schema::{ActivityHeader, ActivityDetail, ParsedStreams, TrendSummary};
-use crate::utils::Storage as StorageCfg;
+use crate::blob::{BlobStore, Credentials, FsBlobStore, S3BlobStore};
+use crate::schema::{ActivityHeader, ActivityDetail, ActivitySummary, ParsedStreams, TrendSummary};
+use crate::store::{HistoryKind, HistoryPoint, JsonStore, MetricStore};
+use crate::utils::{Backend, Config, Storage as StorageCfg, Zones};
+use crate::zones::{time_in_zones, ZoneBounds};
 use chrono::Utc;
-use std::path::{Path, PathBuf};
-use tokio::fs;
-use tokio::io::AsyncWriteExt;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
 use zstd::stream::{encode_all, decode_all};
 
+fn today() -> String {
+    Utc::now().date_naive().to_string()
+}
+
 fn weighted_avg_power(power: &[i64]) -> f64 {
     if power.is_empty() {
         return 0.0;
@@ -21,18 +28,239 @@ fn weighted_avg_power(power: &[i64]) -> f64 {
     (fourth_sum / count as f64).powf(0.25)
 }
 
+/// NP, IF and TSS that [`Storage::save`] stores in the activity metadata.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PowerMetrics {
+    pub normalized_power: f64,
+    pub intensity_factor: f64,
+    pub training_stress_score: f64,
+}
+
+/// Derive [`PowerMetrics`] from the power stream at `ftp`, or `None` for
+/// activities without power data.
+pub fn power_metrics(meta: &serde_json::Value, streams: &ParsedStreams, ftp: f64) -> Option<PowerMetrics> {
+    if streams.power.is_empty() {
+        return None;
+    }
+    let np = weighted_avg_power(&streams.power);
+    let duration = meta
+        .get("elapsed_time")
+        .and_then(|v| v.as_i64())
+        .or_else(|| streams.time.last().cloned())
+        .unwrap_or(0) as f64;
+    let ifv = np / ftp;
+    Some(PowerMetrics {
+        normalized_power: np,
+        intensity_factor: ifv,
+        training_stress_score: (duration * np * ifv) / (ftp * 3600.0) * 100.0,
+    })
+}
+
+/// Summary of a stored activity; IF and TSS fall back to `ftp` when the
+/// metadata does not carry them. Time in zones uses `zones`.
+fn summarize(id: u64, detail: &ActivityDetail, ftp: f64, zones: &ZoneBounds) -> ActivitySummary {
+    let duration = detail
+        .meta
+        .get("elapsed_time")
+        .and_then(|v| v.as_i64())
+        .or_else(|| detail.streams.time.last().cloned())
+        .unwrap_or(0);
+    let weighted_average_power = detail
+        .meta
+        .get("weighted_average_watts")
+        .and_then(|v| v.as_f64())
+        .or_else(|| detail.meta.get("average_watts").and_then(|v| v.as_f64()))
+        .or_else(|| if !detail.streams.power.is_empty() {
+            Some(weighted_avg_power(&detail.streams.power))
+        } else {
+            None
+        });
+    let distance = detail.meta.get("distance").and_then(|v| v.as_f64()).unwrap_or(0.0);
+    let total_elevation_gain = detail
+        .meta
+        .get("total_elevation_gain")
+        .and_then(|v| v.as_f64());
+    let average_speed = detail
+        .meta
+        .get("average_speed")
+        .and_then(|v| v.as_f64())
+        .or_else(|| if duration > 0 { Some(distance / duration as f64) } else { None })
+        .map(|mps| mps * 3.6);
+    let max_speed = detail
+        .meta
+        .get("max_speed")
+        .and_then(|v| v.as_f64())
+        .map(|mps| mps * 3.6);
+    let pr_count = if let Some(efforts) = detail
+        .meta
+        .get("segment_efforts")
+        .and_then(|v| v.as_array())
+    {
+        Some(
+            efforts
+                .iter()
+                .filter(|e| e.get("pr_rank").and_then(|v| v.as_i64()) == Some(1))
+                .count() as i64,
+        )
+    } else {
+        detail.meta.get("pr_count").and_then(|v| v.as_i64())
+    };
+    let average_heartrate = if !detail.streams.heartrate.is_empty() {
+        Some(detail.streams.heartrate.iter().sum::<i64>() as f64 / detail.streams.heartrate.len() as f64)
+    } else {
+        detail.meta.get("average_heartrate").and_then(|v| v.as_f64())
+    };
+    let normalized_power = detail
+        .meta
+        .get("normalized_power")
+        .and_then(|v| v.as_f64())
+        .or_else(|| if !detail.streams.power.is_empty() {
+            Some(weighted_avg_power(&detail.streams.power))
+        } else { None });
+    let intensity_factor = detail
+        .meta
+        .get("intensity_factor")
+        .and_then(|v| v.as_f64())
+        .or_else(|| normalized_power.map(|np| np / ftp));
+    let training_stress_score = detail
+        .meta
+        .get("training_stress_score")
+        .and_then(|v| v.as_f64())
+        .or_else(|| normalized_power.map(|np| (duration as f64 * np * (np / ftp)) / (ftp * 3600.0) * 100.0));
+    let summary_polyline = detail
+        .meta
+        .get("map")
+        .and_then(|m| m.get("summary_polyline"))
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string());
+    let activity_type = detail
+        .meta
+        .get("type")
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string());
+    let min_wbal = detail.meta.get("min_wbal").and_then(|v| v.as_f64());
+    let matches_burned = detail.meta.get("matches_burned").and_then(|v| v.as_u64()).map(|n| n as u32);
+    let time_in_power_zones = time_in_zones(&detail.streams.time, &detail.streams.power, &zones.power);
+    let time_in_hr_zones = zones
+        .heartrate
+        .as_ref()
+        .and_then(|bounds| time_in_zones(&detail.streams.time, &detail.streams.heartrate, bounds));
+    ActivitySummary {
+        id: detail.meta.get("id").and_then(|v| v.as_u64()).unwrap_or(id),
+        name: detail
+            .meta
+            .get("name")
+            .and_then(|v| v.as_str())
+            .unwrap_or("")
+            .to_string(),
+        start_date: detail
+            .meta
+            .get("start_date")
+            .and_then(|v| v.as_str())
+            .unwrap_or("")
+            .to_string(),
+        distance: detail.meta.get("distance").and_then(|v| v.as_f64()).unwrap_or(0.0),
+        total_elevation_gain,
+        duration,
+        weighted_average_power,
+        average_speed,
+        max_speed,
+        pr_count,
+        average_heartrate,
+        summary_polyline,
+        normalized_power,
+        intensity_factor,
+        training_stress_score,
+        activity_type,
+        min_wbal,
+        matches_burned,
+        time_in_power_zones,
+        time_in_hr_zones,
+        trend: None,
+    }
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct FtpEntry {
     pub date: String,
     pub ftp: f64,
 }
 
+/// Value in effect on `date`; entries are `(date, value)` in write order.
+fn value_on<'a>(entries: impl Iterator<Item = (&'a str, f64)>, date: &str) -> Option<f64> {
+    let day = date.get(..10).unwrap_or(date);
+    let mut effective: Option<(&str, f64)> = None;
+    let mut earliest: Option<(&str, f64)> = None;
+    for (d, v) in entries {
+        if d <= day && effective.is_none_or(|(best, _)| d >= best) {
+            effective = Some((d, v));
+        }
+        if earliest.is_none_or(|(first, _)| d < first) {
+            earliest = Some((d, v));
+        }
+    }
+    effective.or(earliest).map(|(_, v)| v)
+}
+
+/// FTP in effect on `date` (a date or RFC 3339 timestamp): the latest entry
+/// dated on or before that day, or the earliest entry for activities older
+/// than the history. Of several entries on one day the last written wins.
+pub fn ftp_on(history: &[FtpEntry], date: &str) -> Option<f64> {
+    value_on(history.iter().map(|e| (e.date.as_str(), e.ftp)), date)
+}
+
+/// Weight in effect on `date`, chosen like [`ftp_on`].
+pub fn weight_on(history: &[WeightEntry], date: &str) -> Option<f64> {
+    value_on(history.iter().map(|e| (e.date.as_str(), e.weight)), date)
+}
+
+fn point_on(history: &[HistoryPoint], date: &str) -> Option<f64> {
+    value_on(history.iter().map(|p| (p.date.as_str(), p.value)), date)
+}
+
+/// FTP, LTHR and max HR histories for summarizing many activities at once.
+struct Thresholds {
+    ftp: Vec<FtpEntry>,
+    lthr: Vec<HistoryPoint>,
+    max_hr: Vec<HistoryPoint>,
+}
+
+impl Thresholds {
+    /// Index entry of an activity with the thresholds of its start date.
+    fn summarize(&self, zones: &Zones, id: u64, meta: serde_json::Value, raw_streams: &serde_json::Value) -> ActivitySummary {
+        let streams = crate::schema::parse_streams(raw_streams).unwrap_or_default();
+        let date = meta["start_date"].as_str().unwrap_or_default();
+        let ftp = ftp_on(&self.ftp, date).unwrap_or(240.0);
+        let bounds = ZoneBounds::new(zones, ftp, point_on(&self.lthr, date), point_on(&self.max_hr, date));
+        summarize(id, &ActivityDetail { meta, streams }, ftp, &bounds)
+    }
+}
+
+/// Decompress and parse one stored `.json.zst` document.
+fn decode_zstd(data: &[u8]) -> anyhow::Result<serde_json::Value> {
+    let decompressed = decode_all(data)?;
+    Ok(serde_json::from_slice(&decompressed)?)
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct WeightEntry {
     pub date: String,
     pub weight: f64,
 }
 
+/// Lactate threshold heart rate in bpm.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct LthrEntry {
+    pub date: String,
+    pub lthr: f64,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct MaxHrEntry {
+    pub date: String,
+    pub max_hr: f64,
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct WkgEntry {
     pub date: String,
@@ -45,69 +273,485 @@ pub struct ScoreEntry {
     pub score: f64,
 }
 
+/// Progress of a full-history backfill, persisted so a restart resumes
+/// from the oldest activity already listed instead of the newest.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct BackfillState {
+    /// Unix timestamp passed as `before=` for the next page
+    pub before: Option<i64>,
+    /// Number of pages processed so far
+    pub pages: usize,
+    /// Number of activities downloaded by the backfill
+    pub downloaded: usize,
+    /// Set once Strava returns an empty page
+    pub complete: bool,
+}
+
+/// Activity that could not be downloaded during a sync.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct SyncFailure {
+    pub id: u64,
+    /// Failures recorded before the kind was stored count as transient
+    #[serde(default)]
+    pub kind: FailureKind,
+    pub error: String,
+    pub date: String,
+}
+
+/// Whether a failed download is worth retrying on the next sync.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    /// Network error, `5xx`, exhausted rate limit or failed save
+    #[default]
+    Transient,
+    /// Any other error Strava answers the same way again, e.g. a `403`
+    Permanent,
+}
+
+/// Activity directory moved below `quarantine/` because its files could not
+/// be decoded.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct QuarantinedActivity {
+    pub id: u64,
+    /// Original location, e.g. `2024/123`
+    pub path: String,
+    /// New location, e.g. `quarantine/2024-123`
+    pub quarantine_path: String,
+    pub error: String,
+    pub date: String,
+}
+
+/// Async mutexes keyed by file name, shared by all clones of a [`Storage`].
+#[derive(Clone, Default)]
+struct FileLocks(Arc<std::sync::Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>);
+
+impl FileLocks {
+    async fn lock(&self, key: &str) -> tokio::sync::OwnedMutexGuard<()> {
+        let lock = self.0.lock().unwrap().entry(key.to_string()).or_default().clone();
+        lock.lock_owned().await
+    }
+}
+
+const INDEX_FILE: &str = "index.json";
+const SYNC_FAILURES_FILE: &str = "sync_failures.json";
+const QUARANTINE_FILE: &str = "quarantine.json";
+const SUBSCRIPTION_FILE: &str = "subscription.json";
+/// Metadata fields the recompute job derives from W'bal.
+const WBAL_FIELDS: [&str; 2] = ["min_wbal", "matches_burned"];
+
+fn history_file(kind: HistoryKind) -> String {
+    format!("{}.json", kind.as_str())
+}
+
 #[derive(Clone)]
 pub struct Storage {
-    base: PathBuf,
+    /// Activity files and JSON documents, keyed relative to the user directory
+    blobs: Arc<dyn BlobStore>,
+    /// Backend for summaries and histories
+    store: Arc<dyn MetricStore>,
+    /// Serialise read-modify-write cycles of the index, the histories and
+    /// the other JSON documents
+    locks: FileLocks,
+    /// Power and heart-rate zone boundaries
+    zones: Zones,
 }
 
 impl Storage {
+    /// Storage keeping summaries and histories in JSON files.
     pub fn new(cfg: &StorageCfg) -> Self {
-        Self { base: PathBuf::from(&cfg.data_dir).join(&cfg.user) }
+        let base = PathBuf::from(&cfg.data_dir).join(&cfg.user);
+        let blobs: Arc<dyn BlobStore> = Arc::new(FsBlobStore::new(&base));
+        Self {
+            store: Arc::new(JsonStore::new(blobs.clone())),
+            blobs,
+            locks: FileLocks::default(),
+            zones: Zones::default(),
+        }
     }
 
-    fn activity_dir(&self, year: &str, id: u64) -> PathBuf {
-        self.base.join(year).join(id.to_string())
+    /// Storage using the blob store selected by the `[s3]` section and the
+    /// backend selected in the `[database]` section.
+    ///
+    /// When SQLite is opened for the first time, existing JSON histories are
+    /// copied into it; the summary index is rebuilt on first use.
+    pub async fn from_config(cfg: &Config) -> anyhow::Result<Self> {
+        let mut storage = Self::new(&cfg.storage).with_zones(cfg.zones.clone());
+        if let Some(s3) = &cfg.s3 {
+            let root = match &s3.prefix {
+                Some(prefix) => format!("{}/{}", prefix.trim_matches('/'), cfg.storage.user),
+                None => cfg.storage.user.clone(),
+            };
+            let creds = Credentials {
+                access_key: s3.access_key.clone(),
+                secret_key: s3.secret_key.clone(),
+                region: s3.region.clone(),
+            };
+            storage = storage.with_blobs(Arc::new(S3BlobStore::new(&s3.endpoint, &s3.bucket, &root, creds)?));
+        }
+        match cfg.database.backend {
+            Backend::Json => Ok(storage),
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite => {
+                let path = cfg
+                    .database
+                    .path
+                    .as_ref()
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| PathBuf::from(&cfg.storage.data_dir).join(&cfg.storage.user).join("abcy.sqlite"));
+                let db = crate::store::SqliteStore::open(path)?;
+                let json = JsonStore::new(storage.blobs.clone());
+                for kind in HistoryKind::ALL {
+                    if db.history(kind).await?.is_empty() {
+                        let points = json.history(kind).await?;
+                        if !points.is_empty() {
+                            db.replace_history(kind, &points).await?;
+                        }
+                    }
+                }
+                Ok(storage.with_store(Arc::new(db)))
+            }
+            #[cfg(not(feature = "sqlite"))]
+            Backend::Sqlite => anyhow::bail!("the sqlite backend requires building with `--features sqlite`"),
+        }
     }
 
-    fn ftp_path(&self) -> PathBuf {
-        self.base.join("ftp.json")
+    /// Replace the backend used for summaries and histories.
+    pub fn with_store(mut self, store: Arc<dyn MetricStore>) -> Self {
+        self.store = store;
+        self
     }
 
-    fn weight_path(&self) -> PathBuf {
-        self.base.join("weight.json")
+    /// Replace the blob store holding activity files. Summaries and histories
+    /// move along as JSON documents unless [`Storage::with_store`] is called
+    /// afterwards.
+    pub fn with_blobs(mut self, blobs: Arc<dyn BlobStore>) -> Self {
+        self.store = Arc::new(JsonStore::new(blobs.clone()));
+        self.blobs = blobs;
+        self
     }
 
-    fn wkg_path(&self) -> PathBuf {
-        self.base.join("wkg.json")
+    /// Replace the default Coggan and Friel zone boundaries.
+    pub fn with_zones(mut self, zones: Zones) -> Self {
+        self.zones = zones;
+        self
     }
 
-    fn enduro_path(&self) -> PathBuf {
-        self.base.join("enduro.json")
+    pub fn zones(&self) -> &Zones {
+        &self.zones
     }
 
-    fn fitness_path(&self) -> PathBuf {
-        self.base.join("fitness.json")
+    pub(crate) fn activity_dir(&self, year: &str, id: u64) -> String {
+        format!("{}/{}", year, id)
     }
 
-    async fn load_ftp_history(&self) -> anyhow::Result<Vec<FtpEntry>> {
-        let path = self.ftp_path();
-        if let Ok(data) = fs::read(&path).await {
-            Ok(serde_json::from_slice(&data)?)
-        } else {
-            Ok(Vec::new())
+    async fn read_json<T: serde::de::DeserializeOwned + Default>(&self, key: &str) -> anyhow::Result<T> {
+        match self.blobs.get(key).await? {
+            Some(data) => Ok(serde_json::from_slice(&data)?),
+            None => Ok(T::default()),
         }
     }
 
-    async fn save_ftp_history(&self, hist: &[FtpEntry]) -> anyhow::Result<()> {
-        let data = serde_json::to_vec(hist)?;
-        if let Some(parent) = self.ftp_path().parent() {
-            fs::create_dir_all(parent).await?;
+    async fn write_json<T: serde::Serialize + ?Sized>(&self, key: &str, value: &T) -> anyhow::Result<()> {
+        self.blobs.put(key, serde_json::to_vec(value)?).await
+    }
+
+    /// Summaries of all stored activities, newest first.
+    ///
+    /// Read from the summary index, which [`Storage::save`] and
+    /// [`Storage::delete_activity`] keep up to date. A missing index is rebuilt
+    /// from the activity directories.
+    pub async fn activity_summaries(&self) -> anyhow::Result<Vec<ActivitySummary>> {
+        self.activity_summaries_between(None, None).await
+    }
+
+    /// Summaries of activities starting within `[from, to)`, newest first.
+    /// Bounds are compared against `start_date`, so plain dates such as
+    /// `2024-01-01` work.
+    pub async fn activity_summaries_between(&self, from: Option<&str>, to: Option<&str>) -> anyhow::Result<Vec<ActivitySummary>> {
+        let _guard = self.locks.lock(INDEX_FILE).await;
+        if let Some(summaries) = self.store.summaries_between(from, to).await? {
+            return Ok(summaries);
+        }
+        self.build_index().await?;
+        Ok(self.store.summaries_between(from, to).await?.unwrap_or_default())
+    }
+
+    /// Recreate the summary index by decoding every stored activity,
+    /// returning the number of indexed activities.
+    pub async fn rebuild_index(&self) -> anyhow::Result<usize> {
+        let _guard = self.locks.lock(INDEX_FILE).await;
+        Ok(self.build_index().await?.len())
+    }
+
+    /// Activity directories whose files cannot be decoded are quarantined
+    /// rather than failing the whole index; directories missing a file are
+    /// skipped and left in place. Blob store errors fail the rebuild so a
+    /// partial index never replaces the stored one.
+    async fn build_index(&self) -> anyhow::Result<Vec<ActivitySummary>> {
+        let mut index = Vec::new();
+        let thresholds = self.thresholds().await?;
+        for year in self.year_dirs().await? {
+            for act in self.blobs.children(&year).await? {
+                if !act.is_dir { continue; }
+                let Ok(id) = act.name.parse::<u64>() else { continue };
+                let dir = self.activity_dir(&year, id);
+                let (Some(meta), Some(raw_streams)) = (
+                    self.blobs.get(&format!("{}/meta.json.zst", dir)).await?,
+                    self.blobs.get(&format!("{}/streams.json.zst", dir)).await?,
+                ) else {
+                    // e.g. a save that has not written both files yet
+                    tracing::warn!("skipping incomplete activity directory {}", dir);
+                    continue;
+                };
+                match decode_zstd(&meta).and_then(|meta| Ok((meta, decode_zstd(&raw_streams)?))) {
+                    Ok((meta, raw_streams)) => index.push(thresholds.summarize(&self.zones, id, meta, &raw_streams)),
+                    Err(e) => {
+                        if let Err(qe) = self.quarantine(id, &dir, &e).await {
+                            tracing::warn!("failed to quarantine {}: {:#}", dir, qe);
+                        }
+                    }
+                }
+            }
+        }
+        self.store.replace_summaries(&index).await?;
+        Ok(index)
+    }
+
+    /// Re-summarize the indexed activities affected by a `kind` entry dated
+    /// `date` being added, changed or removed, so their time in zones
+    /// follows the new thresholds. Those are the activities from `date` on,
+    /// or all of them when no earlier entry exists, since the earliest entry
+    /// also applies to older rides.
+    async fn refresh_index_from(&self, kind: HistoryKind, date: &str) -> anyhow::Result<()> {
+        let _guard = self.locks.lock(INDEX_FILE).await;
+        // a missing index is built with the new thresholds when next read
+        let Some(mut index) = self.store.summaries().await? else {
+            return Ok(());
+        };
+        let thresholds = self.thresholds().await?;
+        let first = match kind {
+            HistoryKind::Ftp => thresholds.ftp.first().map(|e| e.date.as_str()),
+            HistoryKind::Lthr => thresholds.lthr.first().map(|p| p.date.as_str()),
+            HistoryKind::MaxHr => thresholds.max_hr.first().map(|p| p.date.as_str()),
+            _ => None,
+        };
+        let date = if first.is_some_and(|first| first < date) { date } else { "" };
+        for summary in index.iter_mut().filter(|s| s.start_date.as_str() >= date) {
+            let dir = self.activity_dir(summary.start_date.get(..4).unwrap_or_default(), summary.id);
+            match self.read_activity_dir(&dir).await {
+                Ok((meta, raw_streams)) => *summary = thresholds.summarize(&self.zones, summary.id, meta, &raw_streams),
+                Err(e) => tracing::warn!("keeping index entry of {}: {:#}", dir, e),
+            }
+        }
+        self.store.replace_summaries(&index).await
+    }
+
+    async fn thresholds(&self) -> anyhow::Result<Thresholds> {
+        Ok(Thresholds {
+            ftp: self.ftp_entries().await?,
+            lthr: self.history(HistoryKind::Lthr).await?,
+            max_hr: self.history(HistoryKind::MaxHr).await?,
+        })
+    }
+
+    /// Top-level directories named after a year.
+    async fn year_dirs(&self) -> anyhow::Result<Vec<String>> {
+        Ok(self
+            .blobs
+            .children("")
+            .await?
+            .into_iter()
+            .filter(|e| e.is_dir && e.name.len() == 4 && e.name.bytes().all(|b| b.is_ascii_digit()))
+            .map(|e| e.name)
+            .collect())
+    }
+
+    async fn read_activity_dir(&self, dir: &str) -> anyhow::Result<(serde_json::Value, serde_json::Value)> {
+        let meta = self.read_zstd(&format!("{}/meta.json.zst", dir)).await?;
+        let streams = self.read_zstd(&format!("{}/streams.json.zst", dir)).await?;
+        Ok((meta, streams))
+    }
+
+    /// Move an undecodable activity directory to `quarantine/<year>-<id>` and
+    /// record it in `quarantine.json`.
+    async fn quarantine(&self, id: u64, dir: &str, error: &anyhow::Error) -> anyhow::Result<()> {
+        let target = format!("quarantine/{}", dir.replace('/', "-"));
+        tracing::warn!("quarantining {} as {}: {:#}", dir, target, error);
+        for entry in self.blobs.children(dir).await? {
+            if entry.is_dir { continue; }
+            if let Some(data) = self.blobs.get(&format!("{}/{}", dir, entry.name)).await? {
+                self.blobs.put(&format!("{}/{}", target, entry.name), data).await?;
+            }
+        }
+        self.blobs.delete_prefix(dir).await?;
+        let _guard = self.locks.lock(QUARANTINE_FILE).await;
+        let mut entries = self.quarantined().await?;
+        entries.retain(|q| q.path != dir);
+        entries.push(QuarantinedActivity {
+            id,
+            path: dir.to_string(),
+            quarantine_path: target,
+            error: format!("{:#}", error),
+            date: Utc::now().to_rfc3339(),
+        });
+        self.write_json(QUARANTINE_FILE, &entries).await
+    }
+
+    /// Activity directories moved aside by the index rebuild.
+    pub async fn quarantined(&self) -> anyhow::Result<Vec<QuarantinedActivity>> {
+        self.read_json(QUARANTINE_FILE).await
+    }
+
+    /// Insert or replace the index entry of one activity.
+    async fn index_activity(&self, summary: ActivitySummary) -> anyhow::Result<()> {
+        let _guard = self.locks.lock(INDEX_FILE).await;
+        if self.store.summaries().await?.is_none() {
+            self.build_index().await?;
+        }
+        self.store.upsert_summary(&summary).await
+    }
+
+    async fn unindex_activity(&self, id: u64) -> anyhow::Result<()> {
+        let _guard = self.locks.lock(INDEX_FILE).await;
+        if self.store.summaries().await?.is_none() {
+            self.build_index().await?;
+        }
+        self.store.remove_summary(id).await
+    }
+
+    pub async fn sync_failures(&self) -> anyhow::Result<Vec<SyncFailure>> {
+        self.read_json(SYNC_FAILURES_FILE).await
+    }
+
+    async fn save_sync_failures(&self, failures: &[SyncFailure]) -> anyhow::Result<()> {
+        self.write_json(SYNC_FAILURES_FILE, failures).await
+    }
+
+    /// Remember that `id` failed to download, replacing any earlier failure for it.
+    pub async fn record_sync_failure(&self, id: u64, kind: FailureKind, error: &str) -> anyhow::Result<()> {
+        let _guard = self.locks.lock(SYNC_FAILURES_FILE).await;
+        let mut failures = self.sync_failures().await?;
+        failures.retain(|f| f.id != id);
+        failures.push(SyncFailure { id, kind, error: error.to_string(), date: Utc::now().to_rfc3339() });
+        self.save_sync_failures(&failures).await
+    }
+
+    pub async fn clear_sync_failure(&self, id: u64) -> anyhow::Result<()> {
+        let _guard = self.locks.lock(SYNC_FAILURES_FILE).await;
+        let mut failures = self.sync_failures().await?;
+        let len = failures.len();
+        failures.retain(|f| f.id != id);
+        if failures.len() != len {
+            self.save_sync_failures(&failures).await?;
         }
-        fs::write(self.ftp_path(), data).await?;
         Ok(())
     }
 
-    pub async fn get_ftp_history(&self) -> anyhow::Result<Vec<FtpEntry>> {
-        let mut hist = self.load_ftp_history().await?;
+    /// Id of the push subscription registered by the `subscriptions` binary;
+    /// webhook events of any other subscription are dropped.
+    pub async fn subscription_id(&self) -> anyhow::Result<Option<u64>> {
+        self.read_json(SUBSCRIPTION_FILE).await
+    }
+
+    pub async fn save_subscription_id(&self, id: Option<u64>) -> anyhow::Result<()> {
+        self.write_json(SUBSCRIPTION_FILE, &id).await
+    }
+
+    pub async fn backfill_state(&self) -> anyhow::Result<BackfillState> {
+        self.read_json("backfill.json").await
+    }
+
+    pub async fn save_backfill_state(&self, state: &BackfillState) -> anyhow::Result<()> {
+        self.write_json("backfill.json", state).await
+    }
+
+    /// History of `kind` sorted by date; entries of one day keep the order
+    /// they were written in.
+    async fn history(&self, kind: HistoryKind) -> anyhow::Result<Vec<HistoryPoint>> {
+        let mut hist = self.store.history(kind).await?;
+        hist.sort_by(|a, b| a.date.cmp(&b.date));
+        Ok(hist)
+    }
+
+    /// History of `kind`, seeded with `default` when empty.
+    async fn seeded_history(&self, kind: HistoryKind, default: impl FnOnce() -> f64) -> anyhow::Result<Vec<HistoryPoint>> {
+        let mut hist = self.history(kind).await?;
         if hist.is_empty() {
-            hist.push(FtpEntry { date: Utc::now().date_naive().to_string(), ftp: 240.0 });
-            self.save_ftp_history(&hist).await?;
+            let _guard = self.locks.lock(&history_file(kind)).await;
+            // another request may have seeded it while we waited
+            hist = self.history(kind).await?;
+            if !hist.is_empty() {
+                return Ok(hist);
+            }
+            let point = HistoryPoint { date: today(), value: default() };
+            self.store.append_history(kind, point.clone()).await?;
+            hist.push(point);
         }
         Ok(hist)
     }
 
+    /// Add an entry, keeping the history sorted by date.
+    async fn insert(&self, kind: HistoryKind, date: &str, value: f64) -> anyhow::Result<()> {
+        let _guard = self.locks.lock(&history_file(kind)).await;
+        let point = HistoryPoint { date: date.to_string(), value };
+        let mut hist = self.history(kind).await?;
+        if hist.last().is_none_or(|last| last.date <= point.date) {
+            return self.store.append_history(kind, point).await;
+        }
+        hist.push(point);
+        hist.sort_by(|a, b| a.date.cmp(&b.date));
+        self.store.replace_history(kind, &hist).await
+    }
+
+    /// Replace all entries dated `date` by one entry with `value`.
+    async fn put_entry(&self, kind: HistoryKind, date: &str, value: f64) -> anyhow::Result<()> {
+        let _guard = self.locks.lock(&history_file(kind)).await;
+        let mut hist = self.history(kind).await?;
+        hist.retain(|p| p.date != date);
+        hist.push(HistoryPoint { date: date.to_string(), value });
+        hist.sort_by(|a, b| a.date.cmp(&b.date));
+        self.store.replace_history(kind, &hist).await
+    }
+
+    /// Remove all entries dated `date`, returning whether there were any.
+    async fn delete_entry(&self, kind: HistoryKind, date: &str) -> anyhow::Result<bool> {
+        let _guard = self.locks.lock(&history_file(kind)).await;
+        let mut hist = self.history(kind).await?;
+        let len = hist.len();
+        hist.retain(|p| p.date != date);
+        if hist.len() == len {
+            return Ok(false);
+        }
+        self.store.replace_history(kind, &hist).await?;
+        Ok(true)
+    }
+
+    async fn append(&self, kind: HistoryKind, value: f64) -> anyhow::Result<()> {
+        self.insert(kind, &today(), value).await
+    }
+
+    pub async fn get_ftp_history(&self) -> anyhow::Result<Vec<FtpEntry>> {
+        let hist = self.seeded_history(HistoryKind::Ftp, || 240.0).await?;
+        Ok(hist.into_iter().map(|p| FtpEntry { date: p.date, ftp: p.value }).collect())
+    }
+
+    /// FTP in effect today.
     pub async fn current_ftp(&self) -> anyhow::Result<f64> {
-        Ok(self.get_ftp_history().await?.last().map(|e| e.ftp).unwrap_or(240.0))
+        self.ftp_at(&today()).await
+    }
+
+    /// FTP in effect on `date`, see [`ftp_on`]. IF and TSS of an activity are
+    /// computed against the FTP of its start date.
+    pub async fn ftp_at(&self, date: &str) -> anyhow::Result<f64> {
+        Ok(ftp_on(&self.ftp_entries().await?, date).unwrap_or(240.0))
+    }
+
+    /// FTP history without seeding it, for lookups that fall back to the
+    /// default themselves.
+    async fn ftp_entries(&self) -> anyhow::Result<Vec<FtpEntry>> {
+        let hist = self.history(HistoryKind::Ftp).await?;
+        Ok(hist.into_iter().map(|p| FtpEntry { date: p.date, ftp: p.value }).collect())
     }
 
     pub async fn ftp_history(&self, count: Option<usize>) -> anyhow::Result<Vec<FtpEntry>> {
@@ -120,42 +764,46 @@ impl Storage {
     }
 
     pub async fn set_ftp(&self, ftp: f64) -> anyhow::Result<()> {
-        let mut hist = self.get_ftp_history().await?;
-        hist.push(FtpEntry { date: Utc::now().date_naive().to_string(), ftp });
-        self.save_ftp_history(&hist).await?;
-        let weight = self.current_weight().await.unwrap_or(75.0);
-        self.record_wkg(ftp / weight).await
+        self.add_ftp(&today(), ftp).await
     }
 
-    async fn load_weight_history(&self) -> anyhow::Result<Vec<WeightEntry>> {
-        let path = self.weight_path();
-        if let Ok(data) = fs::read(&path).await {
-            Ok(serde_json::from_slice(&data)?)
-        } else {
-            Ok(Vec::new())
+    /// Record an FTP effective from `date` (`YYYY-MM-DD`), e.g. a past lab
+    /// test.
+    pub async fn add_ftp(&self, date: &str, ftp: f64) -> anyhow::Result<()> {
+        // a backdated first entry needs no placeholder before it
+        if date >= today().as_str() {
+            self.seeded_history(HistoryKind::Ftp, || 240.0).await?;
         }
+        self.insert(HistoryKind::Ftp, date, ftp).await?;
+        self.refresh_index_from(HistoryKind::Ftp, date).await?;
+        self.recompute_wkg().await
+    }
+
+    /// Set the FTP recorded on `date`, replacing any entries of that day.
+    pub async fn update_ftp_entry(&self, date: &str, ftp: f64) -> anyhow::Result<()> {
+        self.put_entry(HistoryKind::Ftp, date, ftp).await?;
+        self.refresh_index_from(HistoryKind::Ftp, date).await?;
+        self.recompute_wkg().await
     }
 
-    async fn save_weight_history(&self, hist: &[WeightEntry]) -> anyhow::Result<()> {
-        let data = serde_json::to_vec(hist)?;
-        if let Some(parent) = self.weight_path().parent() {
-            fs::create_dir_all(parent).await?;
+    /// Remove the FTP entries of `date`, returning whether there were any.
+    pub async fn delete_ftp_entry(&self, date: &str) -> anyhow::Result<bool> {
+        let deleted = self.delete_entry(HistoryKind::Ftp, date).await?;
+        if deleted {
+            self.refresh_index_from(HistoryKind::Ftp, date).await?;
+            self.recompute_wkg().await?;
         }
-        fs::write(self.weight_path(), data).await?;
-        Ok(())
+        Ok(deleted)
     }
 
     pub async fn get_weight_history(&self) -> anyhow::Result<Vec<WeightEntry>> {
-        let mut hist = self.load_weight_history().await?;
-        if hist.is_empty() {
-            hist.push(WeightEntry { date: Utc::now().date_naive().to_string(), weight: 75.0 });
-            self.save_weight_history(&hist).await?;
-        }
-        Ok(hist)
+        let hist = self.seeded_history(HistoryKind::Weight, || 75.0).await?;
+        Ok(hist.into_iter().map(|p| WeightEntry { date: p.date, weight: p.value }).collect())
     }
 
+    /// Weight recorded for today.
     pub async fn current_weight(&self) -> anyhow::Result<f64> {
-        Ok(self.get_weight_history().await?.last().map(|e| e.weight).unwrap_or(75.0))
+        Ok(weight_on(&self.get_weight_history().await?, &today()).unwrap_or(75.0))
     }
 
     pub async fn weight_history(&self, count: Option<usize>) -> anyhow::Result<Vec<WeightEntry>> {
@@ -168,48 +816,75 @@ impl Storage {
     }
 
     pub async fn set_weight(&self, weight: f64) -> anyhow::Result<()> {
-        let mut hist = self.get_weight_history().await?;
-        hist.push(WeightEntry { date: Utc::now().date_naive().to_string(), weight });
-        self.save_weight_history(&hist).await?;
-        let ftp = self.current_ftp().await.unwrap_or(240.0);
-        self.record_wkg(ftp / weight).await
+        self.add_weight(&today(), weight).await
     }
 
-    async fn load_wkg_history(&self) -> anyhow::Result<Vec<WkgEntry>> {
-        let path = self.wkg_path();
-        if let Ok(data) = fs::read(&path).await {
-            Ok(serde_json::from_slice(&data)?)
-        } else {
-            Ok(Vec::new())
+    /// Record a weight measured on `date` (`YYYY-MM-DD`).
+    pub async fn add_weight(&self, date: &str, weight: f64) -> anyhow::Result<()> {
+        // a backdated first entry needs no placeholder before it
+        if date >= today().as_str() {
+            self.seeded_history(HistoryKind::Weight, || 75.0).await?;
         }
+        self.insert(HistoryKind::Weight, date, weight).await?;
+        self.recompute_wkg().await
     }
 
-    async fn save_wkg_history(&self, hist: &[WkgEntry]) -> anyhow::Result<()> {
-        let data = serde_json::to_vec(hist)?;
-        if let Some(parent) = self.wkg_path().parent() {
-            fs::create_dir_all(parent).await?;
+    /// Set the weight recorded on `date`, replacing any entries of that day.
+    pub async fn update_weight_entry(&self, date: &str, weight: f64) -> anyhow::Result<()> {
+        self.put_entry(HistoryKind::Weight, date, weight).await?;
+        self.recompute_wkg().await
+    }
+
+    /// Remove the weight entries of `date`, returning whether there were any.
+    pub async fn delete_weight_entry(&self, date: &str) -> anyhow::Result<bool> {
+        let deleted = self.delete_entry(HistoryKind::Weight, date).await?;
+        if deleted {
+            self.recompute_wkg().await?;
         }
-        fs::write(self.wkg_path(), data).await?;
-        Ok(())
+        Ok(deleted)
     }
 
-    pub async fn get_wkg_history(&self) -> anyhow::Result<Vec<WkgEntry>> {
-        let mut hist = self.load_wkg_history().await?;
-        if hist.is_empty() {
-            let ftp = self.current_ftp().await.unwrap_or(240.0);
-            let weight = self.current_weight().await.unwrap_or(75.0);
-            hist.push(WkgEntry { date: Utc::now().date_naive().to_string(), wkg: ftp / weight });
-            self.save_wkg_history(&hist).await?;
+    /// LTHR in effect on `date`, chosen like [`ftp_on`]; `None` until one is
+    /// recorded.
+    pub async fn lthr_at(&self, date: &str) -> anyhow::Result<Option<f64>> {
+        Ok(point_on(&self.history(HistoryKind::Lthr).await?, date))
+    }
+
+    /// Recorded LTHR values ordered by newest first.
+    pub async fn lthr_history(&self, count: Option<usize>) -> anyhow::Result<Vec<LthrEntry>> {
+        let mut hist: Vec<_> = self
+            .history(HistoryKind::Lthr)
+            .await?
+            .into_iter()
+            .map(|p| LthrEntry { date: p.date, lthr: p.value })
+            .collect();
+        hist.reverse();
+        if let Some(n) = count {
+            hist.truncate(n);
         }
         Ok(hist)
     }
 
-    pub async fn current_wkg(&self) -> anyhow::Result<f64> {
-        Ok(self.get_wkg_history().await?.last().map(|e| e.wkg).unwrap_or(0.0))
+    /// Record an LTHR effective from `date` (`YYYY-MM-DD`).
+    pub async fn add_lthr(&self, date: &str, lthr: f64) -> anyhow::Result<()> {
+        self.insert(HistoryKind::Lthr, date, lthr).await?;
+        self.refresh_index_from(HistoryKind::Lthr, date).await
     }
 
-    pub async fn wkg_history(&self, count: Option<usize>) -> anyhow::Result<Vec<WkgEntry>> {
-        let mut hist = self.get_wkg_history().await?;
+    /// Max HR in effect on `date`, chosen like [`ftp_on`]; `None` until one
+    /// is recorded.
+    pub async fn max_hr_at(&self, date: &str) -> anyhow::Result<Option<f64>> {
+        Ok(point_on(&self.history(HistoryKind::MaxHr).await?, date))
+    }
+
+    /// Recorded max HR values ordered by newest first.
+    pub async fn max_hr_history(&self, count: Option<usize>) -> anyhow::Result<Vec<MaxHrEntry>> {
+        let mut hist: Vec<_> = self
+            .history(HistoryKind::MaxHr)
+            .await?
+            .into_iter()
+            .map(|p| MaxHrEntry { date: p.date, max_hr: p.value })
+            .collect();
         hist.reverse();
         if let Some(n) = count {
             hist.truncate(n);
@@ -217,88 +892,118 @@ impl Storage {
         Ok(hist)
     }
 
-    async fn record_wkg(&self, wkg: f64) -> anyhow::Result<()> {
-        let mut hist = self.get_wkg_history().await?;
-        hist.push(WkgEntry { date: Utc::now().date_naive().to_string(), wkg });
-        self.save_wkg_history(&hist).await
+    /// Record a max HR effective from `date` (`YYYY-MM-DD`).
+    pub async fn add_max_hr(&self, date: &str, max_hr: f64) -> anyhow::Result<()> {
+        self.insert(HistoryKind::MaxHr, date, max_hr).await?;
+        self.refresh_index_from(HistoryKind::MaxHr, date).await
     }
 
-    async fn load_score_history(&self, path: &Path) -> anyhow::Result<Vec<ScoreEntry>> {
-        if let Ok(data) = fs::read(path).await {
-            Ok(serde_json::from_slice(&data)?)
-        } else {
-            Ok(Vec::new())
+    pub async fn get_wkg_history(&self) -> anyhow::Result<Vec<WkgEntry>> {
+        let mut hist = self.history(HistoryKind::Wkg).await?;
+        if hist.is_empty() {
+            self.recompute_wkg().await?;
+            hist = self.history(HistoryKind::Wkg).await?;
         }
+        Ok(hist.into_iter().map(|p| WkgEntry { date: p.date, wkg: p.value }).collect())
+    }
+
+    /// W/kg in effect today.
+    pub async fn current_wkg(&self) -> anyhow::Result<f64> {
+        let hist = self.get_wkg_history().await?;
+        Ok(value_on(hist.iter().map(|e| (e.date.as_str(), e.wkg)), &today()).unwrap_or(0.0))
     }
 
-    async fn save_score_history(&self, path: &Path, hist: &[ScoreEntry]) -> anyhow::Result<()> {
-        let data = serde_json::to_vec(hist)?;
-        if let Some(parent) = path.parent() {
-            fs::create_dir_all(parent).await?;
+    pub async fn wkg_history(&self, count: Option<usize>) -> anyhow::Result<Vec<WkgEntry>> {
+        let mut hist = self.get_wkg_history().await?;
+        hist.reverse();
+        if let Some(n) = count {
+            hist.truncate(n);
         }
-        fs::write(path, data).await?;
-        Ok(())
+        Ok(hist)
+    }
+
+    /// Rebuild the W/kg history from the FTP and weight timelines, with one
+    /// entry for every day on which either of them changed.
+    async fn recompute_wkg(&self) -> anyhow::Result<()> {
+        let _guard = self.locks.lock(&history_file(HistoryKind::Wkg)).await;
+        // read without seeding, a placeholder dated today would override
+        // backdated entries
+        let ftp = self.history(HistoryKind::Ftp).await?;
+        let weight = self.history(HistoryKind::Weight).await?;
+        let on = |hist: &[HistoryPoint], date: &str| value_on(hist.iter().map(|p| (p.date.as_str(), p.value)), date);
+        let mut dates: Vec<&str> = ftp.iter().chain(&weight).map(|p| p.date.as_str()).collect();
+        dates.sort_unstable();
+        dates.dedup();
+        let mut points: Vec<HistoryPoint> = dates
+            .into_iter()
+            .map(|date| HistoryPoint {
+                date: date.to_string(),
+                value: on(&ftp, date).unwrap_or(240.0) / on(&weight, date).unwrap_or(75.0),
+            })
+            .collect();
+        if points.is_empty() {
+            points.push(HistoryPoint { date: today(), value: 240.0 / 75.0 });
+        }
+        self.store.replace_history(HistoryKind::Wkg, &points).await
     }
 
-    async fn record_score(&self, path: &Path, score: f64) -> anyhow::Result<()> {
-        let mut hist = self.load_score_history(path).await?;
-        hist.push(ScoreEntry { date: Utc::now().date_naive().to_string(), score });
-        self.save_score_history(path, &hist).await
+    async fn score_history(&self, kind: HistoryKind) -> anyhow::Result<Vec<ScoreEntry>> {
+        let hist = self.history(kind).await?;
+        Ok(hist.into_iter().map(|p| ScoreEntry { date: p.date, score: p.value }).collect())
     }
 
     pub async fn enduro_history(&self, count: Option<usize>) -> anyhow::Result<Vec<ScoreEntry>> {
-        let mut hist = self.load_score_history(&self.enduro_path()).await?;
+        let mut hist = self.score_history(HistoryKind::Enduro).await?;
         hist.reverse();
         if let Some(n) = count { hist.truncate(n); }
         Ok(hist)
     }
 
     pub async fn fitness_history(&self, count: Option<usize>) -> anyhow::Result<Vec<ScoreEntry>> {
-        let mut hist = self.load_score_history(&self.fitness_path()).await?;
+        let mut hist = self.score_history(HistoryKind::Fitness).await?;
         hist.reverse();
         if let Some(n) = count { hist.truncate(n); }
         Ok(hist)
     }
 
     pub async fn current_enduro(&self) -> anyhow::Result<f64> {
-        Ok(self.load_score_history(&self.enduro_path()).await?.last().map(|e| e.score).unwrap_or(0.0))
+        Ok(self.score_history(HistoryKind::Enduro).await?.last().map(|e| e.score).unwrap_or(0.0))
     }
 
     pub async fn current_fitness(&self) -> anyhow::Result<f64> {
-        Ok(self.load_score_history(&self.fitness_path()).await?.last().map(|e| e.score).unwrap_or(0.0))
+        Ok(self.score_history(HistoryKind::Fitness).await?.last().map(|e| e.score).unwrap_or(0.0))
     }
 
     pub async fn update_enduro(&self) -> anyhow::Result<f64> {
         let score = self.compute_enduro_score().await?;
-        self.record_score(&self.enduro_path(), score).await?;
+        self.append(HistoryKind::Enduro, score).await?;
         Ok(score)
     }
 
     pub async fn update_fitness(&self) -> anyhow::Result<f64> {
         let score = self.compute_fitness_score().await?;
-        self.record_score(&self.fitness_path(), score).await?;
+        self.append(HistoryKind::Fitness, score).await?;
         Ok(score)
     }
-
     async fn compute_enduro_score(&self) -> anyhow::Result<f64> {
-        let acts = self.list_activities(None).await?;
         let today = Utc::now().naive_utc().date();
+        let from = (today - chrono::Duration::days(28)).to_string();
+        let acts = self.activity_summaries_between(Some(&from), None).await?;
         let mut long_products = Vec::new();
         let mut week_volume = 0f64;
         let mut tss_sum = 0f64;
         let mut last_long: Option<i64> = None;
-        for a in acts {
-            let dt = chrono::DateTime::parse_from_rfc3339(&a.start_date)?.naive_utc().date();
+        for summary in acts {
+            let dt = chrono::DateTime::parse_from_rfc3339(&summary.start_date)?.naive_utc().date();
             let days = (today - dt).num_days();
             if days <= 28 {
-                let summary = self.load_activity_summary(a.id).await?;
                 if days < 7 {
                     week_volume += summary.duration as f64 / 3600.0;
                 }
                 if let Some(tss) = summary.training_stress_score { tss_sum += tss; }
                 if summary.distance >= 80000.0 {
                     long_products.push(summary.distance * summary.duration as f64);
-                    if last_long.map_or(true, |d| days < d) { last_long = Some(days); }
+                    if last_long.is_none_or(|d| days < d) { last_long = Some(days); }
                 }
             }
         }
@@ -314,17 +1019,17 @@ impl Storage {
 
     async fn compute_fitness_score(&self) -> anyhow::Result<f64> {
         use chrono::Duration;
-        let acts = self.list_activities(None).await?;
         let today = Utc::now().naive_utc().date();
+        let from = (today - Duration::days(28)).to_string();
+        let acts = self.activity_summaries_between(Some(&from), None).await?;
         let mut week_hours = 0f64;
         let mut tss_sum = 0f64;
         let mut long_count = 0u32;
         let mut dates = std::collections::HashSet::new();
-        for a in acts {
-            let dt = chrono::DateTime::parse_from_rfc3339(&a.start_date)?.naive_utc().date();
+        for summary in acts {
+            let dt = chrono::DateTime::parse_from_rfc3339(&summary.start_date)?.naive_utc().date();
             let days = (today - dt).num_days();
             if days <= 28 {
-                let summary = self.load_activity_summary(a.id).await?;
                 if days < 7 { week_hours += summary.duration as f64 / 3600.0; }
                 if let Some(tss) = summary.training_stress_score { tss_sum += tss; }
                 if summary.distance >= 80000.0 { long_count += 1; }
@@ -334,7 +1039,7 @@ impl Storage {
         let four_week_avg = (tss_sum / 4.0) / 10.0;
         let mut score = week_hours * 4.0 + four_week_avg + long_count as f64;
         let mut rest_days = 0i64;
-        for i in 0.. { let day = today - Duration::days(i); if dates.contains(&day) { break } else { rest_days += 1; } }
+        for i in 0..=28 { let day = today - Duration::days(i); if dates.contains(&day) { break } else { rest_days += 1; } }
         if rest_days > 3 { score *= 0.985_f64.powf((rest_days - 3) as f64); }
         Ok(score)
     }
@@ -344,231 +1049,182 @@ impl Storage {
         let year = &date[..4];
         let id = meta["id"].as_u64().unwrap();
         let dir = self.activity_dir(year, id);
-        fs::create_dir_all(&dir).await?;
 
         let mut meta = meta.clone();
-        if let Some(parsed) = crate::schema::parse_streams(streams) {
-            if !parsed.power.is_empty() {
-                let np = weighted_avg_power(&parsed.power);
-                let ftp = self.current_ftp().await.unwrap_or(240.0);
-                let duration = meta
-                    .get("elapsed_time")
-                    .and_then(|v| v.as_i64())
-                    .or_else(|| parsed.time.last().cloned())
-                    .unwrap_or(0) as f64;
-                let ifv = np / ftp;
-                let tss = (duration * np * ifv) / (ftp * 3600.0) * 100.0;
-                if let Some(obj) = meta.as_object_mut() {
-                    obj.insert("normalized_power".into(), serde_json::Value::from(np));
-                    obj.insert("intensity_factor".into(), serde_json::Value::from(ifv));
-                    obj.insert("training_stress_score".into(), serde_json::Value::from(tss));
-                }
+        let ftp = self.ftp_at(date).await?;
+        let parsed = crate::schema::parse_streams(streams);
+        if let Some(m) = parsed.as_ref().and_then(|p| power_metrics(&meta, p, ftp)) {
+            if let Some(obj) = meta.as_object_mut() {
+                obj.insert("normalized_power".into(), serde_json::Value::from(m.normalized_power));
+                obj.insert("intensity_factor".into(), serde_json::Value::from(m.intensity_factor));
+                obj.insert("training_stress_score".into(), serde_json::Value::from(m.training_stress_score));
             }
         }
 
-        self.write_zstd(dir.join("meta.json.zst"), &meta).await?;
-        self.write_zstd(dir.join("streams.json.zst"), streams).await?;
-        Ok(())
+        self.write_zstd(&format!("{}/meta.json.zst", dir), &meta).await?;
+        self.write_zstd(&format!("{}/streams.json.zst", dir), streams).await?;
+        let detail = ActivityDetail { meta, streams: parsed.unwrap_or_default() };
+        self.cache_power_curve(&dir, &detail.streams).await?;
+        let zones = self.zone_bounds(date, ftp).await?;
+        self.index_activity(summarize(id, &detail, ftp, &zones)).await
     }
 
     pub async fn activity_exists(&self, year: &str, id: u64) -> bool {
-        let path = self.activity_dir(year, id).join("meta.json.zst");
-        fs::metadata(path).await.is_ok()
+        let key = format!("{}/meta.json.zst", self.activity_dir(year, id));
+        self.blobs.exists(&key).await.unwrap_or(false)
     }
 
-    async fn write_zstd<P: AsRef<Path>>(&self, path: P, value: &serde_json::Value) -> anyhow::Result<()> {
+    pub(crate) async fn write_zstd(&self, key: &str, value: &serde_json::Value) -> anyhow::Result<()> {
         let data = serde_json::to_vec(value)?;
         let compressed = encode_all(&data[..], 0)?;
-        let mut f = fs::File::create(path).await?;
-        f.write_all(&compressed).await?;
-        Ok(())
+        self.blobs.put(key, compressed).await
     }
 
-    async fn read_zstd<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<serde_json::Value> {
-        let data = fs::read(path).await?;
-        let decompressed = decode_all(&data[..])?;
-        Ok(serde_json::from_slice(&decompressed)?)
+    pub(crate) async fn read_zstd(&self, key: &str) -> anyhow::Result<serde_json::Value> {
+        let data = self.blobs.get(key).await?.ok_or_else(|| anyhow::anyhow!("{} not found", key))?;
+        decode_zstd(&data)
     }
 
     pub async fn list_activities(&self, limit: Option<usize>) -> anyhow::Result<Vec<ActivityHeader>> {
-        let mut list = Vec::new();
-        if let Ok(mut years) = fs::read_dir(&self.base).await {
-            while let Some(year) = years.next_entry().await? {
-                if !year.file_type().await?.is_dir() { continue; }
-                let mut acts = fs::read_dir(year.path()).await?;
-                while let Some(act) = acts.next_entry().await? {
-                    if !act.file_type().await?.is_dir() { continue; }
-                    let meta = self.read_zstd(act.path().join("meta.json.zst")).await?;
-                    if let Ok(header) = serde_json::from_value::<ActivityHeader>(meta.clone()) {
-                        list.push(header);
-                    }
-                }
-            }
-        }
-        list.sort_by(|a, b| b.start_date.cmp(&a.start_date));
+        let mut list: Vec<ActivityHeader> = self
+            .activity_summaries()
+            .await?
+            .into_iter()
+            .map(|s| ActivityHeader { id: s.id, name: s.name, start_date: s.start_date, distance: s.distance })
+            .collect();
         if let Some(n) = limit {
             list.truncate(n);
         }
         Ok(list)
     }
 
-    pub async fn load_activity(&self, id: u64) -> anyhow::Result<ActivityDetail> {
-        let mut years = fs::read_dir(&self.base).await?;
-        while let Some(year) = years.next_entry().await? {
-            let dir = year.path().join(id.to_string());
-            if fs::metadata(&dir).await.is_ok() {
-                let meta = self.read_zstd(dir.join("meta.json.zst")).await?;
-                let raw_streams = self.read_zstd(dir.join("streams.json.zst")).await?;
-                let streams = crate::schema::parse_streams(&raw_streams)
-                    .unwrap_or(ParsedStreams { time: vec![], power: vec![], heartrate: vec![] });
-                return Ok(ActivityDetail { meta, streams });
+    pub(crate) async fn find_activity_dir(&self, id: u64) -> anyhow::Result<Option<String>> {
+        for year in self.year_dirs().await? {
+            let dir = self.activity_dir(&year, id);
+            if self.blobs.exists(&format!("{}/meta.json.zst", dir)).await? {
+                return Ok(Some(dir));
             }
         }
-        anyhow::bail!("not found")
+        Ok(None)
     }
 
-    pub async fn load_activity_summary(&self, id: u64) -> anyhow::Result<crate::schema::ActivitySummary> {
-        let detail = self.load_activity(id).await?;
-        let duration = detail
-            .meta
-            .get("elapsed_time")
-            .and_then(|v| v.as_i64())
-            .or_else(|| detail.streams.time.last().cloned())
-            .unwrap_or(0);
-        let weighted_average_power = detail
-            .meta
-            .get("weighted_average_watts")
-            .and_then(|v| v.as_f64())
-            .or_else(|| detail.meta.get("average_watts").and_then(|v| v.as_f64()))
-            .or_else(|| if !detail.streams.power.is_empty() {
-                Some(weighted_avg_power(&detail.streams.power))
-            } else {
-                None
-            });
-        let distance = detail.meta.get("distance").and_then(|v| v.as_f64()).unwrap_or(0.0);
-        let total_elevation_gain = detail
-            .meta
-            .get("total_elevation_gain")
-            .and_then(|v| v.as_f64());
-        let average_speed = detail
-            .meta
-            .get("average_speed")
-            .and_then(|v| v.as_f64())
-            .or_else(|| if duration > 0 { Some(distance / duration as f64) } else { None })
-            .map(|mps| mps * 3.6);
-        let max_speed = detail
-            .meta
-            .get("max_speed")
-            .and_then(|v| v.as_f64())
-            .map(|mps| mps * 3.6);
-        let pr_count = if let Some(efforts) = detail
-            .meta
-            .get("segment_efforts")
-            .and_then(|v| v.as_array())
-        {
-            Some(
-                efforts
-                    .iter()
-                    .filter(|e| e.get("pr_rank").and_then(|v| v.as_i64()) == Some(1))
-                    .count() as i64,
-            )
-        } else {
-            detail.meta.get("pr_count").and_then(|v| v.as_i64())
-        };
-        let average_heartrate = if !detail.streams.heartrate.is_empty() {
-            Some(detail.streams.heartrate.iter().sum::<i64>() as f64 / detail.streams.heartrate.len() as f64)
-        } else {
-            detail.meta.get("average_heartrate").and_then(|v| v.as_f64())
+    pub async fn load_activity(&self, id: u64) -> anyhow::Result<ActivityDetail> {
+        let (meta, raw_streams) = self.load_raw_activity(id).await?;
+        let streams = crate::schema::parse_streams(&raw_streams).unwrap_or_default();
+        Ok(ActivityDetail { meta, streams })
+    }
+
+    /// Stored metadata and streams exactly as written by [`Storage::save`].
+    pub async fn load_raw_activity(&self, id: u64) -> anyhow::Result<(serde_json::Value, serde_json::Value)> {
+        let Some(dir) = self.find_activity_dir(id).await? else {
+            anyhow::bail!("not found")
         };
-        let normalized_power = detail
-            .meta
-            .get("normalized_power")
-            .and_then(|v| v.as_f64())
-            .or_else(|| if !detail.streams.power.is_empty() {
-                Some(weighted_avg_power(&detail.streams.power))
-            } else { None });
-        let ftp = self.current_ftp().await.unwrap_or(240.0);
-        let intensity_factor = detail
-            .meta
-            .get("intensity_factor")
-            .and_then(|v| v.as_f64())
-            .or_else(|| normalized_power.map(|np| np / ftp));
-        let training_stress_score = detail
-            .meta
-            .get("training_stress_score")
-            .and_then(|v| v.as_f64())
-            .or_else(|| normalized_power.map(|np| (duration as f64 * np * (np / ftp)) / (ftp * 3600.0) * 100.0));
-        let summary_polyline = detail
-            .meta
-            .get("map")
-            .and_then(|m| m.get("summary_polyline"))
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string());
-        let activity_type = detail
-            .meta
-            .get("type")
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string());
-        Ok(crate::schema::ActivitySummary {
-            id: detail.meta.get("id").and_then(|v| v.as_u64()).unwrap_or(id),
-            name: detail
-                .meta
-                .get("name")
-                .and_then(|v| v.as_str())
-                .unwrap_or("")
-                .to_string(),
-            start_date: detail
-                .meta
-                .get("start_date")
-                .and_then(|v| v.as_str())
-                .unwrap_or("")
-                .to_string(),
-            distance: detail.meta.get("distance").and_then(|v| v.as_f64()).unwrap_or(0.0),
-            total_elevation_gain,
-            duration,
-            weighted_average_power,
-            average_speed,
-            max_speed,
-            pr_count,
-            average_heartrate,
-            summary_polyline,
-            normalized_power,
-            intensity_factor,
-            training_stress_score,
-            activity_type,
-            trend: None,
-        })
+        self.read_activity_dir(&dir).await
     }
 
-    pub async fn list_files(&self) -> anyhow::Result<Vec<String>> {
-        let mut files = Vec::new();
-        self.collect_sync(&self.base, "", &mut files)?;
-        Ok(files)
+    /// Replace the metadata of a stored activity, keeping its streams.
+    ///
+    /// Derived metrics are recomputed by [`Storage::save`] and the activity is
+    /// moved if its start year changed. The stored W'bal metrics are kept
+    /// while the start date is unchanged.
+    pub async fn update_meta(&self, meta: &serde_json::Value) -> anyhow::Result<()> {
+        let id = meta["id"].as_u64().ok_or_else(|| anyhow::anyhow!("metadata without id"))?;
+        let (stored, streams) = self.load_raw_activity(id).await?;
+        let mut meta = meta.clone();
+        if stored["start_date"] == meta["start_date"] {
+            if let Some(obj) = meta.as_object_mut() {
+                for field in WBAL_FIELDS {
+                    if let Some(v) = stored.get(field).filter(|_| !obj.contains_key(field)) {
+                        obj.insert(field.into(), v.clone());
+                    }
+                }
+            }
+        }
+        self.replace(&meta, &streams).await
     }
 
-    fn collect_sync(&self, dir: &Path, prefix: &str, out: &mut Vec<String>) -> std::io::Result<()> {
-        for entry in std::fs::read_dir(dir)? {
-            let entry = entry?;
-            let name = if prefix.is_empty() { entry.file_name().to_string_lossy().into() } else { format!("{}/{}", prefix, entry.file_name().to_string_lossy()) };
-            if entry.file_type()?.is_dir() {
-                self.collect_sync(&entry.path(), &name, out)?;
-            } else {
-                out.push(name);
+    /// Save an activity, overwriting any stored copy even if it was filed
+    /// under a different start year.
+    ///
+    /// W'bal metrics stored with the old copy, see [`crate::recompute`], are
+    /// derived again for the new one so the index keeps them.
+    pub async fn replace(&self, meta: &serde_json::Value, streams: &serde_json::Value) -> anyhow::Result<()> {
+        let id = meta["id"].as_u64().ok_or_else(|| anyhow::anyhow!("metadata without id"))?;
+        let old_dir = self.find_activity_dir(id).await?;
+        let mut meta = meta.clone();
+        if let Some(dir) = &old_dir {
+            // an undecodable copy is simply overwritten
+            let stored = match self.blobs.get(&format!("{}/meta.json.zst", dir)).await? {
+                Some(data) => decode_zstd(&data).ok(),
+                None => None,
+            };
+            let had_wbal = stored.is_some_and(|s| WBAL_FIELDS.iter().any(|f| s.get(f).is_some()));
+            if had_wbal && WBAL_FIELDS.iter().all(|f| meta.get(f).is_none()) {
+                let detail = ActivityDetail { meta, streams: crate::schema::parse_streams(streams).unwrap_or_default() };
+                let wbal = self.activity_wbal_metrics(&detail).await?;
+                meta = detail.meta;
+                if let (Some((min, matches)), Some(obj)) = (wbal, meta.as_object_mut()) {
+                    obj.insert("min_wbal".into(), serde_json::Value::from(min));
+                    obj.insert("matches_burned".into(), serde_json::Value::from(matches));
+                }
+            }
+        }
+        self.save(&meta, streams).await?;
+        let date = meta["start_date"].as_str().unwrap_or("1970-01-01");
+        if let Some(old_dir) = old_dir {
+            if self.activity_dir(&date[..4], id) != old_dir {
+                self.blobs.delete_prefix(&old_dir).await?;
             }
         }
         Ok(())
     }
 
+    /// Remove a stored activity, returning whether it existed.
+    pub async fn delete_activity(&self, id: u64) -> anyhow::Result<bool> {
+        match self.find_activity_dir(id).await? {
+            Some(dir) => {
+                self.blobs.delete_prefix(&dir).await?;
+                self.unindex_activity(id).await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    pub async fn load_activity_summary(&self, id: u64) -> anyhow::Result<ActivitySummary> {
+        let detail = self.load_activity(id).await?;
+        let date = detail.meta["start_date"].as_str().unwrap_or_default();
+        let ftp = self.ftp_at(date).await?;
+        let zones = self.zone_bounds(date, ftp).await?;
+        Ok(summarize(id, &detail, ftp, &zones))
+    }
+
+    pub async fn list_files(&self) -> anyhow::Result<Vec<String>> {
+        let mut files = Vec::new();
+        let mut pending = vec![String::new()];
+        while let Some(prefix) = pending.pop() {
+            for entry in self.blobs.children(&prefix).await? {
+                let name = if prefix.is_empty() { entry.name } else { format!("{}/{}", prefix, entry.name) };
+                if entry.is_dir {
+                    pending.push(name);
+                } else {
+                    files.push(name);
+                }
+            }
+        }
+        Ok(files)
+    }
+
     pub async fn recent_trends(&self) -> anyhow::Result<TrendSummary> {
-        let acts = self.list_activities(None).await?;
         let today = Utc::now().naive_utc().date();
+        let from = (today - chrono::Duration::days(180)).to_string();
+        let acts = self.activity_summaries_between(Some(&from), None).await?;
         let mut recent = Vec::new();
         let mut prev = Vec::new();
-        for a in acts {
-            let dt = chrono::DateTime::parse_from_rfc3339(&a.start_date)?.naive_utc().date();
+        for summary in acts {
+            let dt = chrono::DateTime::parse_from_rfc3339(&summary.start_date)?.naive_utc().date();
             let days = (today - dt).num_days();
             if days <= 90 {
-                let summary = self.load_activity_summary(a.id).await?;
                 recent.push((
                     summary.average_speed,
                     summary.max_speed,
@@ -577,7 +1233,6 @@ impl Storage {
                     summary.weighted_average_power,
                 ));
             } else if days <= 180 {
-                let summary = self.load_activity_summary(a.id).await?;
                 prev.push((
                     summary.average_speed,
                     summary.max_speed,
@@ -593,7 +1248,7 @@ impl Storage {
         fn avg(vals: impl Iterator<Item = Option<f64>>) -> f64 {
             let mut sum = 0.0;
             let mut count = 0.0;
-            for v in vals { if let Some(x) = v { sum += x; count += 1.0; } }
+            for x in vals.flatten() { sum += x; count += 1.0; }
             if count > 0.0 { sum / count } else { 0.0 }
         }
 
@@ -628,7 +1283,6 @@ impl Storage {
     }
 
     pub async fn read_file(&self, path: &str) -> anyhow::Result<Vec<u8>> {
-        let full = self.base.join(path);
-        Ok(fs::read(full).await?)
+        self.blobs.get(path).await?.ok_or_else(|| anyhow::anyhow!("{} not found", path))
     }
 }
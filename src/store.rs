@@ -0,0 +1,418 @@
+//! Backends for activity summaries and the FTP, weight, W/kg, score and
+//! heart-rate threshold histories.
+//!
+//! Stream and metadata files always stay zstd blobs in the
+//! [`BlobStore`]; only the small, frequently queried records go through a
+//! [`MetricStore`]. The default [`JsonStore`] keeps the historical JSON
+//! documents next to the activity files, while the optional
+//! `sqlite` feature adds [`SqliteStore`] for indexed range queries and ad-hoc
+//! SQL.
+
+use crate::blob::BlobStore;
+use crate::schema::ActivitySummary;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// History kept by a [`MetricStore`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HistoryKind {
+    Ftp,
+    Weight,
+    Wkg,
+    Enduro,
+    Fitness,
+    Lthr,
+    MaxHr,
+}
+
+impl HistoryKind {
+    pub const ALL: [HistoryKind; 7] = [
+        HistoryKind::Ftp,
+        HistoryKind::Weight,
+        HistoryKind::Wkg,
+        HistoryKind::Enduro,
+        HistoryKind::Fitness,
+        HistoryKind::Lthr,
+        HistoryKind::MaxHr,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HistoryKind::Ftp => "ftp",
+            HistoryKind::Weight => "weight",
+            HistoryKind::Wkg => "wkg",
+            HistoryKind::Enduro => "enduro",
+            HistoryKind::Fitness => "fitness",
+            HistoryKind::Lthr => "lthr",
+            HistoryKind::MaxHr => "max_hr",
+        }
+    }
+
+    /// Name of the value field in the JSON history files.
+    fn json_key(self) -> &'static str {
+        match self {
+            HistoryKind::Enduro | HistoryKind::Fitness => "score",
+            k => k.as_str(),
+        }
+    }
+}
+
+/// One dated value of a history.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryPoint {
+    pub date: String,
+    pub value: f64,
+}
+
+/// Storage of summaries and histories. Histories are returned in the order
+/// they were written.
+#[async_trait]
+pub trait MetricStore: Send + Sync {
+    async fn history(&self, kind: HistoryKind) -> anyhow::Result<Vec<HistoryPoint>>;
+
+    async fn append_history(&self, kind: HistoryKind, point: HistoryPoint) -> anyhow::Result<()>;
+
+    async fn replace_history(&self, kind: HistoryKind, points: &[HistoryPoint]) -> anyhow::Result<()>;
+
+    /// Entries dated within `[from, to)`; either bound may be open.
+    async fn history_between(&self, kind: HistoryKind, from: Option<&str>, to: Option<&str>) -> anyhow::Result<Vec<HistoryPoint>> {
+        Ok(self
+            .history(kind)
+            .await?
+            .into_iter()
+            .filter(|p| in_range(&p.date, from, to))
+            .collect())
+    }
+
+    /// All activity summaries, or `None` when no index was built yet.
+    async fn summaries(&self) -> anyhow::Result<Option<Vec<ActivitySummary>>>;
+
+    /// Summaries starting within `[from, to)`, or `None` when no index was
+    /// built yet.
+    async fn summaries_between(&self, from: Option<&str>, to: Option<&str>) -> anyhow::Result<Option<Vec<ActivitySummary>>> {
+        Ok(self
+            .summaries()
+            .await?
+            .map(|all| all.into_iter().filter(|s| in_range(&s.start_date, from, to)).collect()))
+    }
+
+    async fn replace_summaries(&self, summaries: &[ActivitySummary]) -> anyhow::Result<()>;
+
+    /// Insert or replace one summary. Only called once an index exists.
+    async fn upsert_summary(&self, summary: &ActivitySummary) -> anyhow::Result<()>;
+
+    async fn remove_summary(&self, id: u64) -> anyhow::Result<()>;
+}
+
+fn in_range(date: &str, from: Option<&str>, to: Option<&str>) -> bool {
+    from.is_none_or(|f| date >= f) && to.is_none_or(|t| date < t)
+}
+
+/// Newest first, the order summaries are served in.
+pub(crate) fn sort_summaries(summaries: &mut [ActivitySummary]) {
+    summaries.sort_by(|a, b| b.start_date.cmp(&a.start_date));
+}
+
+/// JSON documents next to the activity files: `ftp.json`, `weight.json`,
+/// `wkg.json`, `enduro.json`, `fitness.json`, `lthr.json`, `max_hr.json` and
+/// `index.json`.
+pub struct JsonStore {
+    blobs: Arc<dyn BlobStore>,
+}
+
+impl JsonStore {
+    pub fn new(blobs: Arc<dyn BlobStore>) -> Self {
+        Self { blobs }
+    }
+
+    fn history_key(kind: HistoryKind) -> String {
+        format!("{}.json", kind.as_str())
+    }
+
+    async fn load_summaries(&self) -> anyhow::Result<Vec<ActivitySummary>> {
+        Ok(self.summaries().await?.unwrap_or_default())
+    }
+}
+
+#[async_trait]
+impl MetricStore for JsonStore {
+    async fn history(&self, kind: HistoryKind) -> anyhow::Result<Vec<HistoryPoint>> {
+        let Some(data) = self.blobs.get(&Self::history_key(kind)).await? else {
+            return Ok(Vec::new());
+        };
+        let entries: Vec<serde_json::Map<String, serde_json::Value>> = serde_json::from_slice(&data)?;
+        entries
+            .into_iter()
+            .map(|e| {
+                let date = e.get("date").and_then(|v| v.as_str());
+                let value = e.get(kind.json_key()).and_then(|v| v.as_f64());
+                match (date, value) {
+                    (Some(date), Some(value)) => Ok(HistoryPoint { date: date.to_string(), value }),
+                    _ => anyhow::bail!("malformed {} history entry", kind.as_str()),
+                }
+            })
+            .collect()
+    }
+
+    async fn append_history(&self, kind: HistoryKind, point: HistoryPoint) -> anyhow::Result<()> {
+        let mut points = self.history(kind).await?;
+        points.push(point);
+        self.replace_history(kind, &points).await
+    }
+
+    async fn replace_history(&self, kind: HistoryKind, points: &[HistoryPoint]) -> anyhow::Result<()> {
+        let entries: Vec<serde_json::Value> = points
+            .iter()
+            .map(|p| serde_json::json!({ "date": p.date, kind.json_key(): p.value }))
+            .collect();
+        self.blobs.put(&Self::history_key(kind), serde_json::to_vec(&entries)?).await
+    }
+
+    async fn summaries(&self) -> anyhow::Result<Option<Vec<ActivitySummary>>> {
+        match self.blobs.get("index.json").await? {
+            Some(data) => match serde_json::from_slice(&data) {
+                Ok(summaries) => Ok(Some(summaries)),
+                // The index only caches the activity files, so rebuild it.
+                Err(e) => {
+                    tracing::warn!("unreadable index.json, rebuilding: {}", e);
+                    Ok(None)
+                }
+            },
+            None => Ok(None),
+        }
+    }
+
+    async fn replace_summaries(&self, summaries: &[ActivitySummary]) -> anyhow::Result<()> {
+        let mut summaries = summaries.to_vec();
+        sort_summaries(&mut summaries);
+        self.blobs.put("index.json", serde_json::to_vec(&summaries)?).await
+    }
+
+    async fn upsert_summary(&self, summary: &ActivitySummary) -> anyhow::Result<()> {
+        let mut summaries = self.load_summaries().await?;
+        summaries.retain(|s| s.id != summary.id);
+        summaries.push(summary.clone());
+        self.replace_summaries(&summaries).await
+    }
+
+    async fn remove_summary(&self, id: u64) -> anyhow::Result<()> {
+        let mut summaries = self.load_summaries().await?;
+        summaries.retain(|s| s.id != id);
+        self.replace_summaries(&summaries).await
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteStore;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::{sort_summaries, HistoryKind, HistoryPoint, MetricStore};
+    use crate::schema::ActivitySummary;
+    use async_trait::async_trait;
+    use rusqlite::{params, Connection, OptionalExtension};
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+
+    const SCHEMA: &str = "
+        CREATE TABLE IF NOT EXISTS history (
+            seq INTEGER PRIMARY KEY AUTOINCREMENT,
+            kind TEXT NOT NULL,
+            date TEXT NOT NULL,
+            value REAL NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS history_kind_date ON history (kind, date);
+        CREATE TABLE IF NOT EXISTS activities (
+            id INTEGER PRIMARY KEY,
+            start_date TEXT NOT NULL,
+            name TEXT NOT NULL,
+            activity_type TEXT,
+            distance REAL NOT NULL,
+            duration INTEGER NOT NULL,
+            total_elevation_gain REAL,
+            average_speed REAL,
+            average_heartrate REAL,
+            weighted_average_power REAL,
+            normalized_power REAL,
+            intensity_factor REAL,
+            training_stress_score REAL,
+            summary TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS activities_start_date ON activities (start_date);
+        CREATE TABLE IF NOT EXISTS settings (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+    ";
+
+    /// SQLite database holding summaries in `activities` and all histories in
+    /// `history`. Summary columns mirror [`ActivitySummary`] for SQL queries;
+    /// the `summary` column keeps the full JSON.
+    #[derive(Clone)]
+    pub struct SqliteStore {
+        conn: Arc<Mutex<Connection>>,
+    }
+
+    impl SqliteStore {
+        /// Open or create the database at `path`.
+        pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+            if let Some(parent) = path.as_ref().parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let conn = Connection::open(path)?;
+            conn.execute_batch(SCHEMA)?;
+            Ok(Self { conn: Arc::new(Mutex::new(conn)) })
+        }
+
+        /// Run `f` on the connection without blocking the async runtime.
+        async fn with_conn<T, F>(&self, f: F) -> anyhow::Result<T>
+        where
+            T: Send + 'static,
+            F: FnOnce(&mut Connection) -> anyhow::Result<T> + Send + 'static,
+        {
+            let conn = self.conn.clone();
+            tokio::task::spawn_blocking(move || f(&mut conn.lock().unwrap())).await?
+        }
+    }
+
+    fn insert_summary(conn: &Connection, s: &ActivitySummary) -> anyhow::Result<()> {
+        conn.execute(
+            "INSERT OR REPLACE INTO activities (id, start_date, name, activity_type, distance, duration, \
+             total_elevation_gain, average_speed, average_heartrate, weighted_average_power, normalized_power, \
+             intensity_factor, training_stress_score, summary) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+            params![
+                s.id as i64,
+                s.start_date,
+                s.name,
+                s.activity_type,
+                s.distance,
+                s.duration,
+                s.total_elevation_gain,
+                s.average_speed,
+                s.average_heartrate,
+                s.weighted_average_power,
+                s.normalized_power,
+                s.intensity_factor,
+                s.training_stress_score,
+                serde_json::to_string(s)?,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn indexed(conn: &Connection) -> anyhow::Result<bool> {
+        Ok(conn
+            .query_row("SELECT value FROM settings WHERE key = 'index_built'", [], |r| r.get::<_, String>(0))
+            .optional()?
+            .is_some())
+    }
+
+    fn history_rows(conn: &Connection, sql: &str, args: &[&dyn rusqlite::ToSql]) -> anyhow::Result<Vec<HistoryPoint>> {
+        let mut stmt = conn.prepare(sql)?;
+        let rows = stmt.query_map(args, |r| Ok(HistoryPoint { date: r.get(0)?, value: r.get(1)? }))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    #[async_trait]
+    impl MetricStore for SqliteStore {
+        async fn history(&self, kind: HistoryKind) -> anyhow::Result<Vec<HistoryPoint>> {
+            self.with_conn(move |c| {
+                history_rows(c, "SELECT date, value FROM history WHERE kind = ?1 ORDER BY seq", &[&kind.as_str()])
+            })
+            .await
+        }
+
+        async fn append_history(&self, kind: HistoryKind, point: HistoryPoint) -> anyhow::Result<()> {
+            self.with_conn(move |c| {
+                c.execute(
+                    "INSERT INTO history (kind, date, value) VALUES (?1, ?2, ?3)",
+                    params![kind.as_str(), point.date, point.value],
+                )?;
+                Ok(())
+            })
+            .await
+        }
+
+        async fn replace_history(&self, kind: HistoryKind, points: &[HistoryPoint]) -> anyhow::Result<()> {
+            let points = points.to_vec();
+            self.with_conn(move |c| {
+                let tx = c.transaction()?;
+                tx.execute("DELETE FROM history WHERE kind = ?1", [kind.as_str()])?;
+                for p in &points {
+                    tx.execute(
+                        "INSERT INTO history (kind, date, value) VALUES (?1, ?2, ?3)",
+                        params![kind.as_str(), p.date, p.value],
+                    )?;
+                }
+                tx.commit()?;
+                Ok(())
+            })
+            .await
+        }
+
+        async fn history_between(&self, kind: HistoryKind, from: Option<&str>, to: Option<&str>) -> anyhow::Result<Vec<HistoryPoint>> {
+            let (from, to) = (from.map(str::to_string), to.map(str::to_string));
+            self.with_conn(move |c| {
+                history_rows(
+                    c,
+                    "SELECT date, value FROM history WHERE kind = ?1 \
+                     AND (?2 IS NULL OR date >= ?2) AND (?3 IS NULL OR date < ?3) ORDER BY seq",
+                    &[&kind.as_str(), &from, &to],
+                )
+            })
+            .await
+        }
+
+        async fn summaries(&self) -> anyhow::Result<Option<Vec<ActivitySummary>>> {
+            self.summaries_between(None, None).await
+        }
+
+        async fn summaries_between(&self, from: Option<&str>, to: Option<&str>) -> anyhow::Result<Option<Vec<ActivitySummary>>> {
+            let (from, to) = (from.map(str::to_string), to.map(str::to_string));
+            self.with_conn(move |c| {
+                if !indexed(c)? {
+                    return Ok(None);
+                }
+                let mut stmt = c.prepare(
+                    "SELECT summary FROM activities \
+                     WHERE (?1 IS NULL OR start_date >= ?1) AND (?2 IS NULL OR start_date < ?2) \
+                     ORDER BY start_date DESC",
+                )?;
+                let rows = stmt.query_map(params![from, to], |r| r.get::<_, String>(0))?;
+                let mut out = Vec::new();
+                for row in rows {
+                    out.push(serde_json::from_str(&row?)?);
+                }
+                Ok(Some(out))
+            })
+            .await
+        }
+
+        async fn replace_summaries(&self, summaries: &[ActivitySummary]) -> anyhow::Result<()> {
+            let mut summaries = summaries.to_vec();
+            sort_summaries(&mut summaries);
+            self.with_conn(move |c| {
+                let tx = c.transaction()?;
+                tx.execute("DELETE FROM activities", [])?;
+                for s in &summaries {
+                    insert_summary(&tx, s)?;
+                }
+                tx.execute("INSERT OR REPLACE INTO settings (key, value) VALUES ('index_built', '1')", [])?;
+                tx.commit()?;
+                Ok(())
+            })
+            .await
+        }
+
+        async fn upsert_summary(&self, summary: &ActivitySummary) -> anyhow::Result<()> {
+            let summary = summary.clone();
+            self.with_conn(move |c| insert_summary(c, &summary)).await
+        }
+
+        async fn remove_summary(&self, id: u64) -> anyhow::Result<()> {
+            self.with_conn(move |c| {
+                c.execute("DELETE FROM activities WHERE id = ?1", [id as i64])?;
+                Ok(())
+            })
+            .await
+        }
+    }
+}
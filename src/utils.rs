@@ -1,4 +1,4 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::fs;
 use anyhow::Context;
 
@@ -16,18 +16,240 @@ pub struct Storage {
     pub user: String,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct Backfill {
+    /// Walk the full activity history in the background on startup
+    #[serde(default)]
+    pub enabled: bool,
+    /// Number of activities requested per page, within [`PER_PAGE`]
+    #[serde(default = "default_per_page", deserialize_with = "per_page")]
+    pub per_page: usize,
+}
+
+impl Default for Backfill {
+    fn default() -> Self {
+        Self { enabled: false, per_page: default_per_page() }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Webhook {
+    /// Token Strava echoes back in the subscription handshake
+    pub verify_token: Option<String>,
+    /// Public URL of `POST /webhook` used when registering a subscription
+    pub callback_url: Option<String>,
+    /// Push subscription whose events are applied, e.g. one created by hand;
+    /// defaults to the id the `subscriptions` binary recorded
+    pub subscription_id: Option<u64>,
+}
+
+/// Backend for activity summaries and histories.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// JSON files next to the activity directories
+    #[default]
+    Json,
+    /// Embedded SQLite database, requires the `sqlite` feature
+    Sqlite,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Database {
+    #[serde(default)]
+    pub backend: Backend,
+    /// Database file, defaults to `<data_dir>/<user>/abcy.sqlite`
+    pub path: Option<String>,
+}
+
+/// S3-compatible bucket holding the activity files instead of `data_dir`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct S3 {
+    /// Endpoint URL, e.g. `https://s3.eu-west-1.amazonaws.com` or `http://minio:9000`
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// Key prefix placed before the user directory
+    pub prefix: Option<String>,
+}
+
+/// Time constants of the Performance Management Chart.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Pmc {
+    /// Chronic training load (fitness) time constant in days
+    #[serde(default = "default_ctl_days")]
+    pub ctl_days: f64,
+    /// Acute training load (fatigue) time constant in days
+    #[serde(default = "default_atl_days")]
+    pub atl_days: f64,
+}
+
+impl Default for Pmc {
+    fn default() -> Self {
+        Self { ctl_days: default_ctl_days(), atl_days: default_atl_days() }
+    }
+}
+
+/// Zone boundaries in percent of a threshold. Each value is the upper bound
+/// of a zone, so `n` bounds make `n + 1` zones. Every list must hold at least
+/// one bound, in strictly ascending order.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Zones {
+    /// Power zones in % of FTP, Coggan's seven zones by default
+    #[serde(default = "default_power_zones", deserialize_with = "zone_bounds")]
+    pub power: Vec<f64>,
+    /// Heart-rate zones in % of LTHR, Friel's seven zones by default
+    #[serde(default = "default_lthr_zones", deserialize_with = "zone_bounds")]
+    pub lthr: Vec<f64>,
+    /// Heart-rate zones in % of max HR, used when no LTHR is recorded
+    #[serde(default = "default_max_hr_zones", deserialize_with = "zone_bounds")]
+    pub max_hr: Vec<f64>,
+}
+
+impl Default for Zones {
+    fn default() -> Self {
+        Self { power: default_power_zones(), lthr: default_lthr_zones(), max_hr: default_max_hr_zones() }
+    }
+}
+
+fn zone_bounds<'de, D: serde::Deserializer<'de>>(de: D) -> Result<Vec<f64>, D::Error> {
+    let bounds = Vec::<f64>::deserialize(de)?;
+    if bounds.is_empty() {
+        return Err(serde::de::Error::custom("zone bounds must not be empty"));
+    }
+    if bounds.iter().any(|b| !b.is_finite()) || bounds.windows(2).any(|w| w[0] >= w[1]) {
+        return Err(serde::de::Error::custom("zone bounds must be finite and in ascending order"));
+    }
+    Ok(bounds)
+}
+
+/// How FTP is estimated from stored rides.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FtpMethod {
+    /// 95% of the best 20-minute power
+    #[default]
+    TwentyMinute,
+    /// Critical power of a two-parameter fit to the 2–20 minute best efforts
+    Cp,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FtpEstimate {
+    #[serde(default)]
+    pub method: FtpMethod,
+    /// Days of rides, up to today, the estimate looks at; clamped to
+    /// `1..=MAX_ESTIMATE_DAYS`
+    #[serde(default = "default_estimate_days", deserialize_with = "estimate_days")]
+    pub days: i64,
+    /// Append the estimate to the FTP history after a sync when it exceeds
+    /// the current FTP by more than `margin`
+    #[serde(default)]
+    pub auto_update: bool,
+    /// Watts above the current FTP required for an automatic update
+    #[serde(default = "default_estimate_margin")]
+    pub margin: f64,
+}
+
+impl Default for FtpEstimate {
+    fn default() -> Self {
+        Self {
+            method: FtpMethod::default(),
+            days: default_estimate_days(),
+            auto_update: false,
+            margin: default_estimate_margin(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     pub strava: Strava,
     pub storage: Storage,
     #[serde(default = "default_base_url")]
     pub base_url: String,
+    #[serde(default)]
+    pub backfill: Backfill,
+    #[serde(default)]
+    pub webhook: Webhook,
+    #[serde(default)]
+    pub database: Database,
+    pub s3: Option<S3>,
+    #[serde(default)]
+    pub pmc: Pmc,
+    #[serde(default)]
+    pub ftp_estimate: FtpEstimate,
+    #[serde(default)]
+    pub zones: Zones,
 }
 
 fn default_base_url() -> String {
     "https://www.strava.com/api/v3".into()
 }
 
+fn default_region() -> String {
+    "us-east-1".into()
+}
+
+fn default_ctl_days() -> f64 {
+    42.0
+}
+
+fn default_atl_days() -> f64 {
+    7.0
+}
+
+fn default_estimate_days() -> i64 {
+    90
+}
+
+/// Longest FTP estimate window, ten years.
+pub const MAX_ESTIMATE_DAYS: i64 = 3650;
+
+fn estimate_days<'de, D: serde::Deserializer<'de>>(de: D) -> Result<i64, D::Error> {
+    Ok(i64::deserialize(de)?.clamp(1, MAX_ESTIMATE_DAYS))
+}
+
+fn default_estimate_margin() -> f64 {
+    5.0
+}
+
+fn default_power_zones() -> Vec<f64> {
+    vec![55.0, 75.0, 90.0, 105.0, 120.0, 150.0]
+}
+
+fn default_lthr_zones() -> Vec<f64> {
+    vec![80.0, 89.0, 93.0, 99.0, 102.0, 106.0]
+}
+
+fn default_max_hr_zones() -> Vec<f64> {
+    vec![60.0, 70.0, 80.0, 90.0]
+}
+
+fn default_per_page() -> usize {
+    200
+}
+
+/// Page sizes the backfill accepts: Strava lists at most 200 activities per
+/// page, and a page must hold more than the one activity the next page
+/// repeats to advance the cursor.
+pub const PER_PAGE: std::ops::RangeInclusive<usize> = 2..=200;
+
+fn per_page<'de, D: serde::Deserializer<'de>>(de: D) -> Result<usize, D::Error> {
+    let per_page = usize::deserialize(de)?;
+    if !PER_PAGE.contains(&per_page) {
+        return Err(serde::de::Error::custom(format!(
+            "per_page must be between {} and {}",
+            PER_PAGE.start(),
+            PER_PAGE.end()
+        )));
+    }
+    Ok(per_page)
+}
+
 impl Config {
     pub fn load(path: &str) -> anyhow::Result<Self> {
         tracing::info!("loading configuration from {}", path);
@@ -38,3 +260,31 @@ impl Config {
         Ok(cfg)
     }
 }
+
+/// Whether `name` is a temporary file left by [`write_atomic`].
+pub fn is_temp_file(name: &str) -> bool {
+    name.starts_with('.') && name.ends_with(".tmp")
+}
+
+/// Write `data` to a temporary file next to `path`, flush it to disk and
+/// rename it into place, so readers see either the old or the new contents
+/// even if the process dies or the disk fills up mid-write.
+pub async fn write_atomic(path: &std::path::Path, data: &[u8]) -> std::io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let name = path
+        .file_name()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let tmp = path.with_file_name(format!(".{}.{:016x}.tmp", name.to_string_lossy(), fastrand::u64(..)));
+    let result = async {
+        let mut file = tokio::fs::File::create(&tmp).await?;
+        file.write_all(data).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&tmp, path).await
+    }
+    .await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&tmp).await;
+    }
+    result
+}
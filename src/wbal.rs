@@ -0,0 +1,129 @@
+//! W' balance: the work capacity above critical power left at each moment of
+//! an activity, after Skiba's differential model. Above CP the balance falls
+//! by `(P - CP)·dt`; below CP it recovers towards W' as
+//! `W' - (W' - W'bal)·e^(-(CP - P)·dt / W')`.
+
+use chrono::{Duration, NaiveDate};
+use serde::Serialize;
+
+use crate::cp::{fit, CpModelKind};
+use crate::power::MAX_HOLD_SECS;
+use crate::schema::{ActivityDetail, ParsedStreams};
+use crate::storage::Storage;
+
+/// W' in joules assumed when the power curve does not support a CP fit.
+pub const DEFAULT_W_PRIME: f64 = 20000.0;
+/// Days of best efforts before an activity its CP and W' are fitted to.
+const MODEL_DAYS: i64 = 90;
+/// A match is burned when W'bal falls this many joules below its last peak;
+/// it ends once W'bal recovers by half as much from the low point.
+const MATCH_JOULES: f64 = 2000.0;
+
+/// W'bal of one activity with the CP and W' it was derived from.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Wbal {
+    pub cp: f64,
+    pub w_prime: f64,
+    /// Lowest W'bal in joules, absent without a power stream
+    pub min_wbal: Option<f64>,
+    pub matches_burned: u32,
+    /// Sample times matching `wbal`
+    pub time: Vec<i64>,
+    /// W'bal in joules at each sample
+    pub wbal: Vec<f64>,
+}
+
+/// W'bal at every sample of `streams`; empty without a power stream.
+/// Recording gaps longer than a few seconds count as recovery at zero watts.
+pub fn wbal_stream(streams: &ParsedStreams, cp: f64, w_prime: f64) -> Vec<f64> {
+    let mut balance = w_prime;
+    let mut out = Vec::with_capacity(streams.power.len());
+    for (i, &p) in streams.power.iter().enumerate() {
+        let dt = match (i.checked_sub(1).and_then(|j| streams.time.get(j)), streams.time.get(i)) {
+            (Some(prev), Some(t)) => (t - prev).max(0),
+            _ => 1,
+        };
+        let p = if dt > MAX_HOLD_SECS { 0.0 } else { p.max(0) as f64 };
+        let dt = dt as f64;
+        if p > cp {
+            balance -= (p - cp) * dt;
+        } else {
+            balance = w_prime - (w_prime - balance) * (-(cp - p) * dt / w_prime).exp();
+        }
+        out.push(balance);
+    }
+    out
+}
+
+/// Number of matches burned in a W'bal stream, see [`MATCH_JOULES`].
+pub fn matches_burned(wbal: &[f64]) -> u32 {
+    let mut matches = 0;
+    let mut peak = f64::MIN;
+    let mut low: Option<f64> = None;
+    for &w in wbal {
+        match low {
+            Some(l) if w - l >= MATCH_JOULES / 2.0 => {
+                low = None;
+                peak = w;
+            }
+            Some(l) => low = Some(l.min(w)),
+            None => {
+                peak = peak.max(w);
+                if peak - w >= MATCH_JOULES {
+                    matches += 1;
+                    low = Some(w);
+                }
+            }
+        }
+    }
+    matches
+}
+
+/// `(min_wbal, matches_burned)` of an activity; `None` without power.
+pub fn wbal_metrics(streams: &ParsedStreams, cp: f64, w_prime: f64) -> Option<(f64, u32)> {
+    let wbal = wbal_stream(streams, cp, w_prime);
+    let min = wbal.iter().copied().reduce(f64::min)?;
+    Some((min, matches_burned(&wbal)))
+}
+
+impl Storage {
+    /// CP and W' of the athlete before `start_date`: a two-parameter fit to
+    /// the best efforts of the preceding 90 days, otherwise the FTP in effect
+    /// and [`DEFAULT_W_PRIME`].
+    pub async fn athlete_cp(&self, start_date: &str) -> anyhow::Result<(f64, f64)> {
+        if let Ok(day) = NaiveDate::parse_from_str(start_date.get(..10).unwrap_or_default(), "%Y-%m-%d") {
+            let efforts = self.power_curve(Some(day - Duration::days(MODEL_DAYS)), day.pred_opt()).await?;
+            let points: Vec<_> = efforts.iter().map(|e| (e.duration, e.watts)).collect();
+            if let Some(model) = fit(CpModelKind::TwoParameter, &points) {
+                return Ok((model.cp, model.w_prime));
+            }
+        }
+        Ok((self.ftp_at(start_date).await?, DEFAULT_W_PRIME))
+    }
+
+    /// `(min_wbal, matches_burned)` of an activity against the athlete's CP
+    /// and W' at the time; `None` without power.
+    pub async fn activity_wbal_metrics(&self, detail: &ActivityDetail) -> anyhow::Result<Option<(f64, u32)>> {
+        if detail.streams.power.is_empty() {
+            return Ok(None);
+        }
+        let (cp, w_prime) = self.athlete_cp(detail.meta["start_date"].as_str().unwrap_or_default()).await?;
+        Ok(wbal_metrics(&detail.streams, cp, w_prime))
+    }
+
+    /// W'bal stream of one activity against the athlete's CP and W' at the
+    /// time, see [`Storage::athlete_cp`].
+    pub async fn activity_wbal(&self, id: u64) -> anyhow::Result<Wbal> {
+        let detail = self.load_activity(id).await?;
+        let (cp, w_prime) = self.athlete_cp(detail.meta["start_date"].as_str().unwrap_or_default()).await?;
+        let wbal = wbal_stream(&detail.streams, cp, w_prime);
+        Ok(Wbal {
+            cp,
+            w_prime,
+            min_wbal: wbal.iter().copied().reduce(f64::min),
+            matches_burned: matches_burned(&wbal),
+            time: detail.streams.time.iter().take(wbal.len()).copied().collect(),
+            wbal,
+        })
+    }
+}
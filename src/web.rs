@@ -1,9 +1,19 @@
-use actix_web::{get, post, web, App, HttpServer, HttpResponse, Responder};
+use actix_multipart::Multipart;
+use actix_web::{delete, get, post, put, web, App, HttpServer, HttpResponse, Responder};
+use futures_util::StreamExt;
 use crate::auth::Auth;
+use crate::cp::CpModelKind;
+use crate::error::StravaError;
+use crate::export::{export, ExportFormat};
 use crate::fetch;
+use crate::import::{decode_file, is_synthetic_id};
+use crate::pmc::{pmc_range, MAX_PMC_DAYS};
+use crate::recompute::{recompute, RecomputeOptions};
+use crate::webhook::{handle_event, verify_challenge, WebhookEvent};
 use crate::storage::Storage;
 use crate::stats::Period;
-use crate::utils::Config;
+use crate::utils::{Config, FtpMethod, MAX_ESTIMATE_DAYS};
+use tracing::error;
 
 #[get("/openapi.json")]
 async fn openapi_spec() -> impl Responder {
@@ -44,6 +54,123 @@ async fn activity_summary(id: web::Path<u64>, storage: web::Data<Storage>) -> im
     }
 }
 
+#[get("/activity/{id}/power-curve")]
+async fn activity_power_curve(id: web::Path<u64>, storage: web::Data<Storage>) -> impl Responder {
+    match storage.activity_power_curve(*id).await {
+        Ok(curve) => HttpResponse::Ok().json(curve),
+        Err(_) => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[get("/activity/{id}/wbal")]
+async fn activity_wbal(id: web::Path<u64>, storage: web::Data<Storage>) -> impl Responder {
+    match storage.activity_wbal(*id).await {
+        Ok(w) => HttpResponse::Ok().json(w),
+        Err(_) => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct ExportParams { format: String }
+
+#[get("/activity/{id}/export")]
+async fn activity_export(id: web::Path<u64>, params: web::Query<ExportParams>, storage: web::Data<Storage>) -> impl Responder {
+    let Some(format) = ExportFormat::parse(&params.format) else {
+        return HttpResponse::BadRequest().body("format must be gpx, tcx or fit");
+    };
+    let detail = match storage.load_activity(*id).await {
+        Ok(d) => d,
+        Err(_) => return HttpResponse::NotFound().finish(),
+    };
+    match export(&detail, format) {
+        Ok(data) => HttpResponse::Ok()
+            .content_type(format.content_type())
+            .insert_header(("Content-Disposition", format!("attachment; filename=\"{}.{}\"", id, format.extension())))
+            .body(data),
+        Err(e) => HttpResponse::UnprocessableEntity().body(format!("{:#}", e)),
+    }
+}
+
+#[post("/activity/{id}/sync")]
+async fn activity_sync(id: web::Path<u64>, auth: web::Data<Auth>, storage: web::Data<Storage>) -> impl Responder {
+    let id = id.into_inner();
+    if is_synthetic_id(id) {
+        return HttpResponse::BadRequest().body("imported activities cannot be synced from Strava");
+    }
+    if let Err(e) = fetch::download_activity(&auth, &storage, id).await {
+        error!(?e, id, "failed to sync activity");
+        return match e.downcast_ref::<StravaError>() {
+            Some(StravaError::NotFound) => HttpResponse::NotFound().finish(),
+            Some(StravaError::RateLimited { .. }) => HttpResponse::TooManyRequests().finish(),
+            Some(StravaError::Unauthorized(_)) => HttpResponse::Unauthorized().finish(),
+            Some(_) => HttpResponse::BadGateway().finish(),
+            None => HttpResponse::InternalServerError().finish(),
+        };
+    }
+    match storage.load_activity_summary(id).await {
+        Ok(s) => HttpResponse::Ok().json(s),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Largest activity file accepted by `POST /import`.
+const MAX_IMPORT_FILE_BYTES: usize = 25 * 1024 * 1024;
+/// Largest total of the files of one `POST /import` request.
+const MAX_IMPORT_REQUEST_BYTES: usize = 100 * 1024 * 1024;
+
+#[post("/import")]
+async fn import_upload(mut payload: Multipart, storage: web::Data<Storage>) -> impl Responder {
+    let mut uploads = Vec::new();
+    let mut total = 0;
+    while let Some(item) = payload.next().await {
+        let Ok(mut field) = item else {
+            return HttpResponse::BadRequest().finish();
+        };
+        let Some(name) = field.content_disposition().and_then(|cd| cd.get_filename()).map(|n| n.to_string()) else {
+            continue;
+        };
+        let mut data = Vec::new();
+        while let Some(chunk) = field.next().await {
+            let Ok(bytes) = chunk else {
+                return HttpResponse::BadRequest().finish();
+            };
+            total += bytes.len();
+            if data.len() + bytes.len() > MAX_IMPORT_FILE_BYTES {
+                return HttpResponse::PayloadTooLarge().body(format!("{}: larger than {} bytes", name, MAX_IMPORT_FILE_BYTES));
+            }
+            if total > MAX_IMPORT_REQUEST_BYTES {
+                return HttpResponse::PayloadTooLarge().body(format!("upload larger than {} bytes", MAX_IMPORT_REQUEST_BYTES));
+            }
+            data.extend_from_slice(&bytes);
+        }
+        uploads.push((name, data));
+    }
+    // decode every file before storing any, so a bad file imports nothing
+    let mut decoded = Vec::new();
+    let mut errors = Vec::new();
+    for (name, data) in &uploads {
+        match decode_file(name, data) {
+            Ok(parts) => decoded.push(parts),
+            Err(e) => {
+                error!(?e, "failed to import {}", name);
+                errors.push(format!("{}: {:#}", name, e));
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return HttpResponse::UnprocessableEntity().body(errors.join("\n"));
+    }
+    let mut imported = Vec::new();
+    for (meta, streams) in &decoded {
+        if let Err(e) = storage.replace(meta, streams).await {
+            error!(?e, "failed to store imported activity");
+            return HttpResponse::InternalServerError().json(serde_json::json!({ "imported": imported, "error": format!("{:#}", e) }));
+        }
+        imported.push(meta["id"].as_u64().unwrap_or_default());
+    }
+    HttpResponse::Ok().json(serde_json::json!({ "imported": imported }))
+}
+
 #[get("/files")]
 async fn files(storage: web::Data<Storage>) -> impl Responder {
     match storage.list_files().await {
@@ -80,16 +207,82 @@ async fn ftp_history(params: web::Query<FtpHistoryParams>, storage: web::Data<St
 }
 
 #[derive(serde::Deserialize)]
-struct FtpUpdate { ftp: f64 }
+struct FtpEstimateParams {
+    method: Option<FtpMethod>,
+    days: Option<i64>,
+}
+
+#[get("/ftp/estimate")]
+async fn ftp_estimate(params: web::Query<FtpEstimateParams>, cfg: web::Data<Config>, storage: web::Data<Storage>) -> impl Responder {
+    let mut estimate = cfg.ftp_estimate.clone();
+    estimate.method = params.method.unwrap_or(estimate.method);
+    estimate.days = params.days.unwrap_or(estimate.days);
+    if !(1..=MAX_ESTIMATE_DAYS).contains(&estimate.days) {
+        return HttpResponse::BadRequest().body(format!("days must be between 1 and {}", MAX_ESTIMATE_DAYS));
+    }
+    match storage.estimate_ftp(&estimate).await {
+        Ok(Some(e)) => HttpResponse::Ok().json(e),
+        Ok(None) => HttpResponse::UnprocessableEntity().body("no efforts to estimate FTP from"),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Normalised `YYYY-MM-DD` form of a history entry date.
+fn parse_date(date: &str) -> Option<String> {
+    chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").ok().map(|d| d.to_string())
+}
+
+/// Optional `YYYY-MM-DD` query parameter.
+fn optional_date(date: &Option<String>) -> Result<Option<chrono::NaiveDate>, chrono::ParseError> {
+    date.as_deref().map(|d| chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d")).transpose()
+}
+
+const INVALID_DATE: &str = "date must be YYYY-MM-DD";
+
+#[derive(serde::Deserialize)]
+struct FtpUpdate { ftp: f64, date: Option<String> }
 
 #[post("/ftp")]
 async fn ftp_post(info: web::Json<FtpUpdate>, storage: web::Data<Storage>) -> impl Responder {
-    match storage.set_ftp(info.ftp).await {
+    let result = match &info.date {
+        Some(date) => match parse_date(date) {
+            Some(date) => storage.add_ftp(&date, info.ftp).await,
+            None => return HttpResponse::BadRequest().body(INVALID_DATE),
+        },
+        None => storage.set_ftp(info.ftp).await,
+    };
+    match result {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct FtpValue { ftp: f64 }
+
+#[put("/ftp/history/{date}")]
+async fn ftp_entry_put(date: web::Path<String>, info: web::Json<FtpValue>, storage: web::Data<Storage>) -> impl Responder {
+    let Some(date) = parse_date(&date) else {
+        return HttpResponse::BadRequest().body(INVALID_DATE);
+    };
+    match storage.update_ftp_entry(&date, info.ftp).await {
         Ok(_) => HttpResponse::Ok().finish(),
         Err(_) => HttpResponse::InternalServerError().finish(),
     }
 }
 
+#[delete("/ftp/history/{date}")]
+async fn ftp_entry_delete(date: web::Path<String>, storage: web::Data<Storage>) -> impl Responder {
+    let Some(date) = parse_date(&date) else {
+        return HttpResponse::BadRequest().body(INVALID_DATE);
+    };
+    match storage.delete_ftp_entry(&date).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
 #[get("/weight")]
 async fn weight_get(storage: web::Data<Storage>) -> impl Responder {
     match storage.current_weight().await {
@@ -110,16 +303,112 @@ async fn weight_history(params: web::Query<WeightHistoryParams>, storage: web::D
 }
 
 #[derive(serde::Deserialize)]
-struct WeightUpdate { weight: f64 }
+struct WeightUpdate { weight: f64, date: Option<String> }
 
 #[post("/weight")]
 async fn weight_post(info: web::Json<WeightUpdate>, storage: web::Data<Storage>) -> impl Responder {
-    match storage.set_weight(info.weight).await {
+    let result = match &info.date {
+        Some(date) => match parse_date(date) {
+            Some(date) => storage.add_weight(&date, info.weight).await,
+            None => return HttpResponse::BadRequest().body(INVALID_DATE),
+        },
+        None => storage.set_weight(info.weight).await,
+    };
+    match result {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct WeightValue { weight: f64 }
+
+#[put("/weight/history/{date}")]
+async fn weight_entry_put(date: web::Path<String>, info: web::Json<WeightValue>, storage: web::Data<Storage>) -> impl Responder {
+    let Some(date) = parse_date(&date) else {
+        return HttpResponse::BadRequest().body(INVALID_DATE);
+    };
+    match storage.update_weight_entry(&date, info.weight).await {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[delete("/weight/history/{date}")]
+async fn weight_entry_delete(date: web::Path<String>, storage: web::Data<Storage>) -> impl Responder {
+    let Some(date) = parse_date(&date) else {
+        return HttpResponse::BadRequest().body(INVALID_DATE);
+    };
+    match storage.delete_weight_entry(&date).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct HrHistoryParams { count: Option<usize> }
+
+/// Requested entry date, today when absent.
+fn entry_date(date: &Option<String>) -> Option<String> {
+    match date {
+        Some(date) => parse_date(date),
+        None => Some(chrono::Utc::now().date_naive().to_string()),
+    }
+}
+
+#[get("/lthr/history")]
+async fn lthr_history(params: web::Query<HrHistoryParams>, storage: web::Data<Storage>) -> impl Responder {
+    match storage.lthr_history(params.count).await {
+        Ok(h) => HttpResponse::Ok().json(h),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct LthrUpdate { lthr: f64, date: Option<String> }
+
+#[post("/lthr")]
+async fn lthr_post(info: web::Json<LthrUpdate>, storage: web::Data<Storage>) -> impl Responder {
+    let Some(date) = entry_date(&info.date) else {
+        return HttpResponse::BadRequest().body(INVALID_DATE);
+    };
+    match storage.add_lthr(&date, info.lthr).await {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[get("/max-hr/history")]
+async fn max_hr_history(params: web::Query<HrHistoryParams>, storage: web::Data<Storage>) -> impl Responder {
+    match storage.max_hr_history(params.count).await {
+        Ok(h) => HttpResponse::Ok().json(h),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct MaxHrUpdate { max_hr: f64, date: Option<String> }
+
+#[post("/max-hr")]
+async fn max_hr_post(info: web::Json<MaxHrUpdate>, storage: web::Data<Storage>) -> impl Responder {
+    let Some(date) = entry_date(&info.date) else {
+        return HttpResponse::BadRequest().body(INVALID_DATE);
+    };
+    match storage.add_max_hr(&date, info.max_hr).await {
         Ok(_) => HttpResponse::Ok().finish(),
         Err(_) => HttpResponse::InternalServerError().finish(),
     }
 }
 
+#[get("/zones")]
+async fn zones_get(storage: web::Data<Storage>) -> impl Responder {
+    match storage.current_zones().await {
+        Ok(z) => HttpResponse::Ok().json(z),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
 #[get("/wkg")]
 async fn wkg_get(storage: web::Data<Storage>) -> impl Responder {
     match storage.current_wkg().await {
@@ -182,6 +471,89 @@ async fn trend_get(storage: web::Data<Storage>) -> impl Responder {
     }
 }
 
+#[derive(serde::Deserialize)]
+struct PmcParams {
+    from: Option<String>,
+    to: Option<String>,
+    ctl_days: Option<f64>,
+    atl_days: Option<f64>,
+}
+
+#[get("/pmc")]
+async fn pmc_get(params: web::Query<PmcParams>, cfg: web::Data<Config>, storage: web::Data<Storage>) -> impl Responder {
+    let (Ok(from), Ok(to)) = (optional_date(&params.from), optional_date(&params.to)) else {
+        return HttpResponse::BadRequest().body(INVALID_DATE);
+    };
+    if let (Some(from), Some(to)) = (from, to) {
+        if from > to {
+            return HttpResponse::BadRequest().body("from must not be after to");
+        }
+    }
+    let (first, last) = pmc_range(from, to);
+    if (last - first).num_days() >= MAX_PMC_DAYS {
+        return HttpResponse::BadRequest().body(format!("range must not exceed {} days", MAX_PMC_DAYS));
+    }
+    let mut pmc = cfg.pmc.clone();
+    pmc.ctl_days = params.ctl_days.unwrap_or(pmc.ctl_days);
+    pmc.atl_days = params.atl_days.unwrap_or(pmc.atl_days);
+    if !(pmc.ctl_days >= 1.0 && pmc.atl_days >= 1.0) {
+        return HttpResponse::BadRequest().body("time constants must be at least one day");
+    }
+    match storage.pmc(from, to, &pmc).await {
+        Ok(series) => HttpResponse::Ok().json(series),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct PowerCurveParams {
+    from: Option<String>,
+    to: Option<String>,
+}
+
+#[get("/power-curve")]
+async fn power_curve_get(params: web::Query<PowerCurveParams>, storage: web::Data<Storage>) -> impl Responder {
+    let (Ok(from), Ok(to)) = (optional_date(&params.from), optional_date(&params.to)) else {
+        return HttpResponse::BadRequest().body(INVALID_DATE);
+    };
+    if let (Some(from), Some(to)) = (from, to) {
+        if from > to {
+            return HttpResponse::BadRequest().body("from must not be after to");
+        }
+    }
+    match storage.power_curve(from, to).await {
+        Ok(curve) => HttpResponse::Ok().json(curve),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct CpModelParams {
+    from: Option<String>,
+    to: Option<String>,
+    model: Option<String>,
+}
+
+#[get("/cp-model")]
+async fn cp_model_get(params: web::Query<CpModelParams>, storage: web::Data<Storage>) -> impl Responder {
+    let (Ok(from), Ok(to)) = (optional_date(&params.from), optional_date(&params.to)) else {
+        return HttpResponse::BadRequest().body(INVALID_DATE);
+    };
+    if let (Some(from), Some(to)) = (from, to) {
+        if from > to {
+            return HttpResponse::BadRequest().body("from must not be after to");
+        }
+    }
+    let Some(model) = CpModelKind::parse(params.model.as_deref().unwrap_or("2p")) else {
+        return HttpResponse::BadRequest().body("model must be 2p, 3p or exp");
+    };
+    match storage.cp_model(from, to, model).await {
+        Ok(Some(m)) => HttpResponse::Ok().json(m),
+        Ok(None) => HttpResponse::UnprocessableEntity().body("not enough efforts to fit the model"),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
 #[derive(serde::Deserialize)]
 struct StatsParams {
     period: String,
@@ -213,52 +585,141 @@ async fn stats_get(params: web::Query<StatsParams>, storage: web::Data<Storage>)
     }
 }
 
+#[get("/sync/failures")]
+async fn sync_failures(storage: web::Data<Storage>) -> impl Responder {
+    match storage.sync_failures().await {
+        Ok(f) => HttpResponse::Ok().json(f),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[get("/quarantine")]
+async fn quarantine(storage: web::Data<Storage>) -> impl Responder {
+    match storage.quarantined().await {
+        Ok(q) => HttpResponse::Ok().json(q),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
 #[derive(serde::Deserialize)]
-struct WebhookEvent {
-    object_type: String,
-    aspect_type: String,
+struct RecomputeParams {
+    from: Option<String>,
+    to: Option<String>,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[post("/admin/recompute")]
+async fn admin_recompute(params: web::Query<RecomputeParams>, storage: web::Data<Storage>) -> impl Responder {
+    let (Ok(from), Ok(to)) = (optional_date(&params.from), optional_date(&params.to)) else {
+        return HttpResponse::BadRequest().body(INVALID_DATE);
+    };
+    let opts = RecomputeOptions { from, to, dry_run: params.dry_run };
+    match recompute(&storage, &opts).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => {
+            error!(?e, "recompute failed");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[get("/ratelimit")]
+async fn ratelimit_get(auth: web::Data<Auth>) -> impl Responder {
+    HttpResponse::Ok().json(auth.rate_limit())
+}
+
+#[derive(serde::Deserialize)]
+struct WebhookChallenge {
+    #[serde(rename = "hub.mode")]
+    mode: String,
+    #[serde(rename = "hub.challenge")]
+    challenge: String,
+    #[serde(rename = "hub.verify_token")]
+    verify_token: String,
+}
+
+#[get("/webhook")]
+async fn webhook_challenge(params: web::Query<WebhookChallenge>, config: web::Data<Config>) -> impl Responder {
+    if verify_challenge(&config, &params.mode, &params.verify_token) {
+        HttpResponse::Ok().json(serde_json::json!({ "hub.challenge": params.challenge }))
+    } else {
+        HttpResponse::Forbidden().finish()
+    }
 }
 
 #[post("/webhook")]
 async fn webhook(event: web::Json<WebhookEvent>, auth: web::Data<Auth>, storage: web::Data<Storage>) -> impl Responder {
-    if event.object_type == "activity" && event.aspect_type == "create" {
-        let auth = auth.clone();
-        let storage = storage.clone();
-        actix_web::rt::spawn(async move {
-            let _ = fetch::download_latest(&auth, &storage, 1).await;
-        });
-    }
+    let auth = auth.clone();
+    let storage = storage.clone();
+    let event = event.into_inner();
+    actix_web::rt::spawn(async move {
+        if let Err(e) = handle_event(&auth, &storage, &event).await {
+            error!(?e, object_id = event.object_id, "failed to handle webhook event");
+        }
+    });
     HttpResponse::Ok()
 }
 
-pub async fn run(_config: Config, auth: Auth, storage: Storage) -> std::io::Result<()> {
+/// Register all routes; the caller provides `Config`, `Auth` and `Storage` as app data.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(activities)
+        .service(activity)
+        .service(activity_summary)
+        .service(activity_power_curve)
+        .service(activity_wbal)
+        .service(activity_sync)
+        .service(activity_export)
+        .service(import_upload)
+        .service(files)
+        .service(raw)
+        .service(ftp_get)
+        .service(ftp_history)
+        .service(ftp_estimate)
+        .service(ftp_post)
+        .service(ftp_entry_put)
+        .service(ftp_entry_delete)
+        .service(weight_get)
+        .service(weight_history)
+        .service(weight_post)
+        .service(weight_entry_put)
+        .service(weight_entry_delete)
+        .service(lthr_history)
+        .service(lthr_post)
+        .service(max_hr_history)
+        .service(max_hr_post)
+        .service(zones_get)
+        .service(wkg_get)
+        .service(wkg_history)
+        .service(enduro_get)
+        .service(enduro_history)
+        .service(fitness_get)
+        .service(fitness_history)
+        .service(trend_get)
+        .service(openapi_spec)
+        .service(stats_get)
+        .service(pmc_get)
+        .service(power_curve_get)
+        .service(cp_model_get)
+        .service(webhook)
+        .service(webhook_challenge)
+        .service(ratelimit_get)
+        .service(sync_failures)
+        .service(quarantine)
+        .service(admin_recompute);
+}
+
+pub async fn run(config: Config, auth: Auth, storage: Storage) -> std::io::Result<()> {
+    let data_config = web::Data::new(config);
     let data_auth = web::Data::new(auth);
     let data_storage = web::Data::new(storage);
     HttpServer::new(move || {
         App::new()
+            .app_data(data_config.clone())
             .app_data(data_auth.clone())
             .app_data(data_storage.clone())
-            .service(activities)
-            .service(activity)
-            .service(activity_summary)
-            .service(files)
-            .service(raw)
-            .service(ftp_get)
-            .service(ftp_history)
-            .service(ftp_post)
-            .service(weight_get)
-            .service(weight_history)
-            .service(weight_post)
-            .service(wkg_get)
-            .service(wkg_history)
-            .service(enduro_get)
-            .service(enduro_history)
-            .service(fitness_get)
-            .service(fitness_history)
-            .service(trend_get)
-            .service(openapi_spec)
-            .service(stats_get)
-            .service(webhook)
+            .configure(configure)
     })
     .bind(("0.0.0.0", 8080))?
     .run()
@@ -0,0 +1,181 @@
+use crate::auth::Auth;
+use crate::fetch;
+use crate::import::is_synthetic_id;
+use crate::storage::Storage;
+use crate::utils::Config;
+use anyhow::Context;
+use std::collections::HashMap;
+use tracing::{info, warn};
+
+/// Push event delivered by Strava to `POST /webhook`.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct WebhookEvent {
+    /// `activity` or `athlete`
+    pub object_type: String,
+    /// `create`, `update` or `delete`
+    pub aspect_type: String,
+    /// Activity id for activity events, athlete id for athlete events
+    pub object_id: u64,
+    /// Athlete the event belongs to
+    pub owner_id: u64,
+    /// Push subscription that delivered the event
+    pub subscription_id: u64,
+    /// Changed fields for `update` events, e.g. `title`, `type`, `private`
+    /// or `authorized` for athlete deauthorization
+    #[serde(default)]
+    pub updates: HashMap<String, serde_json::Value>,
+}
+
+impl WebhookEvent {
+    fn is_deauthorization(&self) -> bool {
+        self.object_type == "athlete"
+            && self
+                .updates
+                .get("authorized")
+                .is_some_and(|v| v.as_str() == Some("false") || v.as_bool() == Some(false))
+    }
+}
+
+/// Whether `event` belongs to the athlete who authorized the stored token
+/// and, when one is known, was delivered by the registered subscription:
+/// `webhook.subscription_id` or else the one the `subscriptions` binary
+/// recorded. `POST /webhook` is public, so anything else is dropped. No
+/// request is sent, so a revoked token cannot hold up the check.
+async fn is_own_event(auth: &Auth, storage: &Storage, event: &WebhookEvent) -> anyhow::Result<bool> {
+    let registered = match auth.cfg.webhook.subscription_id {
+        Some(id) => Some(id),
+        None => storage.subscription_id().await?,
+    };
+    if registered.is_some_and(|id| id != event.subscription_id) {
+        warn!(subscription_id = event.subscription_id, ?registered, "dropping webhook event of an unknown subscription");
+        return Ok(false);
+    }
+    match auth.stored_athlete_id().await {
+        Some(athlete_id) if athlete_id != event.owner_id => {
+            warn!(owner_id = event.owner_id, athlete_id, "dropping webhook event of another athlete");
+            Ok(false)
+        }
+        Some(_) => Ok(true),
+        // a token stored before the athlete id was recorded; the startup sync
+        // looks it up, until then the subscription has to vouch for the event
+        None if registered.is_some() => Ok(true),
+        None => {
+            warn!(owner_id = event.owner_id, "athlete of the stored token unknown, dropping webhook event");
+            Ok(false)
+        }
+    }
+}
+
+/// Apply a webhook event to the local mirror. Events of other subscriptions
+/// or athletes are ignored, as are activity events for imported files. A
+/// deauthorization is handled before anything that talks to Strava.
+pub async fn handle_event(auth: &Auth, storage: &Storage, event: &WebhookEvent) -> anyhow::Result<()> {
+    info!(?event, "webhook event");
+    if !is_own_event(auth, storage, event).await? {
+        return Ok(());
+    }
+    if event.is_deauthorization() {
+        warn!(owner_id = event.owner_id, "athlete revoked access, wiping cached token");
+        return auth.clear_token().await;
+    }
+    if event.object_type != "activity" {
+        return Ok(());
+    }
+    let id = event.object_id;
+    if is_synthetic_id(id) {
+        warn!(id, "ignoring webhook event for an imported activity");
+        return Ok(());
+    }
+    match event.aspect_type.as_str() {
+        "create" => fetch::download_activity(auth, storage, id).await,
+        "update" => fetch::refresh_meta(auth, storage, id).await,
+        "delete" => {
+            if !storage.delete_activity(id).await? {
+                info!(id, "deleted activity was not stored");
+            }
+            Ok(())
+        }
+        other => {
+            warn!(aspect_type = other, "unknown webhook aspect type");
+            Ok(())
+        }
+    }
+}
+
+/// Push subscription registered with Strava.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Subscription {
+    pub id: u64,
+    pub callback_url: String,
+    #[serde(default)]
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub updated_at: Option<String>,
+}
+
+/// Whether a `GET /webhook` handshake carries the configured verify token.
+pub fn verify_challenge(cfg: &Config, mode: &str, verify_token: &str) -> bool {
+    mode == "subscribe" && cfg.webhook.verify_token.as_deref() == Some(verify_token)
+}
+
+fn subscriptions_url(cfg: &Config) -> String {
+    format!("{}/push_subscriptions", cfg.base_url)
+}
+
+async fn check(resp: reqwest::Response) -> anyhow::Result<reqwest::Response> {
+    let status = resp.status();
+    if !status.is_success() {
+        let body = resp.text().await.unwrap_or_default();
+        anyhow::bail!("subscription request failed with status {}: {}", status, body);
+    }
+    Ok(resp)
+}
+
+/// Register `callback_url` for push events. Strava immediately validates it
+/// with a `GET` handshake, so the server must already be reachable.
+pub async fn create_subscription(client: &reqwest::Client, cfg: &Config, callback_url: &str) -> anyhow::Result<u64> {
+    let verify_token = cfg
+        .webhook
+        .verify_token
+        .as_deref()
+        .context("webhook.verify_token is not configured")?;
+    let resp = client
+        .post(subscriptions_url(cfg))
+        .form(&[
+            ("client_id", cfg.strava.client_id.as_str()),
+            ("client_secret", cfg.strava.client_secret.as_str()),
+            ("callback_url", callback_url),
+            ("verify_token", verify_token),
+        ])
+        .send()
+        .await?;
+    #[derive(serde::Deserialize)]
+    struct Created { id: u64 }
+    let created: Created = check(resp).await?.json().await?;
+    Ok(created.id)
+}
+
+pub async fn list_subscriptions(client: &reqwest::Client, cfg: &Config) -> anyhow::Result<Vec<Subscription>> {
+    let resp = client
+        .get(subscriptions_url(cfg))
+        .query(&[
+            ("client_id", cfg.strava.client_id.as_str()),
+            ("client_secret", cfg.strava.client_secret.as_str()),
+        ])
+        .send()
+        .await?;
+    Ok(check(resp).await?.json().await?)
+}
+
+pub async fn delete_subscription(client: &reqwest::Client, cfg: &Config, id: u64) -> anyhow::Result<()> {
+    let resp = client
+        .delete(format!("{}/{}", subscriptions_url(cfg), id))
+        .query(&[
+            ("client_id", cfg.strava.client_id.as_str()),
+            ("client_secret", cfg.strava.client_secret.as_str()),
+        ])
+        .send()
+        .await?;
+    check(resp).await?;
+    Ok(())
+}
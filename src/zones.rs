@@ -0,0 +1,84 @@
+//! Power and heart-rate training zones and the time an activity spends in
+//! each. Zone boundaries are configured in percent of FTP, LTHR or max HR,
+//! see [`Zones`], and resolved against the thresholds in effect on the
+//! activity's start date.
+
+use chrono::Utc;
+use serde::Serialize;
+
+use crate::power::MAX_HOLD_SECS;
+use crate::storage::Storage;
+use crate::utils::Zones;
+
+/// Upper zone bounds in watts and bpm for one day.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ZoneBounds {
+    pub power: Vec<f64>,
+    /// Absent while neither LTHR nor max HR is recorded
+    pub heartrate: Option<Vec<f64>>,
+}
+
+impl ZoneBounds {
+    /// Bounds from the zone configuration and thresholds. Heart-rate zones
+    /// use LTHR when known, otherwise max HR.
+    pub fn new(cfg: &Zones, ftp: f64, lthr: Option<f64>, max_hr: Option<f64>) -> Self {
+        let scale = |pcts: &[f64], threshold: f64| pcts.iter().map(|p| p * threshold / 100.0).collect::<Vec<_>>();
+        let heartrate = match (lthr, max_hr) {
+            (Some(lthr), _) => Some(scale(&cfg.lthr, lthr)),
+            (None, Some(max)) => Some(scale(&cfg.max_hr, max)),
+            (None, None) => None,
+        };
+        Self { power: scale(&cfg.power, ftp), heartrate }
+    }
+}
+
+/// Zone of `value`: the first zone whose upper bound it does not exceed.
+pub fn zone_of(value: f64, bounds: &[f64]) -> usize {
+    bounds.iter().take_while(|b| value > **b).count()
+}
+
+/// Seconds spent in each of the `bounds.len() + 1` zones, `None` without
+/// samples. A sample counts for the time since the previous one; recording
+/// gaps longer than a few seconds count as one second.
+pub fn time_in_zones(time: &[i64], values: &[i64], bounds: &[f64]) -> Option<Vec<i64>> {
+    if values.is_empty() {
+        return None;
+    }
+    let mut seconds = vec![0; bounds.len() + 1];
+    for (i, &v) in values.iter().enumerate() {
+        let dt = match (i.checked_sub(1).and_then(|j| time.get(j)), time.get(i)) {
+            (Some(prev), Some(t)) if (1..=MAX_HOLD_SECS).contains(&(t - prev)) => t - prev,
+            _ => 1,
+        };
+        seconds[zone_of(v as f64, bounds)] += dt;
+    }
+    Some(seconds)
+}
+
+/// Zone bounds of today, as served by `GET /zones`.
+#[derive(Debug, Clone, Serialize)]
+pub struct CurrentZones {
+    pub date: String,
+    pub ftp: f64,
+    pub lthr: Option<f64>,
+    pub max_hr: Option<f64>,
+    #[serde(flatten)]
+    pub bounds: ZoneBounds,
+}
+
+impl Storage {
+    /// Zone bounds for an activity starting at `date` given the FTP in
+    /// effect then.
+    pub async fn zone_bounds(&self, date: &str, ftp: f64) -> anyhow::Result<ZoneBounds> {
+        let (lthr, max_hr) = (self.lthr_at(date).await?, self.max_hr_at(date).await?);
+        Ok(ZoneBounds::new(self.zones(), ftp, lthr, max_hr))
+    }
+
+    pub async fn current_zones(&self) -> anyhow::Result<CurrentZones> {
+        let date = Utc::now().date_naive().to_string();
+        let ftp = self.ftp_at(&date).await?;
+        let (lthr, max_hr) = (self.lthr_at(&date).await?, self.max_hr_at(&date).await?);
+        let bounds = ZoneBounds::new(self.zones(), ftp, lthr, max_hr);
+        Ok(CurrentZones { date, ftp, lthr, max_hr, bounds })
+    }
+}
@@ -0,0 +1,58 @@
+use abcy_data::{stats::Period, storage::Storage, utils::Storage as StorageCfg};
+use serde_json::json;
+use tempfile::tempdir;
+
+fn make_storage(dir: &std::path::Path) -> Storage {
+    let cfg = StorageCfg { data_dir: dir.to_str().unwrap().into(), download_count: 1, user: "t".into() };
+    Storage::new(&cfg)
+}
+
+#[tokio::test]
+async fn index_follows_saves_and_deletes() {
+    let dir = tempdir().unwrap();
+    let storage = make_storage(dir.path());
+    let streams = json!({"time": [0, 3600], "watts": [200, 200]});
+    storage.save(&json!({"id": 1, "name": "a", "start_date": "2024-01-01T08:00:00Z", "distance": 10.0, "type": "Ride"}), &streams).await.unwrap();
+    storage.save(&json!({"id": 2, "name": "b", "start_date": "2024-02-01T08:00:00Z", "distance": 20.0, "type": "Ride"}), &streams).await.unwrap();
+
+    let index_path = dir.path().join("t").join("index.json");
+    let index: Vec<serde_json::Value> = serde_json::from_slice(&std::fs::read(&index_path).unwrap()).unwrap();
+    assert_eq!(index.len(), 2);
+    assert_eq!(index[0]["id"], 2);
+    assert!(index[0]["training_stress_score"].as_f64().unwrap() > 0.0);
+
+    // moving an activity to another year keeps one entry
+    storage.replace(&json!({"id": 1, "name": "a2", "start_date": "2023-12-31T08:00:00Z", "distance": 10.0, "type": "Ride"}), &streams).await.unwrap();
+    let list = storage.list_activities(None).await.unwrap();
+    assert_eq!(list.iter().map(|a| a.id).collect::<Vec<_>>(), vec![2, 1]);
+    assert_eq!(list[1].name, "a2");
+
+    assert!(storage.delete_activity(2).await.unwrap());
+    let summaries = storage.activity_summaries().await.unwrap();
+    assert_eq!(summaries.len(), 1);
+    assert_eq!(summaries[0].id, 1);
+    let stats = storage.activity_stats(Period::Year, None, None).await.unwrap();
+    assert_eq!(stats.len(), 1);
+    assert_eq!(stats[0].period, "2023");
+}
+
+#[tokio::test]
+async fn missing_index_is_rebuilt() {
+    let dir = tempdir().unwrap();
+    let storage = make_storage(dir.path());
+    let streams = json!({"time": [0, 10], "watts": [100, 200]});
+    for id in 1..=3 {
+        let meta = json!({"id": id, "name": format!("r{}", id), "start_date": format!("2024-03-0{}T08:00:00Z", id), "distance": 5.0});
+        storage.save(&meta, &streams).await.unwrap();
+    }
+    let index_path = dir.path().join("t").join("index.json");
+    std::fs::remove_file(&index_path).unwrap();
+
+    assert_eq!(storage.list_activities(Some(2)).await.unwrap().iter().map(|a| a.id).collect::<Vec<_>>(), vec![3, 2]);
+    assert!(index_path.exists());
+
+    std::fs::write(&index_path, "[]").unwrap();
+    assert!(storage.list_activities(None).await.unwrap().is_empty());
+    assert_eq!(storage.rebuild_index().await.unwrap(), 3);
+    assert_eq!(storage.list_activities(None).await.unwrap().len(), 3);
+}
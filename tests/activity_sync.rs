@@ -0,0 +1,61 @@
+use abcy_data::{auth::Auth, storage::Storage, utils::Config, web};
+use actix_web::{test, App};
+use mockito::Matcher;
+use serde_json::json;
+use tempfile::{tempdir, TempDir};
+
+mod common;
+
+fn make_env(base_url: &str) -> (TempDir, Config, Auth, Storage) {
+    let dir = tempdir().unwrap();
+    common::write_valid_token(dir.path());
+    let cfg = common::test_config(base_url, dir.path());
+    let storage = Storage::new(&cfg.storage);
+    let auth = Auth::new(cfg.clone());
+    (dir, cfg, auth, storage)
+}
+
+#[actix_rt::test]
+async fn sync_overwrites_stored_activity() {
+    let mut server = mockito::Server::new_async().await;
+    server
+        .mock("GET", "/activities/5")
+        .with_body(json!({"id": 5, "name": "cropped", "start_date": "2024-01-01T08:00:00Z", "distance": 900.0, "elapsed_time": 2}).to_string())
+        .create_async()
+        .await;
+    server
+        .mock("GET", "/activities/5/streams")
+        .match_query(Matcher::Any)
+        .with_body(json!({"time": {"data": [0, 1, 2]}, "watts": {"data": [300, 300, 300]}}).to_string())
+        .create_async()
+        .await;
+    server.mock("GET", "/activities/6").with_status(404).create_async().await;
+
+    let (_dir, cfg, auth, storage) = make_env(&server.url());
+    // stored copy was filed under the wrong year before the edit on Strava
+    let meta = json!({"id": 5, "name": "original", "start_date": "2023-12-31T23:00:00Z", "distance": 1000.0, "elapsed_time": 4});
+    let streams = json!({"time": {"data": [0, 1, 2, 3, 4]}, "watts": {"data": [100, 100, 100, 100, 100]}});
+    storage.save(&meta, &streams).await.unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(actix_web::web::Data::new(cfg))
+            .app_data(actix_web::web::Data::new(auth))
+            .app_data(actix_web::web::Data::new(storage.clone()))
+            .configure(web::configure),
+    )
+    .await;
+    let req = test::TestRequest::post().uri("/activity/5/sync").to_request();
+    let summary: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(summary["name"], "cropped");
+    assert!((summary["normalized_power"].as_f64().unwrap() - 300.0).abs() < 1e-6);
+
+    assert!(!storage.activity_exists("2023", 5).await);
+    assert!(storage.activity_exists("2024", 5).await);
+    let act = storage.load_activity(5).await.unwrap();
+    assert_eq!(act.streams.power, vec![300, 300, 300]);
+
+    let req = test::TestRequest::post().uri("/activity/6/sync").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 404);
+}
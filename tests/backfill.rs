@@ -0,0 +1,175 @@
+use abcy_data::{auth::Auth, fetch, storage::Storage, utils::Backfill};
+use mockito::Matcher;
+use serde_json::json;
+use tempfile::{tempdir, TempDir};
+
+mod common;
+
+fn make_env(base_url: &str) -> (TempDir, Auth, Storage) {
+    let dir = tempdir().unwrap();
+    common::write_valid_token(dir.path());
+    let cfg = common::test_config(base_url, dir.path());
+    let storage = Storage::new(&cfg.storage);
+    (dir, Auth::new(cfg), storage)
+}
+
+#[tokio::test]
+async fn backfill_walks_pages_and_resumes() {
+    let mut server = mockito::Server::new_async().await;
+    let page1 = server
+        .mock("GET", "/athlete/activities")
+        .match_query(Matcher::Regex("^per_page=2$".into()))
+        .expect(1)
+        .with_body(json!([
+            {"id": 2, "name": "b", "start_date": "2024-01-02T00:00:00Z", "distance": 1.0},
+            {"id": 1, "name": "a", "start_date": "2024-01-01T00:00:00Z", "distance": 1.0}
+        ]).to_string())
+        .create_async()
+        .await;
+    let page2 = server
+        .mock("GET", "/athlete/activities")
+        .match_query(Matcher::Regex("before=1704067201$".into()))
+        .with_body(json!([
+            {"id": 1, "name": "a", "start_date": "2024-01-01T00:00:00Z", "distance": 1.0},
+            {"id": 3, "name": "c", "start_date": "2023-12-31T00:00:00Z", "distance": 1.0}
+        ]).to_string())
+        .create_async()
+        .await;
+    let page3 = server
+        .mock("GET", "/athlete/activities")
+        .match_query(Matcher::Regex("before=1703980801$".into()))
+        .with_body(json!([
+            {"id": 3, "name": "c", "start_date": "2023-12-31T00:00:00Z", "distance": 1.0}
+        ]).to_string())
+        .create_async()
+        .await;
+    for (id, date) in [(1, "2024-01-01T00:00:00Z"), (2, "2024-01-02T00:00:00Z"), (3, "2023-12-31T00:00:00Z")] {
+        server
+            .mock("GET", format!("/activities/{}", id).as_str())
+            .with_body(json!({"id": id, "name": "ride", "start_date": date, "distance": 1.0}).to_string())
+            .create_async()
+            .await;
+        server
+            .mock("GET", format!("/activities/{}/streams", id).as_str())
+            .match_query(Matcher::Any)
+            .with_body(json!({"time": {"data": [0, 1, 2]}}).to_string())
+            .create_async()
+            .await;
+    }
+
+    let (_dir, auth, storage) = make_env(&server.url());
+    fetch::backfill(&auth, &storage, 2).await.unwrap();
+    assert!(storage.activity_exists("2024", 1).await);
+    assert!(storage.activity_exists("2024", 2).await);
+    assert!(storage.activity_exists("2023", 3).await);
+
+    let state = storage.backfill_state().await.unwrap();
+    assert!(state.complete);
+    assert_eq!(state.pages, 2);
+    assert_eq!(state.downloaded, 3);
+
+    // a completed backfill does not list again
+    fetch::backfill(&auth, &storage, 2).await.unwrap();
+    page1.assert_async().await;
+    page2.assert_async().await;
+    page3.assert_async().await;
+}
+
+#[tokio::test]
+async fn backfill_resumes_from_cursor() {
+    let mut server = mockito::Server::new_async().await;
+    let first = server
+        .mock("GET", "/athlete/activities")
+        .match_query(Matcher::Regex("^per_page=2$".into()))
+        .expect(0)
+        .create_async()
+        .await;
+    let resumed = server
+        .mock("GET", "/athlete/activities")
+        .match_query(Matcher::Regex("before=1704067200$".into()))
+        .with_body("[]")
+        .create_async()
+        .await;
+
+    let (_dir, auth, storage) = make_env(&server.url());
+    let mut state = storage.backfill_state().await.unwrap();
+    state.before = Some(1704067200);
+    state.pages = 1;
+    storage.save_backfill_state(&state).await.unwrap();
+
+    fetch::backfill(&auth, &storage, 2).await.unwrap();
+    first.assert_async().await;
+    resumed.assert_async().await;
+    assert!(storage.backfill_state().await.unwrap().complete);
+}
+
+#[tokio::test]
+async fn backfill_keeps_activities_sharing_the_oldest_second() {
+    let ride = |id: u64, date: &str| json!({"id": id, "name": "ride", "start_date": date, "distance": 1.0});
+    let mut server = mockito::Server::new_async().await;
+    server
+        .mock("GET", "/athlete/activities")
+        .match_query(Matcher::Regex("^per_page=3$".into()))
+        .with_body(json!([ride(4, "2024-01-03T00:00:00Z"), ride(3, "2024-01-02T00:00:00Z"), ride(2, "2024-01-01T00:00:00Z")]).to_string())
+        .create_async()
+        .await;
+    // ride 1 starts in the same second as ride 2 but did not fit on the first page
+    let spill = server
+        .mock("GET", "/athlete/activities")
+        .match_query(Matcher::Regex("before=1704067201$".into()))
+        .with_body(json!([ride(2, "2024-01-01T00:00:00Z"), ride(1, "2024-01-01T00:00:00Z")]).to_string())
+        .expect(2)
+        .create_async()
+        .await;
+    for id in 1..=4 {
+        server
+            .mock("GET", format!("/activities/{}", id).as_str())
+            .with_body(ride(id, &format!("2024-01-0{}T00:00:00Z", id.max(2) - 1)).to_string())
+            .expect(1)
+            .create_async()
+            .await;
+        server
+            .mock("GET", format!("/activities/{}/streams", id).as_str())
+            .match_query(Matcher::Any)
+            .with_body(json!({"time": {"data": [0, 1]}}).to_string())
+            .create_async()
+            .await;
+    }
+
+    let (_dir, auth, storage) = make_env(&server.url());
+    fetch::backfill(&auth, &storage, 3).await.unwrap();
+    assert!(storage.activity_exists("2024", 1).await);
+    let state = storage.backfill_state().await.unwrap();
+    assert!(state.complete);
+    assert_eq!((state.pages, state.downloaded), (2, 4));
+    spill.assert_async().await;
+
+    // a full page of activities listed before cannot be paged past
+    let mut server = mockito::Server::new_async().await;
+    server
+        .mock("GET", "/athlete/activities")
+        .match_query(Matcher::Any)
+        .with_body(json!([ride(2, "2024-01-01T00:00:00Z"), ride(1, "2024-01-01T00:00:00Z")]).to_string())
+        .create_async()
+        .await;
+    server.mock("GET", Matcher::Regex("^/activities/".into())).with_status(404).create_async().await;
+    let (_dir, auth, storage) = make_env(&server.url());
+    let err = fetch::backfill(&auth, &storage, 2).await.unwrap_err();
+    assert!(err.to_string().contains("did not advance"));
+    assert!(!storage.backfill_state().await.unwrap().complete);
+}
+
+#[tokio::test]
+async fn page_size_must_advance_the_cursor() {
+    for per_page in [0, 1, 201] {
+        let err = toml::from_str::<Backfill>(&format!("per_page = {}", per_page)).unwrap_err();
+        assert!(err.to_string().contains("per_page must be between 2 and 200"));
+    }
+    assert_eq!(toml::from_str::<Backfill>("per_page = 2").unwrap().per_page, 2);
+    assert_eq!(Backfill::default().per_page, 200);
+
+    // rejected before any request is sent
+    let (_dir, auth, storage) = make_env("http://127.0.0.1:9");
+    assert!(fetch::backfill(&auth, &storage, 1).await.is_err());
+    assert_eq!(storage.backfill_state().await.unwrap().pages, 0);
+}
@@ -0,0 +1,35 @@
+//! Fixtures shared by the integration tests.
+#![allow(dead_code)]
+
+use abcy_data::utils::Config;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+
+/// Config for a Strava API at `base_url` keeping the token and the data of
+/// user `t` below `dir`. `extra` is appended as is, e.g. a `[pmc]` section.
+pub fn test_config_with(base_url: &str, dir: &Path, extra: &str) -> Config {
+    let text = format!(
+        "base_url = \"{}\"\n[strava]\nclient_id = \"1\"\nclient_secret = \"s\"\ntoken_path = \"{}\"\n[storage]\ndata_dir = \"{}\"\ndownload_count = 1\nuser = \"t\"\n{}",
+        base_url,
+        dir.join("token.json").display(),
+        dir.display(),
+        extra
+    );
+    toml::from_str(&text).unwrap()
+}
+
+pub fn test_config(base_url: &str, dir: &Path) -> Config {
+    test_config_with(base_url, dir, "")
+}
+
+/// Store `token` where [`test_config`] expects the cached Strava token.
+pub fn write_token(dir: &Path, token: &serde_json::Value) -> PathBuf {
+    let path = dir.join("token.json");
+    std::fs::write(&path, token.to_string()).unwrap();
+    path
+}
+
+/// Store an access token that stays valid for the next hour.
+pub fn write_valid_token(dir: &Path) -> PathBuf {
+    write_token(dir, &json!({"access_token": "abc", "expires_at": chrono::Utc::now().timestamp() + 3600}))
+}
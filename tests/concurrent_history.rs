@@ -0,0 +1,76 @@
+use abcy_data::{storage::{FailureKind, Storage}, utils::Storage as StorageCfg};
+use actix_web::{test, App};
+use futures_util::future::join_all;
+use serde_json::json;
+use tempfile::tempdir;
+
+fn make_storage() -> Storage {
+    let dir = tempdir().unwrap();
+    let cfg = StorageCfg { data_dir: dir.path().to_str().unwrap().into(), download_count: 1, user: "t".into() };
+    Storage::new(&cfg)
+}
+
+#[actix_rt::test]
+async fn parallel_posts_keep_every_entry() {
+    let storage = make_storage();
+    let app = test::init_service(
+        App::new()
+            .app_data(actix_web::web::Data::new(storage.clone()))
+            .configure(abcy_data::web::configure),
+    )
+    .await;
+
+    let mut requests = Vec::new();
+    for i in 0..20 {
+        requests.push(test::TestRequest::post().uri("/ftp").set_json(json!({"ftp": 200.0 + i as f64})).to_request());
+        requests.push(test::TestRequest::post().uri("/weight").set_json(json!({"weight": 60.0 + i as f64})).to_request());
+        requests.push(test::TestRequest::get().uri("/enduro").to_request());
+        requests.push(test::TestRequest::get().uri("/fitness").to_request());
+    }
+    let responses = join_all(requests.into_iter().map(|req| test::call_service(&app, req))).await;
+    assert!(responses.iter().all(|r| r.status() == 200));
+
+    // one seeded default plus every posted value
+    let mut ftp: Vec<f64> = storage.get_ftp_history().await.unwrap().iter().map(|e| e.ftp).collect();
+    assert_eq!(ftp.len(), 21);
+    ftp.sort_by(f64::total_cmp);
+    assert_eq!(ftp[..20], (0..20).map(|i| 200.0 + i as f64).collect::<Vec<_>>()[..]);
+    assert_eq!(storage.get_weight_history().await.unwrap().len(), 21);
+    // W/kg follows the last FTP and weight written
+    let wkg = storage.get_wkg_history().await.unwrap();
+    assert_eq!(wkg.len(), 1);
+    let expected = storage.current_ftp().await.unwrap() / storage.current_weight().await.unwrap();
+    assert!((wkg[0].wkg - expected).abs() < 1e-9);
+    assert_eq!(storage.enduro_history(None).await.unwrap().len(), 20);
+    assert_eq!(storage.fitness_history(None).await.unwrap().len(), 20);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn clones_share_locks_across_threads() {
+    let storage = make_storage();
+    let tasks: Vec<_> = (0..32)
+        .map(|i| {
+            let storage = storage.clone();
+            tokio::spawn(async move {
+                storage.set_ftp(250.0 + i as f64).await.unwrap();
+                storage.record_sync_failure(i, FailureKind::Transient, "timeout").await.unwrap();
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+    assert_eq!(storage.get_ftp_history().await.unwrap().len(), 33);
+    assert_eq!(storage.sync_failures().await.unwrap().len(), 32);
+
+    let tasks: Vec<_> = (0..32)
+        .map(|i| {
+            let storage = storage.clone();
+            tokio::spawn(async move { storage.clear_sync_failure(i).await.unwrap() })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+    assert!(storage.sync_failures().await.unwrap().is_empty());
+}
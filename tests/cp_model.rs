@@ -0,0 +1,107 @@
+use abcy_data::{
+    cp::{fit, CpModelKind},
+    power::DURATIONS,
+    storage::Storage,
+    utils::Storage as StorageCfg,
+};
+use actix_web::{test::{call_and_read_body_json, call_service, init_service, TestRequest}, App};
+use serde_json::json;
+use tempfile::tempdir;
+
+fn make_storage(dir: &std::path::Path) -> Storage {
+    let cfg = StorageCfg { data_dir: dir.to_str().unwrap().into(), download_count: 1, user: "t".into() };
+    Storage::new(&cfg)
+}
+
+fn efforts(model: impl Fn(f64) -> f64) -> Vec<(usize, f64)> {
+    DURATIONS.iter().map(|&d| (d, model(d as f64))).collect()
+}
+
+fn close(a: f64, b: f64, tolerance: f64) -> bool {
+    (a - b).abs() <= tolerance
+}
+
+#[test]
+fn models_recover_their_parameters() {
+    let two = fit(CpModelKind::TwoParameter, &efforts(|t| 250.0 + 20000.0 / t)).unwrap();
+    assert!(close(two.cp, 250.0, 1e-6) && close(two.w_prime, 20000.0, 1e-3));
+    assert_eq!(two.pmax, None);
+    assert_eq!(two.points, 7);
+    assert!(close(two.r_squared, 1.0, 1e-9) && two.rmse < 1e-6);
+
+    // Pmax 1000 W gives k = 20000 / 750 s
+    let three = fit(CpModelKind::ThreeParameter, &efforts(|t| 250.0 + 20000.0 / (t + 20000.0 / 750.0))).unwrap();
+    assert!(close(three.cp, 250.0, 1e-3) && close(three.w_prime, 20000.0, 1.0));
+    assert!(close(three.pmax.unwrap(), 1000.0, 0.1));
+    assert_eq!(three.points, 17);
+    assert!(close(three.power_at(300.0), 250.0 + 20000.0 / (300.0 + 20000.0 / 750.0), 1e-3));
+
+    let exp = fit(CpModelKind::Exponential, &efforts(|t| 250.0 + 750.0 * (-t / 30.0).exp())).unwrap();
+    assert!(close(exp.cp, 250.0, 1e-3) && close(exp.pmax.unwrap(), 1000.0, 0.1));
+    assert!(close(exp.w_prime, 750.0 * 30.0, 1.0));
+    assert!(close(exp.r_squared, 1.0, 1e-9));
+
+    // noisy efforts fit worse but still fit
+    let noisy: Vec<_> = efforts(|t| 250.0 + 20000.0 / t)
+        .into_iter()
+        .enumerate()
+        .map(|(i, (d, p))| (d, p + if i % 2 == 0 { 5.0 } else { -5.0 }))
+        .collect();
+    let rough = fit(CpModelKind::TwoParameter, &noisy).unwrap();
+    assert!(rough.r_squared < 1.0 && rough.rmse > 1.0);
+    assert!(close(rough.cp, 250.0, 10.0));
+
+    // too few efforts in range or a fit without W'
+    assert!(fit(CpModelKind::TwoParameter, &[(60, 400.0), (300, 300.0)]).is_none());
+    assert!(fit(CpModelKind::ThreeParameter, &[(5, 900.0), (60, 400.0)]).is_none());
+    assert!(fit(CpModelKind::TwoParameter, &[(120, 250.0), (600, 250.0), (1200, 250.0)]).is_none());
+}
+
+#[actix_rt::test]
+async fn cp_model_endpoint() {
+    let dir = tempdir().unwrap();
+    let storage = make_storage(dir.path());
+    storage.add_ftp("2024-01-01", 260.0).await.unwrap();
+    // one maximal effort per fitted duration, P = 250 + 25200 / t
+    for (i, secs) in [120, 180, 300, 420, 600, 900, 1200].into_iter().enumerate() {
+        let watts = 250 + 25200 / secs;
+        let streams = json!({"time": (0..secs).collect::<Vec<_>>(), "watts": vec![watts; secs as usize]});
+        let start = format!("2024-03-{:02}T07:00:00Z", i + 1);
+        storage.save(&json!({"id": i + 1, "name": "test", "start_date": start, "distance": 1.0}), &streams).await.unwrap();
+    }
+    let app = init_service(
+        App::new()
+            .app_data(actix_web::web::Data::new(storage.clone()))
+            .configure(abcy_data::web::configure),
+    )
+    .await;
+
+    let req = TestRequest::get().uri("/cp-model?from=2024-03-01&to=2024-03-31").to_request();
+    let body: serde_json::Value = call_and_read_body_json(&app, req).await;
+    assert_eq!(body["model"], "2p");
+    assert!(close(body["cp"].as_f64().unwrap(), 250.0, 1e-6));
+    assert!(close(body["w_prime"].as_f64().unwrap(), 25200.0, 1e-3));
+    assert!(body["pmax"].is_null());
+    assert_eq!(body["ftp"], 260.0);
+    assert_eq!(body["points"], 7);
+    let curve = body["curve"].as_array().unwrap();
+    assert_eq!(curve.len(), DURATIONS.len());
+    let at_300 = curve.iter().find(|p| p["duration"] == 300).unwrap();
+    assert_eq!(at_300["actual"], 334.0);
+    assert!(close(at_300["watts"].as_f64().unwrap(), 334.0, 1e-6));
+    assert!(curve.iter().find(|p| p["duration"] == 3600).unwrap().get("actual").is_none());
+
+    for model in ["3p", "exp"] {
+        let req = TestRequest::get().uri(&format!("/cp-model?model={}", model)).to_request();
+        let body: serde_json::Value = call_and_read_body_json(&app, req).await;
+        assert_eq!(body["model"], model);
+        assert!(body["cp"].as_f64().unwrap() > 0.0 && body["pmax"].as_f64().is_some());
+    }
+
+    let req = TestRequest::get().uri("/cp-model?to=2024-03-01").to_request();
+    assert_eq!(call_service(&app, req).await.status(), 422);
+    for uri in ["/cp-model?model=5p", "/cp-model?from=2024-03-02&to=2024-03-01", "/cp-model?to=march"] {
+        let req = TestRequest::get().uri(uri).to_request();
+        assert_eq!(call_service(&app, req).await.status(), 400);
+    }
+}
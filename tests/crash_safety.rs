@@ -0,0 +1,130 @@
+use abcy_data::{storage::Storage, utils::{write_atomic, Storage as StorageCfg}};
+use actix_web::{test, App};
+use serde_json::json;
+use std::path::Path;
+use tempfile::tempdir;
+
+fn make_storage(dir: &Path) -> Storage {
+    let cfg = StorageCfg { data_dir: dir.to_str().unwrap().into(), download_count: 1, user: "t".into() };
+    Storage::new(&cfg)
+}
+
+async fn save_rides(storage: &Storage) {
+    let streams = json!({"time": [0, 3600], "watts": [200, 200]});
+    for id in 1..=3 {
+        let meta = json!({"id": id, "name": format!("r{}", id), "start_date": format!("2024-03-0{}T08:00:00Z", id), "distance": 5.0});
+        storage.save(&meta, &streams).await.unwrap();
+    }
+}
+
+fn entries(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().into()).collect();
+    names.sort();
+    names
+}
+
+#[tokio::test]
+async fn writes_replace_files_without_leftovers() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("ftp.json");
+    write_atomic(&path, b"[1]").await.unwrap();
+    write_atomic(&path, b"[1,2]").await.unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"[1,2]");
+    assert_eq!(entries(dir.path()), vec!["ftp.json"]);
+
+    // a failed write keeps the previous contents
+    assert!(write_atomic(&dir.path().join("missing").join("ftp.json"), b"[]").await.is_err());
+    assert!(write_atomic(dir.path(), b"[]").await.is_err());
+    assert_eq!(std::fs::read(&path).unwrap(), b"[1,2]");
+    assert_eq!(entries(dir.path()), vec!["ftp.json"]);
+
+    let storage = make_storage(dir.path());
+    save_rides(&storage).await;
+    storage.set_ftp(260.0).await.unwrap();
+    let user = dir.path().join("t");
+    assert_eq!(entries(&user.join("2024").join("1")), vec!["meta.json.zst", "power_curve.json.zst", "streams.json.zst"]);
+    assert!(!entries(&user).iter().any(|n| n.ends_with(".tmp")));
+
+    // temporary files left by a crash are not reported as stored files
+    std::fs::write(user.join(".ftp.json.0123456789abcdef.tmp"), "[{\"da").unwrap();
+    let files = storage.list_files().await.unwrap();
+    assert!(files.contains(&"ftp.json".to_string()));
+    assert!(!files.iter().any(|f| f.ends_with(".tmp")));
+    assert_eq!(storage.current_ftp().await.unwrap(), 260.0);
+}
+
+#[tokio::test]
+async fn corrupt_index_is_rebuilt() {
+    let dir = tempdir().unwrap();
+    let storage = make_storage(dir.path());
+    save_rides(&storage).await;
+    std::fs::write(dir.path().join("t").join("index.json"), "[{\"id\": 3, \"na").unwrap();
+    let ids: Vec<u64> = storage.list_activities(None).await.unwrap().iter().map(|a| a.id).collect();
+    assert_eq!(ids, vec![3, 2, 1]);
+    assert!(storage.quarantined().await.unwrap().is_empty());
+}
+
+#[actix_rt::test]
+async fn corrupt_activity_is_quarantined() {
+    let dir = tempdir().unwrap();
+    let storage = make_storage(dir.path());
+    save_rides(&storage).await;
+    let user = dir.path().join("t");
+    std::fs::write(user.join("2024").join("2").join("meta.json.zst"), b"\x28\xb5\x2f\xfd truncated").unwrap();
+    std::fs::remove_file(user.join("index.json")).unwrap();
+
+    let ids: Vec<u64> = storage.list_activities(None).await.unwrap().iter().map(|a| a.id).collect();
+    assert_eq!(ids, vec![3, 1]);
+    assert!(!user.join("2024").join("2").exists());
+    let moved = user.join("quarantine").join("2024-2");
+    assert_eq!(std::fs::read(moved.join("meta.json.zst")).unwrap(), b"\x28\xb5\x2f\xfd truncated");
+    assert!(moved.join("streams.json.zst").exists());
+
+    let quarantined = storage.quarantined().await.unwrap();
+    assert_eq!(quarantined.len(), 1);
+    assert_eq!(quarantined[0].id, 2);
+    assert_eq!(quarantined[0].path, "2024/2");
+    assert_eq!(quarantined[0].quarantine_path, "quarantine/2024-2");
+    assert!(!quarantined[0].error.is_empty());
+
+    // the quarantine directory is never mistaken for a year
+    assert_eq!(storage.rebuild_index().await.unwrap(), 2);
+    assert!(storage.load_activity(2).await.is_err());
+    assert!(storage.load_activity(3).await.is_ok());
+
+    let app = test::init_service(
+        App::new()
+            .app_data(actix_web::web::Data::new(storage.clone()))
+            .configure(abcy_data::web::configure),
+    )
+    .await;
+    let req = test::TestRequest::get().uri("/activities").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+    let req = test::TestRequest::get().uri("/quarantine").to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body[0]["id"], 2);
+    assert_eq!(body[0]["quarantine_path"], "quarantine/2024-2");
+}
+
+#[tokio::test]
+async fn incomplete_activity_is_skipped_not_quarantined() {
+    let dir = tempdir().unwrap();
+    let storage = make_storage(dir.path());
+    save_rides(&storage).await;
+    let user = dir.path().join("t");
+    // a save interrupted between writing meta and streams
+    std::fs::remove_file(user.join("2024").join("2").join("streams.json.zst")).unwrap();
+    std::fs::remove_file(user.join("index.json")).unwrap();
+
+    let ids: Vec<u64> = storage.list_activities(None).await.unwrap().iter().map(|a| a.id).collect();
+    assert_eq!(ids, vec![3, 1]);
+    assert!(user.join("2024").join("2").join("meta.json.zst").exists());
+    assert!(!user.join("quarantine").exists());
+    assert!(storage.quarantined().await.unwrap().is_empty());
+
+    // once the save completes the activity is indexed again
+    let streams = json!({"time": [0, 3600], "watts": [200, 200]});
+    let meta = json!({"id": 2, "name": "r2", "start_date": "2024-03-02T08:00:00Z", "distance": 5.0});
+    storage.save(&meta, &streams).await.unwrap();
+    assert_eq!(storage.rebuild_index().await.unwrap(), 3);
+}
@@ -0,0 +1,125 @@
+use abcy_data::{
+    export::{export, ExportFormat},
+    fit,
+    import::{parse_fit, parse_gpx, parse_tcx},
+    storage::Storage,
+    utils::Storage as StorageCfg,
+};
+use actix_web::{test, App};
+use serde_json::json;
+use tempfile::tempdir;
+
+fn make_storage() -> Storage {
+    let dir = tempdir().unwrap();
+    let cfg = StorageCfg { data_dir: dir.path().to_str().unwrap().into(), download_count: 1, user: "t".into() };
+    Storage::new(&cfg)
+}
+
+const START: i64 = 1_700_000_000;
+
+async fn stored_ride(storage: &Storage) {
+    let meta = json!({
+        "id": 42,
+        "name": "Tempo & hills",
+        "type": "Ride",
+        "start_date": "2023-11-14T22:13:20Z",
+        "distance": 60.0,
+        "elapsed_time": 2,
+        "moving_time": 2,
+        "average_watts": 210.0,
+        "total_elevation_gain": 3.0
+    });
+    let streams = json!({
+        "time": {"data": [0, 1, 2]},
+        "latlng": {"data": [[51.5, -0.12], [51.5001, -0.12], [51.5002, -0.12]]},
+        "altitude": {"data": [10.0, 12.0, 13.0]},
+        "distance": {"data": [0.0, 30.0, 60.0]},
+        "velocity_smooth": {"data": [9.5, 10.0, 10.5]},
+        "heartrate": {"data": [120, 0, 130]},
+        "cadence": {"data": [85, 90, 95]},
+        "watts": {"data": [200, 210, 220]}
+    });
+    storage.save(&meta, &streams).await.unwrap();
+}
+
+#[tokio::test]
+async fn exports_round_trip_through_importers() {
+    let storage = make_storage();
+    stored_ride(&storage).await;
+    let detail = storage.load_activity(42).await.unwrap();
+
+    let gpx = parse_gpx(&export(&detail, ExportFormat::Gpx).unwrap()).unwrap();
+    assert_eq!(gpx.name.as_deref(), Some("Tempo & hills"));
+    assert_eq!(gpx.start, START);
+    assert_eq!(gpx.points.len(), 3);
+    assert_eq!(gpx.points[2].time, START + 2);
+    assert_eq!(gpx.points[1].power, Some(210));
+    assert_eq!(gpx.points[1].heartrate, None);
+    assert_eq!(gpx.points[2].heartrate, Some(130));
+    assert_eq!(gpx.points[0].cadence, Some(85));
+    assert!((gpx.points[1].lat.unwrap() - 51.5001).abs() < 1e-6);
+    assert_eq!(gpx.points[2].altitude, Some(13.0));
+
+    let tcx = parse_tcx(&export(&detail, ExportFormat::Tcx).unwrap()).unwrap();
+    assert_eq!(tcx.activity_type, "Ride");
+    assert_eq!(tcx.start, START);
+    assert_eq!(tcx.points.iter().map(|p| p.power).collect::<Vec<_>>(), vec![Some(200), Some(210), Some(220)]);
+    assert_eq!(tcx.points[2].distance, Some(60.0));
+    assert_eq!(tcx.points[0].speed, Some(9.5));
+
+    let data = export(&detail, ExportFormat::Fit).unwrap();
+    let decoded = fit::decode(&data).unwrap();
+    let session = decoded.session.clone().unwrap();
+    assert_eq!(session.start_time, Some(START));
+    assert_eq!(session.sport, Some(2));
+    assert_eq!(session.total_distance, Some(60.0));
+    assert_eq!(session.avg_power, Some(210));
+    assert_eq!(decoded.records.len(), 3);
+    let r = &decoded.records[1];
+    assert_eq!(r.timestamp, START + 1);
+    assert_eq!(r.power, Some(210));
+    assert_eq!(r.heart_rate, None);
+    assert_eq!(r.cadence, Some(90));
+    assert_eq!(r.distance, Some(30.0));
+    assert_eq!(r.speed, Some(10.0));
+    assert_eq!(r.altitude, Some(12.0));
+    assert!((r.lat.unwrap() - 51.5001).abs() < 1e-6);
+    let fit = parse_fit(&data).unwrap();
+    assert_eq!(fit.activity_type, "Ride");
+    assert_eq!(fit.points.len(), 3);
+}
+
+#[actix_rt::test]
+async fn export_endpoint() {
+    let storage = make_storage();
+    stored_ride(&storage).await;
+    let app = test::init_service(
+        App::new()
+            .app_data(actix_web::web::Data::new(storage.clone()))
+            .configure(abcy_data::web::configure),
+    )
+    .await;
+
+    let req = test::TestRequest::get().uri("/activity/42/export?format=fit").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "application/vnd.ant.fit");
+    assert_eq!(resp.headers().get("content-disposition").unwrap(), "attachment; filename=\"42.fit\"");
+    let body = test::read_body(resp).await;
+    assert_eq!(fit::decode(&body).unwrap().records.len(), 3);
+
+    let req = test::TestRequest::get().uri("/activity/42/export?format=kml").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    let req = test::TestRequest::get().uri("/activity/7/export?format=gpx").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+
+    // an indoor ride has nothing to put in a GPX track, but still exports as TCX
+    let meta = json!({"id": 43, "name": "Trainer", "type": "VirtualRide", "start_date": "2023-11-15T07:00:00Z"});
+    storage.save(&meta, &json!({"time": {"data": [0, 1]}, "watts": {"data": [180, 190]}})).await.unwrap();
+    let req = test::TestRequest::get().uri("/activity/43/export?format=gpx").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 422);
+    assert!(String::from_utf8_lossy(&test::read_body(resp).await).contains("TCX"));
+    let req = test::TestRequest::get().uri("/activity/43/export?format=tcx").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+}
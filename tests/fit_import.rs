@@ -0,0 +1,197 @@
+use abcy_data::{
+    fit::{crc16, FIT_EPOCH_OFFSET},
+    import::{import_file, is_synthetic_id, synthetic_id, ImportFormat},
+    storage::Storage,
+    utils::Storage as StorageCfg,
+};
+use actix_web::{test, App};
+use tempfile::tempdir;
+
+fn make_storage() -> Storage {
+    let dir = tempdir().unwrap();
+    let cfg = StorageCfg { data_dir: dir.path().to_str().unwrap().into(), download_count: 1, user: "t".into() };
+    Storage::new(&cfg)
+}
+
+/// Writes FIT messages in little-endian layout.
+#[derive(Default)]
+struct FitWriter {
+    data: Vec<u8>,
+}
+
+impl FitWriter {
+    fn define(&mut self, local: u8, global: u16, fields: &[(u8, u8, u8)]) {
+        self.data.push(0x40 | local);
+        self.data.extend_from_slice(&[0, 0]);
+        self.data.extend_from_slice(&global.to_le_bytes());
+        self.data.push(fields.len() as u8);
+        for (num, size, base) in fields {
+            self.data.extend_from_slice(&[*num, *size, *base]);
+        }
+    }
+
+    fn message(&mut self, header: u8, values: &[&[u8]]) {
+        self.data.push(header);
+        for v in values {
+            self.data.extend_from_slice(v);
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        let mut out = vec![14, 0x20];
+        out.extend_from_slice(&2140u16.to_le_bytes());
+        out.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        out.extend_from_slice(b".FIT");
+        let header_crc = crc16(&out);
+        out.extend_from_slice(&header_crc.to_le_bytes());
+        out.extend_from_slice(&self.data);
+        let crc = crc16(&out);
+        out.extend_from_slice(&crc.to_le_bytes());
+        out
+    }
+}
+
+const T0: u32 = 1_000_000_000;
+
+fn semicircles(deg: f64) -> [u8; 4] {
+    ((deg * 2f64.powi(31) / 180.0) as i32).to_le_bytes()
+}
+
+fn sample_fit() -> Vec<u8> {
+    let mut w = FitWriter::default();
+    w.define(0, 0, &[(0, 1, 0x00), (4, 4, 0x86)]);
+    w.message(0, &[&[4], &T0.to_le_bytes()]);
+    let record_fields = [(253, 4, 0x86), (0, 4, 0x85), (1, 4, 0x85), (2, 2, 0x84), (3, 1, 0x02), (4, 1, 0x02), (5, 4, 0x86), (6, 2, 0x84), (7, 2, 0x84)];
+    w.define(1, 20, &record_fields);
+    for (i, (hr, power)) in [(120u8, 200u16), (0xFF, 210), (125, 220)].iter().enumerate() {
+        let ts = T0 + i as u32;
+        let alt = ((100.0 + i as f64 + 500.0) * 5.0) as u16;
+        w.message(1, &[
+            &ts.to_le_bytes(),
+            &semicircles(51.5 + i as f64 * 0.0001),
+            &semicircles(-0.12),
+            &alt.to_le_bytes(),
+            &[*hr],
+            &[90],
+            &(i as u32 * 1000).to_le_bytes(),
+            &10_000u16.to_le_bytes(),
+            &power.to_le_bytes(),
+        ]);
+    }
+    // record using a compressed timestamp header
+    w.define(2, 20, &[(3, 1, 0x02), (7, 2, 0x84)]);
+    let offset = ((T0 + 3) & 0x1F) as u8;
+    w.message(0x80 | (2 << 5) | offset, &[&[130], &230u16.to_le_bytes()]);
+    w.define(3, 18, &[(2, 4, 0x86), (5, 1, 0x00), (6, 1, 0x00), (7, 4, 0x86), (8, 4, 0x86), (9, 4, 0x86), (22, 2, 0x84)]);
+    w.message(3, &[&T0.to_le_bytes(), &[2], &[6], &3000u32.to_le_bytes(), &3000u32.to_le_bytes(), &3000u32.to_le_bytes(), &2u16.to_le_bytes()]);
+    w.finish()
+}
+
+#[tokio::test]
+async fn fit_file_is_imported() {
+    let storage = make_storage();
+    let id = import_file(&storage, "trainer.FIT", &sample_fit()).await.unwrap();
+    let start = T0 as i64 + FIT_EPOCH_OFFSET;
+    assert_eq!(id, synthetic_id(ImportFormat::Fit, start));
+    assert!(is_synthetic_id(id));
+
+    let act = storage.load_activity(id).await.unwrap();
+    assert_eq!(act.meta["type"], "VirtualRide");
+    assert_eq!(act.meta["start_date"], "2021-09-08T01:46:40Z");
+    assert_eq!(act.meta["elapsed_time"], 3);
+    assert_eq!(act.meta["distance"], 30.0);
+    assert_eq!(act.meta["total_elevation_gain"], 2.0);
+    assert!(act.meta["normalized_power"].as_f64().unwrap() > 0.0);
+    assert_eq!(act.streams.time, vec![0, 1, 2, 3]);
+    assert_eq!(act.streams.power, vec![200, 210, 220, 230]);
+    assert_eq!(act.streams.heartrate, vec![120, 0, 125, 130]);
+    assert_eq!(act.streams.cadence, Some(vec![90, 90, 90, 0]));
+    assert_eq!(act.streams.velocity_smooth, Some(vec![10.0, 10.0, 10.0, 0.0]));
+    assert_eq!(act.streams.distance, Some(vec![0.0, 10.0, 20.0, 20.0]));
+    let latlng = act.streams.latlng.unwrap();
+    assert!((latlng[1][0] - 51.5001).abs() < 1e-6);
+    assert!((latlng[3][1] + 0.12).abs() < 1e-6);
+    let alt = act.streams.altitude.unwrap();
+    assert!((alt[2] - 102.0).abs() < 1e-6);
+
+    // importing the same file again keeps a single copy
+    assert_eq!(import_file(&storage, "trainer.fit", &sample_fit()).await.unwrap(), id);
+    assert_eq!(storage.list_activities(None).await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn corrupt_fit_file_is_rejected() {
+    let storage = make_storage();
+    let mut data = sample_fit();
+    let n = data.len();
+    data[n - 5] ^= 0xFF;
+    assert!(import_file(&storage, "broken.fit", &data).await.is_err());
+    assert!(import_file(&storage, "notes.txt", b"hello").await.is_err());
+}
+
+/// Multipart body with one file part per `(filename, data)` and its content type.
+fn multipart(files: &[(&str, &[u8])]) -> (String, Vec<u8>) {
+    let boundary = "XBOUNDARYX";
+    let mut body = Vec::new();
+    for (name, data) in files {
+        body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\nContent-Type: application/octet-stream\r\n\r\n",
+                boundary, name
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    (format!("multipart/form-data; boundary={}", boundary), body)
+}
+
+#[actix_rt::test]
+async fn fit_upload_endpoint() {
+    let storage = make_storage();
+    let app = test::init_service(
+        App::new()
+            .app_data(actix_web::web::Data::new(storage.clone()))
+            .configure(abcy_data::web::configure),
+    )
+    .await;
+    let (content_type, body) = multipart(&[("ride.fit", &sample_fit())]);
+    let req = test::TestRequest::post()
+        .uri("/import")
+        .insert_header(("content-type", content_type))
+        .set_payload(body)
+        .to_request();
+    let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let id = synthetic_id(ImportFormat::Fit, T0 as i64 + FIT_EPOCH_OFFSET);
+    assert_eq!(resp["imported"], serde_json::json!([id]));
+    assert!(storage.load_activity(id).await.is_ok());
+}
+
+#[actix_rt::test]
+async fn upload_is_all_or_nothing_and_bounded() {
+    let storage = make_storage();
+    let app = test::init_service(
+        App::new()
+            .app_data(actix_web::web::Data::new(storage.clone()))
+            .configure(abcy_data::web::configure),
+    )
+    .await;
+    let id = synthetic_id(ImportFormat::Fit, T0 as i64 + FIT_EPOCH_OFFSET);
+
+    let (content_type, body) = multipart(&[("ride.fit", &sample_fit()), ("notes.txt", b"hello"), ("empty.gpx", b"<gpx/>")]);
+    let req = test::TestRequest::post().uri("/import").insert_header(("content-type", content_type)).set_payload(body).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 422);
+    let text = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(text.starts_with("notes.txt: unsupported file type"));
+    assert_eq!(text.lines().count(), 2);
+    assert!(storage.load_activity(id).await.is_err());
+
+    let huge = vec![0u8; 25 * 1024 * 1024 + 1];
+    let (content_type, body) = multipart(&[("ride.fit", &sample_fit()), ("huge.fit", &huge)]);
+    let req = test::TestRequest::post().uri("/import").insert_header(("content-type", content_type)).set_payload(body).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 413);
+    assert!(storage.load_activity(id).await.is_err());
+}
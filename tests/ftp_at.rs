@@ -0,0 +1,81 @@
+use abcy_data::{
+    storage::{ftp_on, FtpEntry, Storage},
+    utils::Storage as StorageCfg,
+};
+use serde_json::json;
+use std::path::Path;
+use tempfile::tempdir;
+
+fn make_storage(dir: &Path) -> Storage {
+    let cfg = StorageCfg { data_dir: dir.to_str().unwrap().into(), download_count: 1, user: "t".into() };
+    Storage::new(&cfg)
+}
+
+fn entry(date: &str, ftp: f64) -> FtpEntry {
+    FtpEntry { date: date.into(), ftp }
+}
+
+#[test]
+fn effective_ftp_lookup() {
+    let history = vec![entry("2024-06-01", 250.0), entry("2024-01-01", 200.0), entry("2024-06-01", 260.0)];
+    assert_eq!(ftp_on(&history, "2023-12-01"), Some(200.0));
+    assert_eq!(ftp_on(&history, "2024-01-01"), Some(200.0));
+    assert_eq!(ftp_on(&history, "2024-05-31T23:00:00Z"), Some(200.0));
+    assert_eq!(ftp_on(&history, "2024-06-01T06:00:00Z"), Some(260.0));
+    assert_eq!(ftp_on(&history, "2025-01-01"), Some(260.0));
+    assert_eq!(ftp_on(&[], "2025-01-01"), None);
+}
+
+#[tokio::test]
+async fn metrics_use_ftp_of_start_date() {
+    let dir = tempdir().unwrap();
+    let user = dir.path().join("t");
+    std::fs::create_dir_all(&user).unwrap();
+    std::fs::write(
+        user.join("ftp.json"),
+        json!([{"date": "2024-01-01", "ftp": 200.0}, {"date": "2024-06-01", "ftp": 250.0}]).to_string(),
+    )
+    .unwrap();
+    let storage = make_storage(dir.path());
+    assert_eq!(storage.ftp_at("2024-03-01T08:00:00Z").await.unwrap(), 200.0);
+
+    let streams = json!({"time": [0, 3600], "watts": [200, 200]});
+    storage.save(&json!({"id": 1, "name": "spring", "start_date": "2024-03-01T08:00:00Z", "distance": 1.0}), &streams).await.unwrap();
+    storage.save(&json!({"id": 2, "name": "summer", "start_date": "2024-07-01T08:00:00Z", "distance": 1.0}), &streams).await.unwrap();
+
+    // raising FTP today leaves earlier rides alone
+    storage.set_ftp(400.0).await.unwrap();
+    for _ in 0..2 {
+        let spring = storage.load_activity_summary(1).await.unwrap();
+        assert!((spring.intensity_factor.unwrap() - 1.0).abs() < 1e-9);
+        assert!((spring.training_stress_score.unwrap() - 100.0).abs() < 1e-6);
+        let summer = storage.load_activity_summary(2).await.unwrap();
+        assert!((summer.intensity_factor.unwrap() - 0.8).abs() < 1e-9);
+        assert!((summer.training_stress_score.unwrap() - 64.0).abs() < 1e-6);
+        let detail = storage.load_activity(2).await.unwrap();
+        assert!((detail.meta["intensity_factor"].as_f64().unwrap() - 0.8).abs() < 1e-9);
+        storage.rebuild_index().await.unwrap();
+    }
+    let index = storage.activity_summaries().await.unwrap();
+    assert!((index[1].training_stress_score.unwrap() - 100.0).abs() < 1e-6);
+
+    // a ride synced later is computed against the FTP of its own date
+    storage.save(&json!({"id": 3, "name": "winter", "start_date": "2023-11-01T08:00:00Z", "distance": 1.0}), &streams).await.unwrap();
+    assert!((storage.load_activity_summary(3).await.unwrap().intensity_factor.unwrap() - 1.0).abs() < 1e-9);
+}
+
+#[tokio::test]
+async fn threshold_lookups_only_read_the_history() {
+    let dir = tempdir().unwrap();
+    let storage = make_storage(dir.path());
+    let streams = json!({"time": [0, 3600], "watts": [180, 180]});
+    storage.save(&json!({"id": 1, "name": "first", "start_date": "2024-03-01T08:00:00Z", "distance": 1.0}), &streams).await.unwrap();
+    storage.rebuild_index().await.unwrap();
+    // summarised against the default FTP without writing it
+    assert!((storage.load_activity_summary(1).await.unwrap().intensity_factor.unwrap() - 0.75).abs() < 1e-9);
+    assert!(!dir.path().join("t/ftp.json").exists());
+
+    // an unreadable history fails the rebuild instead of falling back
+    std::fs::write(dir.path().join("t/ftp.json"), "not json").unwrap();
+    assert!(storage.rebuild_index().await.is_err());
+}
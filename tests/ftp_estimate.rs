@@ -0,0 +1,157 @@
+use abcy_data::{
+    auth::Auth,
+    fetch,
+    ftp_estimate::estimate,
+    power::BestEffort,
+    storage::Storage,
+    utils::{Config, FtpEstimate, FtpMethod},
+};
+use actix_web::{test::{call_and_read_body_json, call_service, init_service, TestRequest}, App};
+use chrono::{Duration, Utc};
+use mockito::Matcher;
+use serde_json::json;
+use tempfile::{tempdir, TempDir};
+
+mod common;
+
+fn make_env(base_url: &str, extra: &str) -> (TempDir, Config, Auth, Storage) {
+    let dir = tempdir().unwrap();
+    common::write_valid_token(dir.path());
+    let cfg = common::test_config_with(base_url, dir.path(), extra);
+    let storage = Storage::new(&cfg.storage);
+    let auth = Auth::new(cfg.clone());
+    (dir, cfg, auth, storage)
+}
+
+fn days_ago(days: i64) -> String {
+    (Utc::now() - Duration::days(days)).format("%Y-%m-%dT07:00:00Z").to_string()
+}
+
+fn steady(secs: usize, watts: i64) -> serde_json::Value {
+    json!({"time": (0..secs).collect::<Vec<_>>(), "watts": vec![watts; secs]})
+}
+
+fn effort(duration: usize, watts: f64, activity_id: u64) -> BestEffort {
+    BestEffort { duration, watts, activity_id, start_date: format!("2024-03-{:02}T07:00:00Z", activity_id) }
+}
+
+#[test]
+fn estimate_methods() {
+    let efforts: Vec<_> = [120, 300, 600, 1200, 3600]
+        .into_iter()
+        .enumerate()
+        .map(|(i, d)| effort(d, 250.0 + 25200.0 / d as f64, i as u64 + 1))
+        .collect();
+    let (ftp, used) = estimate(FtpMethod::TwentyMinute, &efforts).unwrap();
+    assert_eq!(ftp, (0.95f64 * 271.0).round());
+    assert_eq!(used, vec![efforts[3].clone()]);
+    let (ftp, used) = estimate(FtpMethod::Cp, &efforts).unwrap();
+    assert_eq!(ftp, 250.0);
+    assert_eq!(used.len(), 4);
+
+    assert!(estimate(FtpMethod::TwentyMinute, &efforts[..3]).is_none());
+    assert!(estimate(FtpMethod::Cp, &efforts[..1]).is_none());
+}
+
+#[actix_rt::test]
+async fn estimate_endpoint() {
+    let (_dir, cfg, _auth, storage) = make_env("http://localhost", "[ftp_estimate]\ndays = 60\n");
+    assert_eq!(cfg.ftp_estimate.method, FtpMethod::TwentyMinute);
+    assert!(!cfg.ftp_estimate.auto_update);
+    let app = init_service(
+        App::new()
+            .app_data(actix_web::web::Data::new(storage.clone()))
+            .app_data(actix_web::web::Data::new(cfg.clone()))
+            .configure(abcy_data::web::configure),
+    )
+    .await;
+    let req = TestRequest::get().uri("/ftp/estimate").to_request();
+    assert_eq!(call_service(&app, req).await.status(), 422);
+
+    storage.save(&json!({"id": 1, "name": "old", "start_date": days_ago(70), "distance": 1.0}), &steady(1200, 400)).await.unwrap();
+    storage.save(&json!({"id": 2, "name": "test", "start_date": days_ago(10), "distance": 1.0}), &steady(1200, 300)).await.unwrap();
+    storage.save(&json!({"id": 3, "name": "short", "start_date": days_ago(5), "distance": 1.0}), &steady(300, 340)).await.unwrap();
+
+    let req = TestRequest::get().uri("/ftp/estimate").to_request();
+    let body: serde_json::Value = call_and_read_body_json(&app, req).await;
+    assert_eq!(body["method"], "twenty_minute");
+    assert_eq!(body["ftp"], 285.0);
+    assert_eq!(body["current_ftp"], 240.0);
+    assert_eq!(body["activity_id"], 2);
+    assert_eq!(body["efforts"].as_array().unwrap().len(), 1);
+
+    // a longer window reaches the older, stronger ride
+    let req = TestRequest::get().uri("/ftp/estimate?days=90").to_request();
+    let body: serde_json::Value = call_and_read_body_json(&app, req).await;
+    assert_eq!((body["ftp"].clone(), body["activity_id"].clone()), (json!(380.0), json!(1)));
+
+    // the CP fit names the newest ride it used
+    let req = TestRequest::get().uri("/ftp/estimate?method=cp").to_request();
+    let body: serde_json::Value = call_and_read_body_json(&app, req).await;
+    assert_eq!(body["method"], "cp");
+    assert_eq!(body["activity_id"], 3);
+    assert!(body["ftp"].as_f64().unwrap() > 0.0);
+
+    for uri in ["/ftp/estimate?method=ramp", "/ftp/estimate?days=0", "/ftp/estimate?days=3651", "/ftp/estimate?days=9223372036854775807"] {
+        let req = TestRequest::get().uri(uri).to_request();
+        assert_eq!(call_service(&app, req).await.status(), 400);
+    }
+    // estimating never changes the stored FTP
+    assert_eq!(storage.current_ftp().await.unwrap(), 240.0);
+}
+
+async fn mock_ride(server: &mut mockito::ServerGuard, id: u64, watts: i64) {
+    let start = days_ago(1);
+    server
+        .mock("GET", "/athlete/activities")
+        .match_query(Matcher::Any)
+        .with_body(json!([{"id": id, "name": "ride", "start_date": start, "distance": 1.0}]).to_string())
+        .create_async()
+        .await;
+    server
+        .mock("GET", format!("/activities/{}", id).as_str())
+        .with_body(json!({"id": id, "name": "ride", "start_date": start, "distance": 1.0}).to_string())
+        .create_async()
+        .await;
+    let streams = json!({"time": {"data": (0..1200).collect::<Vec<_>>()}, "watts": {"data": vec![watts; 1200]}});
+    server
+        .mock("GET", format!("/activities/{}/streams", id).as_str())
+        .match_query(Matcher::Any)
+        .with_body(streams.to_string())
+        .create_async()
+        .await;
+}
+
+#[actix_rt::test]
+async fn sync_raises_ftp_beyond_margin() {
+    let mut server = mockito::Server::new_async().await;
+    mock_ride(&mut server, 7, 300).await;
+    let (_dir, _cfg, auth, storage) = make_env(&server.url(), "[ftp_estimate]\nauto_update = true\nmargin = 10\n");
+    fetch::download_latest(&auth, &storage, 1).await.unwrap();
+    assert_eq!(storage.current_ftp().await.unwrap(), 285.0);
+    assert_eq!(storage.ftp_history(Some(1)).await.unwrap()[0].ftp, 285.0);
+
+    // within the margin of the current FTP nothing is appended
+    let cfg = FtpEstimate { auto_update: true, margin: 10.0, ..FtpEstimate::default() };
+    storage.save(&json!({"id": 8, "name": "ride", "start_date": days_ago(1), "distance": 1.0}), &steady(1200, 310)).await.unwrap();
+    assert_eq!(storage.auto_update_ftp(&cfg).await.unwrap(), None);
+    assert_eq!(storage.current_ftp().await.unwrap(), 285.0);
+    let lower = FtpEstimate { margin: 5.0, ..cfg.clone() };
+    assert_eq!(storage.auto_update_ftp(&lower).await.unwrap(), Some(295.0));
+
+    // disabled by default
+    let mut server = mockito::Server::new_async().await;
+    mock_ride(&mut server, 9, 400).await;
+    let (_dir, _cfg, auth, storage) = make_env(&server.url(), "");
+    fetch::download_latest(&auth, &storage, 1).await.unwrap();
+    assert_eq!(storage.current_ftp().await.unwrap(), 240.0);
+}
+
+#[actix_rt::test]
+async fn configured_window_is_capped() {
+    let (_dir, cfg, _auth, storage) = make_env("http://localhost", "[ftp_estimate]\ndays = 9223372036854775807\n");
+    assert_eq!(cfg.ftp_estimate.days, 3650);
+    storage.save(&json!({"id": 1, "name": "ride", "start_date": days_ago(1), "distance": 1.0}), &steady(1200, 300)).await.unwrap();
+    let estimate = storage.estimate_ftp(&cfg.ftp_estimate).await.unwrap().unwrap();
+    assert_eq!(estimate.from, (Utc::now().date_naive() - Duration::days(3649)).to_string());
+}
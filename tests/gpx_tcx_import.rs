@@ -0,0 +1,133 @@
+use abcy_data::{
+    import::{import_file, synthetic_id, ImportFormat},
+    storage::Storage,
+    utils::Storage as StorageCfg,
+};
+use tempfile::tempdir;
+
+fn make_storage() -> Storage {
+    let dir = tempdir().unwrap();
+    let cfg = StorageCfg { data_dir: dir.path().to_str().unwrap().into(), download_count: 1, user: "t".into() };
+    Storage::new(&cfg)
+}
+
+const GPX: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.1" creator="Garmin Connect" xmlns="http://www.topografix.com/GPX/1/1"
+     xmlns:gpxtpx="http://www.garmin.com/xmlschemas/TrackPointExtension/v1">
+  <trk>
+    <name>Morning loop</name>
+    <type>cycling</type>
+    <trkseg>
+      <trkpt lat="51.5000" lon="-0.1200">
+        <ele>10.0</ele><time>2020-05-01T07:00:00Z</time>
+        <extensions><power>200</power><gpxtpx:TrackPointExtension><gpxtpx:hr>120</gpxtpx:hr><gpxtpx:cad>85</gpxtpx:cad><gpxtpx:atemp>18</gpxtpx:atemp></gpxtpx:TrackPointExtension></extensions>
+      </trkpt>
+      <trkpt lat="51.5010" lon="-0.1200">
+        <ele>14.0</ele><time>2020-05-01T07:00:10Z</time>
+        <extensions><power>220</power><gpxtpx:TrackPointExtension><gpxtpx:hr>125</gpxtpx:hr><gpxtpx:cad>88</gpxtpx:cad></gpxtpx:TrackPointExtension></extensions>
+      </trkpt>
+      <trkpt lat="51.5020" lon="-0.1200">
+        <ele>12.0</ele><time>2020-05-01T07:00:20Z</time>
+        <extensions><power>240</power><gpxtpx:TrackPointExtension><gpxtpx:hr>130</gpxtpx:hr><gpxtpx:cad>90</gpxtpx:cad></gpxtpx:TrackPointExtension></extensions>
+      </trkpt>
+    </trkseg>
+  </trk>
+</gpx>"#;
+
+const TCX: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<TrainingCenterDatabase xmlns="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2"
+    xmlns:ns3="http://www.garmin.com/xmlschemas/ActivityExtension/v2">
+  <Activities>
+    <Activity Sport="Biking">
+      <Id>2019-03-02T09:30:00Z</Id>
+      <Lap StartTime="2019-03-02T09:30:00Z">
+        <Track>
+          <Trackpoint>
+            <Time>2019-03-02T09:30:00Z</Time>
+            <AltitudeMeters>50.0</AltitudeMeters><DistanceMeters>0.0</DistanceMeters>
+            <HeartRateBpm><Value>110</Value></HeartRateBpm><Cadence>80</Cadence>
+            <Extensions><ns3:TPX><ns3:Speed>8.0</ns3:Speed><ns3:Watts>150</ns3:Watts></ns3:TPX></Extensions>
+          </Trackpoint>
+          <Trackpoint>
+            <Time>2019-03-02T09:30:05Z</Time>
+            <AltitudeMeters>53.0</AltitudeMeters><DistanceMeters>40.0</DistanceMeters>
+            <HeartRateBpm><Value>115</Value></HeartRateBpm><Cadence>82</Cadence>
+            <Extensions><ns3:TPX><ns3:Speed>9.0</ns3:Speed><ns3:Watts>170</ns3:Watts></ns3:TPX></Extensions>
+          </Trackpoint>
+        </Track>
+      </Lap>
+    </Activity>
+  </Activities>
+</TrainingCenterDatabase>"#;
+
+#[tokio::test]
+async fn gpx_file_is_imported() {
+    let storage = make_storage();
+    let id = import_file(&storage, "loop.gpx", GPX.as_bytes()).await.unwrap();
+    assert_eq!(id, synthetic_id(ImportFormat::Gpx, 1_588_316_400));
+
+    let act = storage.load_activity(id).await.unwrap();
+    assert_eq!(act.meta["name"], "Morning loop");
+    assert_eq!(act.meta["type"], "Ride");
+    assert_eq!(act.meta["start_date"], "2020-05-01T07:00:00Z");
+    assert_eq!(act.meta["elapsed_time"], 20);
+    assert_eq!(act.meta["total_elevation_gain"], 4.0);
+    let distance = act.meta["distance"].as_f64().unwrap();
+    assert!((distance - 222.4).abs() < 0.5, "distance {}", distance);
+    assert_eq!(act.streams.time, vec![0, 10, 20]);
+    assert_eq!(act.streams.power, vec![200, 220, 240]);
+    assert_eq!(act.streams.heartrate, vec![120, 125, 130]);
+    assert_eq!(act.streams.cadence, Some(vec![85, 88, 90]));
+    assert_eq!(act.streams.temp, Some(vec![18, 0, 0]));
+}
+
+#[tokio::test]
+async fn tcx_file_is_imported() {
+    let storage = make_storage();
+    let id = import_file(&storage, "ride.tcx", TCX.as_bytes()).await.unwrap();
+    assert_eq!(id, synthetic_id(ImportFormat::Tcx, 1_551_519_000));
+
+    let act = storage.load_activity(id).await.unwrap();
+    assert_eq!(act.meta["type"], "Ride");
+    assert_eq!(act.meta["elapsed_time"], 5);
+    assert_eq!(act.meta["distance"], 40.0);
+    assert_eq!(act.meta["total_elevation_gain"], 3.0);
+    assert_eq!(act.meta["max_speed"], 9.0);
+    assert_eq!(act.streams.power, vec![150, 170]);
+    assert_eq!(act.streams.heartrate, vec![110, 115]);
+    assert_eq!(act.streams.velocity_smooth, Some(vec![8.0, 9.0]));
+}
+
+#[tokio::test]
+async fn formats_get_distinct_ids() {
+    let start = 1_600_000_000;
+    let ids = [ImportFormat::Fit, ImportFormat::Gpx, ImportFormat::Tcx].map(|f| synthetic_id(f, start));
+    assert!(ids[0] != ids[1] && ids[1] != ids[2] && ids[0] != ids[2]);
+
+    let storage = make_storage();
+    assert!(import_file(&storage, "broken.gpx", b"<gpx><trk>").await.is_err());
+    assert!(import_file(&storage, "empty.tcx", b"<TrainingCenterDatabase/>").await.is_err());
+}
+
+#[tokio::test]
+async fn noisy_altitude_does_not_inflate_elevation_gain() {
+    // a flat stretch with ±1 m of barometer noise, then a 30 m climb with ±0.8 m
+    let mut ele: Vec<f64> = (0..200).map(|i| if i % 2 == 0 { 101.0 } else { 99.0 }).collect();
+    ele.extend((1..=30).map(|i| 100.0 + i as f64 + if i % 2 == 0 { 0.8 } else { -0.8 }));
+    let start = chrono::DateTime::parse_from_rfc3339("2020-05-01T07:00:00Z").unwrap();
+    let points: String = ele
+        .iter()
+        .enumerate()
+        .map(|(i, e)| {
+            let time = (start + chrono::Duration::seconds(i as i64)).format("%Y-%m-%dT%H:%M:%SZ");
+            format!("<trkpt lat=\"51.5\" lon=\"{:.4}\"><ele>{}</ele><time>{}</time></trkpt>", -0.12 + i as f64 * 0.0001, e, time)
+        })
+        .collect();
+    let gpx = format!(r#"<?xml version="1.0"?><gpx version="1.1" xmlns="http://www.topografix.com/GPX/1/1"><trk><trkseg>{}</trkseg></trk></gpx>"#, points);
+
+    let storage = make_storage();
+    let id = import_file(&storage, "noisy.gpx", gpx.as_bytes()).await.unwrap();
+    let gain = storage.load_activity(id).await.unwrap().meta["total_elevation_gain"].as_f64().unwrap();
+    // from the lowest reading of the flat to the top of the climb
+    assert!((gain - 31.8).abs() < 1e-6, "gain {}", gain);
+}
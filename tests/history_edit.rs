@@ -0,0 +1,102 @@
+use abcy_data::{storage::Storage, utils::Storage as StorageCfg};
+use actix_web::{test, App};
+use serde_json::json;
+use tempfile::tempdir;
+
+fn make_storage() -> Storage {
+    let dir = tempdir().unwrap();
+    let cfg = StorageCfg { data_dir: dir.path().to_str().unwrap().into(), download_count: 1, user: "t".into() };
+    Storage::new(&cfg)
+}
+
+fn today() -> String {
+    chrono::Utc::now().date_naive().to_string()
+}
+
+#[tokio::test]
+async fn backdated_entries_are_sorted() {
+    let storage = make_storage();
+    storage.add_ftp("2024-05-01", 300.0).await.unwrap();
+    storage.add_ftp("2024-01-10", 280.0).await.unwrap();
+    storage.set_ftp(310.0).await.unwrap();
+    storage.add_ftp("2024-03-01", 290.0).await.unwrap();
+
+    let hist = storage.get_ftp_history().await.unwrap();
+    let dates: Vec<&str> = hist.iter().map(|e| e.date.as_str()).collect();
+    assert_eq!(dates, vec!["2024-01-10", "2024-03-01", "2024-05-01", today().as_str()]);
+    assert_eq!(storage.current_ftp().await.unwrap(), 310.0);
+    assert_eq!(storage.ftp_at("2024-04-15").await.unwrap(), 290.0);
+    assert_eq!(storage.ftp_history(Some(1)).await.unwrap()[0].ftp, 310.0);
+
+    // fixing a typo replaces the entry of that day
+    storage.update_ftp_entry("2024-03-01", 295.0).await.unwrap();
+    assert_eq!(storage.ftp_at("2024-03-02").await.unwrap(), 295.0);
+    assert_eq!(storage.get_ftp_history().await.unwrap().len(), 4);
+    assert!(storage.delete_ftp_entry("2024-03-01").await.unwrap());
+    assert!(!storage.delete_ftp_entry("2024-03-01").await.unwrap());
+    assert_eq!(storage.ftp_at("2024-03-02").await.unwrap(), 280.0);
+}
+
+#[tokio::test]
+async fn wkg_follows_merged_timelines() {
+    let storage = make_storage();
+    storage.add_ftp("2024-01-01", 250.0).await.unwrap();
+    storage.add_weight("2024-02-01", 70.0).await.unwrap();
+    storage.add_ftp("2024-03-01", 280.0).await.unwrap();
+    storage.add_weight("2024-04-01", 80.0).await.unwrap();
+
+    let wkg = storage.get_wkg_history().await.unwrap();
+    let points: Vec<(&str, f64)> = wkg.iter().map(|e| (e.date.as_str(), e.wkg)).collect();
+    assert_eq!(
+        points,
+        vec![("2024-01-01", 250.0 / 70.0), ("2024-02-01", 250.0 / 70.0), ("2024-03-01", 4.0), ("2024-04-01", 3.5)]
+    );
+    assert_eq!(storage.current_wkg().await.unwrap(), 3.5);
+
+    storage.update_weight_entry("2024-04-01", 70.0).await.unwrap();
+    assert_eq!(storage.current_wkg().await.unwrap(), 4.0);
+    assert!(storage.delete_ftp_entry("2024-03-01").await.unwrap());
+    let wkg = storage.get_wkg_history().await.unwrap();
+    assert_eq!(wkg.iter().map(|e| e.date.as_str()).collect::<Vec<_>>(), vec!["2024-01-01", "2024-02-01", "2024-04-01"]);
+    assert_eq!(storage.current_wkg().await.unwrap(), 250.0 / 70.0);
+}
+
+#[actix_rt::test]
+async fn history_endpoints() {
+    let storage = make_storage();
+    let app = test::init_service(
+        App::new()
+            .app_data(actix_web::web::Data::new(storage.clone()))
+            .configure(abcy_data::web::configure),
+    )
+    .await;
+
+    let req = test::TestRequest::post().uri("/ftp").set_json(json!({"ftp": 265.0, "date": "2024-02-03"})).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+    let req = test::TestRequest::post().uri("/ftp").set_json(json!({"ftp": 265.0, "date": "03/02/2024"})).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    let req = test::TestRequest::post().uri("/weight").set_json(json!({"weight": 71.5, "date": "2024-2-3"})).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+    let req = test::TestRequest::post().uri("/weight").set_json(json!({"weight": 72.0})).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+    let req = test::TestRequest::get().uri("/weight/history").to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body, json!([{"date": today(), "weight": 72.0}, {"date": "2024-02-03", "weight": 71.5}]));
+
+    let req = test::TestRequest::put().uri("/ftp/history/2024-02-03").set_json(json!({"ftp": 270.0})).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+    let req = test::TestRequest::put().uri("/weight/history/2024-02-03").set_json(json!({"weight": 67.5})).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+    let req = test::TestRequest::get().uri("/wkg/history").to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body[1], json!({"date": "2024-02-03", "wkg": 4.0}));
+
+    let req = test::TestRequest::delete().uri("/weight/history/2024-02-03").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 204);
+    let req = test::TestRequest::delete().uri("/weight/history/2024-02-03").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+    let req = test::TestRequest::delete().uri("/ftp/history/yesterday").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    assert_eq!(storage.get_weight_history().await.unwrap().len(), 1);
+}
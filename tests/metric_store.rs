@@ -0,0 +1,111 @@
+use abcy_data::{
+    blob::FsBlobStore,
+    storage::Storage,
+    store::{HistoryKind, HistoryPoint, JsonStore, MetricStore},
+    utils::{Config, Storage as StorageCfg},
+};
+use serde_json::json;
+use std::sync::Arc;
+use tempfile::tempdir;
+
+mod common;
+
+fn make_storage(dir: &std::path::Path) -> Storage {
+    let cfg = StorageCfg { data_dir: dir.to_str().unwrap().into(), download_count: 1, user: "t".into() };
+    Storage::new(&cfg)
+}
+
+fn config(dir: &std::path::Path, database: &str) -> Config {
+    common::test_config_with("http://localhost", dir, database)
+}
+
+fn point(date: &str, value: f64) -> HistoryPoint {
+    HistoryPoint { date: date.into(), value }
+}
+
+/// Behaviour every backend must share.
+async fn exercise(store: Arc<dyn MetricStore>, dir: &std::path::Path) {
+    store.append_history(HistoryKind::Ftp, point("2024-01-01", 250.0)).await.unwrap();
+    store.append_history(HistoryKind::Ftp, point("2024-03-01", 260.0)).await.unwrap();
+    store.append_history(HistoryKind::Enduro, point("2024-03-01", 12.5)).await.unwrap();
+    assert_eq!(store.history(HistoryKind::Ftp).await.unwrap(), vec![point("2024-01-01", 250.0), point("2024-03-01", 260.0)]);
+    assert_eq!(store.history_between(HistoryKind::Ftp, Some("2024-02-01"), None).await.unwrap(), vec![point("2024-03-01", 260.0)]);
+    assert_eq!(store.history(HistoryKind::Enduro).await.unwrap().len(), 1);
+    store.replace_history(HistoryKind::Ftp, &[point("2023-01-01", 230.0)]).await.unwrap();
+    assert_eq!(store.history(HistoryKind::Ftp).await.unwrap(), vec![point("2023-01-01", 230.0)]);
+
+    assert!(store.summaries().await.unwrap().is_none());
+    let storage = make_storage(dir).with_store(store.clone());
+    let streams = json!({"time": [0, 3600], "watts": [200, 200]});
+    for (id, date) in [(1, "2024-01-05T08:00:00Z"), (2, "2024-02-05T08:00:00Z"), (3, "2024-03-05T08:00:00Z")] {
+        let meta = json!({"id": id, "name": "ride", "start_date": date, "distance": 1000.0, "type": "Ride"});
+        storage.save(&meta, &streams).await.unwrap();
+    }
+    assert_eq!(storage.current_ftp().await.unwrap(), 230.0);
+    let between = storage.activity_summaries_between(Some("2024-02-01"), Some("2024-03-01")).await.unwrap();
+    assert_eq!(between.iter().map(|s| s.id).collect::<Vec<_>>(), vec![2]);
+    let ifv = between[0].intensity_factor.unwrap();
+    assert!((ifv - 200.0 / 230.0).abs() < 1e-6);
+
+    assert!(storage.delete_activity(3).await.unwrap());
+    let ids: Vec<u64> = storage.list_activities(None).await.unwrap().iter().map(|a| a.id).collect();
+    assert_eq!(ids, vec![2, 1]);
+}
+
+#[tokio::test]
+async fn json_store_keeps_history_files() {
+    let dir = tempdir().unwrap();
+    let base = dir.path().join("t");
+    exercise(Arc::new(JsonStore::new(Arc::new(FsBlobStore::new(&base)))), dir.path()).await;
+
+    let ftp: serde_json::Value = serde_json::from_slice(&std::fs::read(base.join("ftp.json")).unwrap()).unwrap();
+    assert_eq!(ftp, json!([{"date": "2023-01-01", "ftp": 230.0}]));
+    let enduro: serde_json::Value = serde_json::from_slice(&std::fs::read(base.join("enduro.json")).unwrap()).unwrap();
+    assert_eq!(enduro, json!([{"date": "2024-03-01", "score": 12.5}]));
+}
+
+#[tokio::test]
+async fn json_backend_is_the_default() {
+    let dir = tempdir().unwrap();
+    let storage = Storage::from_config(&config(dir.path(), "")).await.unwrap();
+    storage.set_weight(70.0).await.unwrap();
+    assert!(dir.path().join("t").join("weight.json").exists());
+}
+
+#[cfg(not(feature = "sqlite"))]
+#[tokio::test]
+async fn sqlite_backend_requires_feature() {
+    let dir = tempdir().unwrap();
+    let cfg = config(dir.path(), "[database]\nbackend = \"sqlite\"\n");
+    assert!(Storage::from_config(&cfg).await.is_err());
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn sqlite_store_matches_json_store() {
+    let dir = tempdir().unwrap();
+    let store = abcy_data::store::SqliteStore::open(dir.path().join("db.sqlite")).unwrap();
+    exercise(Arc::new(store), dir.path()).await;
+    assert!(!dir.path().join("t").join("ftp.json").exists());
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn sqlite_backend_imports_json_histories() {
+    let dir = tempdir().unwrap();
+    let json_storage = make_storage(dir.path());
+    json_storage.set_ftp(270.0).await.unwrap();
+
+    let db_path = dir.path().join("abcy.sqlite");
+    let cfg = config(dir.path(), &format!("[database]\nbackend = \"sqlite\"\npath = \"{}\"\n", db_path.to_str().unwrap()));
+    let storage = Storage::from_config(&cfg).await.unwrap();
+    assert_eq!(storage.current_ftp().await.unwrap(), 270.0);
+    assert_eq!(storage.get_ftp_history().await.unwrap().len(), 2);
+
+    storage.set_ftp(280.0).await.unwrap();
+    let conn = rusqlite::Connection::open(&db_path).unwrap();
+    let max: f64 = conn
+        .query_row("SELECT MAX(value) FROM history WHERE kind = 'ftp'", [], |r| r.get(0))
+        .unwrap();
+    assert_eq!(max, 280.0);
+}
@@ -0,0 +1,102 @@
+use abcy_data::{
+    pmc::pmc_series,
+    storage::Storage,
+    utils::{Pmc, Storage as StorageCfg},
+};
+use actix_web::{test::{call_and_read_body_json, call_service, init_service, TestRequest}, App};
+use chrono::NaiveDate;
+use serde_json::json;
+use std::collections::BTreeMap;
+use tempfile::tempdir;
+
+mod common;
+
+fn make_storage() -> Storage {
+    let dir = tempdir().unwrap();
+    let cfg = StorageCfg { data_dir: dir.path().to_str().unwrap().into(), download_count: 1, user: "t".into() };
+    Storage::new(&cfg)
+}
+
+fn day(s: &str) -> NaiveDate {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+}
+
+#[test]
+fn loads_decay_over_rest_days() {
+    let daily = BTreeMap::from([(day("2024-01-01"), 100.0), (day("2024-01-03"), 50.0)]);
+    let series = pmc_series(&daily, day("2024-01-01"), day("2024-01-04"), &Pmc::default());
+    assert_eq!(series.iter().map(|d| d.tss).collect::<Vec<_>>(), vec![100.0, 0.0, 50.0, 0.0]);
+    let ctl1 = 100.0 / 42.0;
+    let atl1 = 100.0 / 7.0;
+    assert!((series[0].ctl - ctl1).abs() < 1e-12);
+    assert!((series[0].atl - atl1).abs() < 1e-12);
+    assert!((series[1].ctl - ctl1 * 41.0 / 42.0).abs() < 1e-12);
+    assert!((series[1].atl - atl1 * 6.0 / 7.0).abs() < 1e-12);
+    let ctl3 = ctl1 * 41.0 / 42.0 + (50.0 - ctl1 * 41.0 / 42.0) / 42.0;
+    assert!((series[2].ctl - ctl3).abs() < 1e-12);
+    assert!(series.iter().all(|d| (d.tsb - (d.ctl - d.atl)).abs() < 1e-12));
+
+    // earlier load carries into a later window
+    let window = pmc_series(&daily, day("2024-01-03"), day("2024-01-04"), &Pmc::default());
+    assert_eq!(window, series[2..]);
+    let empty = pmc_series(&BTreeMap::new(), day("2024-01-01"), day("2024-01-02"), &Pmc::default());
+    assert!(empty.iter().all(|d| d.ctl == 0.0 && d.atl == 0.0));
+}
+
+#[actix_rt::test]
+async fn pmc_endpoint() {
+    let storage = make_storage();
+    storage.add_ftp("2024-01-01", 200.0).await.unwrap();
+    // one hour at FTP is 100 TSS
+    let hour = json!({"time": [0, 3600], "watts": [200, 200]});
+    storage.save(&json!({"id": 1, "name": "a", "start_date": "2024-03-01T07:00:00Z", "distance": 1.0}), &hour).await.unwrap();
+    storage.save(&json!({"id": 2, "name": "b", "start_date": "2024-03-01T18:00:00Z", "distance": 1.0}), &hour).await.unwrap();
+    storage.save(&json!({"id": 3, "name": "c", "start_date": "2024-03-04T07:00:00Z", "distance": 1.0}), &hour).await.unwrap();
+    let dir = tempdir().unwrap();
+    let cfg = common::test_config_with("http://localhost", dir.path(), "[pmc]\nctl_days = 28\n");
+    assert_eq!((cfg.pmc.ctl_days, cfg.pmc.atl_days), (28.0, 7.0));
+    let app = init_service(
+        App::new()
+            .app_data(actix_web::web::Data::new(storage.clone()))
+            .app_data(actix_web::web::Data::new(cfg.clone()))
+            .configure(abcy_data::web::configure),
+    )
+    .await;
+
+    let req = TestRequest::get().uri("/pmc?from=2024-02-28&to=2024-03-05").to_request();
+    let body: Vec<serde_json::Value> = call_and_read_body_json(&app, req).await;
+    assert_eq!(body.len(), 7);
+    assert_eq!(body[0]["date"], "2024-02-28");
+    assert_eq!(body[0]["ctl"], 0.0);
+    let tss: Vec<f64> = body.iter().map(|d| d["tss"].as_f64().unwrap().round()).collect();
+    assert_eq!(tss, vec![0.0, 0.0, 200.0, 0.0, 0.0, 100.0, 0.0]);
+    let expected = storage.pmc(Some(day("2024-02-28")), Some(day("2024-03-05")), &cfg.pmc).await.unwrap();
+    assert_eq!(body[5]["ctl"].as_f64().unwrap(), expected[5].ctl);
+    assert!((body[2]["ctl"].as_f64().unwrap() - 200.0 / 28.0).abs() < 1e-6);
+
+    let req = TestRequest::get().uri("/pmc?from=2024-03-01&to=2024-03-01&ctl_days=1&atl_days=1").to_request();
+    let body: Vec<serde_json::Value> = call_and_read_body_json(&app, req).await;
+    assert!((body[0]["ctl"].as_f64().unwrap() - 200.0).abs() < 1e-6);
+    assert!(body[0]["tsb"].as_f64().unwrap().abs() < 1e-9);
+
+    let req = TestRequest::get().uri("/pmc?to=2024-03-05").to_request();
+    let body: Vec<serde_json::Value> = call_and_read_body_json(&app, req).await;
+    assert_eq!(body.len(), 90);
+    assert_eq!(body[89]["date"], "2024-03-05");
+
+    let req = TestRequest::get().uri("/pmc?from=2014-03-08&to=2024-03-04").to_request();
+    let body: Vec<serde_json::Value> = call_and_read_body_json(&app, req).await;
+    assert_eq!(body.len(), 3650);
+
+    for uri in [
+        "/pmc?from=2024-03-05&to=2024-03-01",
+        "/pmc?from=March",
+        "/pmc?atl_days=0",
+        "/pmc?from=2014-03-07&to=2024-03-04",
+        "/pmc?from=0001-01-01",
+        "/pmc?from=0001-01-01&to=9999-12-31",
+    ] {
+        let req = TestRequest::get().uri(uri).to_request();
+        assert_eq!(call_service(&app, req).await.status(), 400, "{}", uri);
+    }
+}
@@ -0,0 +1,95 @@
+use abcy_data::{
+    power::{mean_max, MeanMax},
+    schema::parse_streams,
+    storage::Storage,
+    utils::Storage as StorageCfg,
+};
+use actix_web::{test::{call_and_read_body_json, call_service, init_service, TestRequest}, App};
+use serde_json::json;
+use tempfile::tempdir;
+
+fn make_storage(dir: &std::path::Path) -> Storage {
+    let cfg = StorageCfg { data_dir: dir.to_str().unwrap().into(), download_count: 1, user: "t".into() };
+    Storage::new(&cfg)
+}
+
+fn curve(streams: serde_json::Value) -> Vec<(usize, f64)> {
+    mean_max(&parse_streams(&streams).unwrap()).into_iter().map(|m| (m.duration, m.watts)).collect()
+}
+
+#[test]
+fn mean_max_of_streams() {
+    let step = json!({"time": (0..10).collect::<Vec<_>>(), "watts": [100, 100, 100, 100, 100, 300, 300, 300, 300, 300]});
+    assert_eq!(curve(step), vec![(1, 300.0), (2, 300.0), (5, 300.0), (10, 200.0)]);
+
+    // short gaps hold the previous sample, long gaps are pauses
+    let held = json!({"time": [0, 3, 4], "watts": [200, 200, 0]});
+    assert_eq!(curve(held), vec![(1, 200.0), (2, 200.0), (5, 160.0)]);
+    let paused = json!({"time": [0, 20], "watts": [300, 300]});
+    assert_eq!(curve(paused)[..4], [(1, 300.0), (2, 150.0), (5, 60.0), (10, 30.0)]);
+
+    assert!(curve(json!({"time": [0, 1, 2], "heartrate": [120, 121, 122]})).is_empty());
+}
+
+#[actix_rt::test]
+async fn best_efforts_across_activities() {
+    let dir = tempdir().unwrap();
+    let storage = make_storage(dir.path());
+    let sprint = json!({"time": (0..5).collect::<Vec<_>>(), "watts": [800, 900, 700, 100, 100]});
+    let steady = json!({"time": (0..60).collect::<Vec<_>>(), "watts": vec![250; 60]});
+    storage.save(&json!({"id": 1, "name": "sprint", "start_date": "2024-03-01T07:00:00Z", "distance": 1.0}), &sprint).await.unwrap();
+    storage.save(&json!({"id": 2, "name": "steady", "start_date": "2024-03-05T07:00:00Z", "distance": 1.0}), &steady).await.unwrap();
+    assert!(dir.path().join("t/2024/1/power_curve.json.zst").exists());
+
+    let per_activity = storage.activity_power_curve(1).await.unwrap();
+    assert_eq!(per_activity[0], MeanMax { duration: 1, watts: 900.0 });
+    assert!(storage.activity_power_curve(9).await.is_err());
+
+    // a missing cache is rebuilt from the streams
+    std::fs::remove_file(dir.path().join("t/2024/2/power_curve.json.zst")).unwrap();
+    let best = storage.power_curve(None, None).await.unwrap();
+    assert!(dir.path().join("t/2024/2/power_curve.json.zst").exists());
+    let summary: Vec<_> = best.iter().map(|b| (b.duration, b.watts, b.activity_id)).collect();
+    assert_eq!(summary[..3], [(1, 900.0, 1), (2, 850.0, 1), (5, 520.0, 1)]);
+    assert_eq!(summary[3..], [(10, 250.0, 2), (15, 250.0, 2), (20, 250.0, 2), (30, 250.0, 2), (45, 250.0, 2), (60, 250.0, 2)]);
+    assert_eq!(best[0].start_date, "2024-03-01T07:00:00Z");
+
+    let day = |s| Some(chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap());
+    let later = storage.power_curve(day("2024-03-02"), None).await.unwrap();
+    assert!(later.iter().all(|b| b.activity_id == 2));
+    let earlier = storage.power_curve(None, day("2024-03-01")).await.unwrap();
+    assert!(earlier.iter().all(|b| b.activity_id == 1));
+    assert!(storage.power_curve(day("2024-04-01"), None).await.unwrap().is_empty());
+}
+
+#[actix_rt::test]
+async fn power_curve_endpoints() {
+    let dir = tempdir().unwrap();
+    let storage = make_storage(dir.path());
+    let ride = json!({"time": (0..10).collect::<Vec<_>>(), "watts": vec![300; 10]});
+    storage.save(&json!({"id": 1, "name": "a", "start_date": "2024-03-01T07:00:00Z", "distance": 1.0}), &ride).await.unwrap();
+    let app = init_service(
+        App::new()
+            .app_data(actix_web::web::Data::new(storage.clone()))
+            .configure(abcy_data::web::configure),
+    )
+    .await;
+
+    let req = TestRequest::get().uri("/activity/1/power-curve").to_request();
+    let body: serde_json::Value = call_and_read_body_json(&app, req).await;
+    assert_eq!(body.as_array().unwrap().len(), 4);
+    assert_eq!(body[3], json!({"duration": 10, "watts": 300.0}));
+    let req = TestRequest::get().uri("/activity/2/power-curve").to_request();
+    assert_eq!(call_service(&app, req).await.status(), 404);
+
+    let req = TestRequest::get().uri("/power-curve?from=2024-03-01&to=2024-03-01").to_request();
+    let body: serde_json::Value = call_and_read_body_json(&app, req).await;
+    assert_eq!(body[0], json!({"duration": 1, "watts": 300.0, "activity_id": 1, "start_date": "2024-03-01T07:00:00Z"}));
+    let req = TestRequest::get().uri("/power-curve?to=2024-02-01").to_request();
+    let body: serde_json::Value = call_and_read_body_json(&app, req).await;
+    assert_eq!(body, json!([]));
+    for uri in ["/power-curve?from=2024-13-01", "/power-curve?from=2024-03-02&to=2024-03-01"] {
+        let req = TestRequest::get().uri(uri).to_request();
+        assert_eq!(call_service(&app, req).await.status(), 400);
+    }
+}
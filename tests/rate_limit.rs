@@ -0,0 +1,86 @@
+use abcy_data::{auth::Auth, ratelimit::{next_daily_window, next_short_window, RateLimiter}};
+use reqwest::header::HeaderMap;
+use chrono::{Duration, TimeZone, Utc};
+use tempfile::{tempdir, TempDir};
+
+mod common;
+
+fn make_auth(base_url: &str) -> (TempDir, Auth) {
+    let dir = tempdir().unwrap();
+    common::write_valid_token(dir.path());
+    let cfg = common::test_config(base_url, dir.path());
+    (dir, Auth::new(cfg))
+}
+
+#[test]
+fn window_boundaries() {
+    let now = Utc.with_ymd_and_hms(2024, 3, 1, 12, 7, 30).unwrap();
+    assert_eq!(next_short_window(now), Utc.with_ymd_and_hms(2024, 3, 1, 12, 15, 0).unwrap());
+    assert_eq!(next_daily_window(now), Utc.with_ymd_and_hms(2024, 3, 2, 0, 0, 0).unwrap());
+}
+
+#[test]
+fn pause_ends_with_the_window() {
+    let limiter = RateLimiter::default();
+    let mut headers = HeaderMap::new();
+    headers.insert("x-ratelimit-limit", "100,1000".parse().unwrap());
+    headers.insert("x-ratelimit-usage", "100,340".parse().unwrap());
+    let now = Utc.with_ymd_and_hms(2024, 3, 1, 12, 7, 30).unwrap();
+    limiter.update(&headers, now);
+    assert_eq!(limiter.status().paused_until, Some(next_short_window(now)));
+
+    // an error response without budget headers once the window rolled over
+    let later = next_short_window(now) + Duration::seconds(1);
+    limiter.update(&HeaderMap::new(), later);
+    let status = limiter.status();
+    assert_eq!((status.paused_until, status.short_usage, status.daily_usage), (None, None, Some(340)));
+    assert!(limiter.wait_duration(later).is_none());
+
+    headers.insert("x-ratelimit-usage", "3,1000".parse().unwrap());
+    limiter.update(&headers, later);
+    assert_eq!(limiter.status().reason.as_deref(), Some("daily"));
+    let tomorrow = next_daily_window(later) + Duration::seconds(1);
+    limiter.update(&HeaderMap::new(), tomorrow);
+    let status = limiter.status();
+    assert_eq!((status.paused_until, status.short_usage, status.daily_usage), (None, None, None));
+}
+
+#[tokio::test]
+async fn budget_is_tracked_from_headers() {
+    let mut server = mockito::Server::new_async().await;
+    server
+        .mock("GET", "/athlete")
+        .with_header("X-RateLimit-Limit", "100,1000")
+        .with_header("X-RateLimit-Usage", "12,340")
+        .with_body("{}")
+        .create_async()
+        .await;
+    let (_dir, auth) = make_auth(&server.url());
+    let _: serde_json::Value = auth.get_json(&format!("{}/athlete", server.url())).await.unwrap();
+    let status = auth.rate_limit();
+    assert_eq!(status.short_limit, Some(100));
+    assert_eq!(status.short_usage, Some(12));
+    assert_eq!(status.daily_limit, Some(1000));
+    assert_eq!(status.daily_usage, Some(340));
+    assert!(status.paused_until.is_none());
+}
+
+#[tokio::test]
+async fn exhausted_window_pauses_requests() {
+    let mut server = mockito::Server::new_async().await;
+    server
+        .mock("GET", "/athlete")
+        .with_header("X-RateLimit-Limit", "100,1000")
+        .with_header("X-RateLimit-Usage", "100,340")
+        .with_body("{}")
+        .create_async()
+        .await;
+    let (_dir, auth) = make_auth(&server.url());
+    let before = Utc::now();
+    let _: serde_json::Value = auth.get_json(&format!("{}/athlete", server.url())).await.unwrap();
+    let status = auth.rate_limit();
+    assert_eq!(status.reason.as_deref(), Some("short"));
+    let paused = status.paused_until.unwrap();
+    assert!(paused > before);
+    assert!(paused <= next_short_window(Utc::now()));
+}
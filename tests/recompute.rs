@@ -0,0 +1,111 @@
+use abcy_data::{
+    recompute::{recompute, RecomputeOptions},
+    storage::Storage,
+    utils::Storage as StorageCfg,
+};
+use actix_web::{test, App};
+use chrono::NaiveDate;
+use serde_json::json;
+use tempfile::tempdir;
+
+fn make_storage() -> Storage {
+    let dir = tempdir().unwrap();
+    let cfg = StorageCfg { data_dir: dir.path().to_str().unwrap().into(), download_count: 1, user: "t".into() };
+    Storage::new(&cfg)
+}
+
+fn date(s: &str) -> Option<NaiveDate> {
+    Some(NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap())
+}
+
+/// Rides saved while FTP was 200, then a 250 test backdated to June.
+async fn archive() -> Storage {
+    let storage = make_storage();
+    storage.add_ftp("2024-01-01", 200.0).await.unwrap();
+    let power = json!({"time": [0, 3600], "watts": [200, 200]});
+    storage.save(&json!({"id": 1, "name": "spring", "start_date": "2024-03-01T08:00:00Z", "distance": 1.0}), &power).await.unwrap();
+    storage.save(&json!({"id": 2, "name": "summer", "start_date": "2024-07-01T08:00:00Z", "distance": 1.0}), &power).await.unwrap();
+    storage.save(&json!({"id": 3, "name": "run", "start_date": "2024-07-02T08:00:00Z", "distance": 1.0}), &json!({"time": [0, 60]})).await.unwrap();
+    storage.add_ftp("2024-06-01", 250.0).await.unwrap();
+    storage
+}
+
+#[tokio::test]
+async fn recompute_applies_backdated_ftp() {
+    let storage = archive().await;
+
+    let report = recompute(&storage, &RecomputeOptions { dry_run: true, ..Default::default() }).await.unwrap();
+    assert!(report.dry_run);
+    assert_eq!((report.scanned, report.updated, report.unchanged, report.skipped), (3, 2, 0, 1));
+    // W'bal metrics are only stored by recompute
+    let spring = &report.changes[0];
+    assert_eq!(spring.id, 1);
+    assert!(spring.intensity_factor.is_none());
+    assert_eq!(spring.matches_burned.as_ref().unwrap().before, None);
+    assert_eq!(spring.matches_burned.as_ref().unwrap().after, 0.0);
+    assert!(spring.min_wbal.is_some());
+    let change = &report.changes[1];
+    assert_eq!(change.id, 2);
+    assert_eq!(change.ftp, 250.0);
+    assert!(change.normalized_power.is_none());
+    let intensity = change.intensity_factor.as_ref().unwrap();
+    assert!((intensity.before.unwrap() - 1.0).abs() < 1e-9);
+    assert!((intensity.after - 0.8).abs() < 1e-9);
+    assert!((change.training_stress_score.as_ref().unwrap().after - 64.0).abs() < 1e-6);
+    // a dry run leaves the files alone
+    let meta = storage.load_activity(2).await.unwrap().meta;
+    assert!((meta["intensity_factor"].as_f64().unwrap() - 1.0).abs() < 1e-9);
+
+    let report = recompute(&storage, &RecomputeOptions::default()).await.unwrap();
+    assert_eq!(report.updated, 2);
+    let meta = storage.load_activity(2).await.unwrap().meta;
+    assert!((meta["intensity_factor"].as_f64().unwrap() - 0.8).abs() < 1e-9);
+    let summaries = storage.activity_summaries().await.unwrap();
+    let summer = summaries.iter().find(|s| s.id == 2).unwrap();
+    assert!((summer.training_stress_score.unwrap() - 64.0).abs() < 1e-6);
+
+    let report = recompute(&storage, &RecomputeOptions::default()).await.unwrap();
+    assert_eq!((report.updated, report.unchanged, report.skipped), (0, 2, 1));
+    assert!(report.changes.is_empty());
+}
+
+#[tokio::test]
+async fn recompute_limits_to_date_range() {
+    let storage = archive().await;
+    storage.update_ftp_entry("2024-01-01", 210.0).await.unwrap();
+
+    let opts = RecomputeOptions { from: date("2024-01-01"), to: date("2024-03-01"), dry_run: false };
+    let report = recompute(&storage, &opts).await.unwrap();
+    assert_eq!(report.scanned, 1);
+    assert_eq!(report.changes.iter().map(|c| c.id).collect::<Vec<_>>(), vec![1]);
+    assert!((storage.load_activity(1).await.unwrap().meta["intensity_factor"].as_f64().unwrap() - 200.0 / 210.0).abs() < 1e-9);
+    // outside the range the stale values remain
+    assert!((storage.load_activity(2).await.unwrap().meta["intensity_factor"].as_f64().unwrap() - 1.0).abs() < 1e-9);
+}
+
+#[actix_rt::test]
+async fn recompute_endpoint() {
+    let storage = archive().await;
+    let app = test::init_service(
+        App::new()
+            .app_data(actix_web::web::Data::new(storage.clone()))
+            .configure(abcy_data::web::configure),
+    )
+    .await;
+
+    let req = test::TestRequest::post().uri("/admin/recompute?from=2024-06-01&dry_run=true").to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["scanned"], 2);
+    assert_eq!(body["updated"], 1);
+    assert_eq!(body["changes"][0]["id"], 2);
+    assert_eq!(body["changes"][0]["intensity_factor"]["after"], 0.8);
+    assert!(body["changes"][0].get("normalized_power").is_none());
+
+    let req = test::TestRequest::post().uri("/admin/recompute").to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["dry_run"], false);
+    assert_eq!(body["updated"], 2);
+
+    let req = test::TestRequest::post().uri("/admin/recompute?to=July").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+}
@@ -0,0 +1,128 @@
+use abcy_data::{auth::Auth, error::StravaError};
+use mockito::Matcher;
+use serde_json::json;
+use tempfile::{tempdir, TempDir};
+
+mod common;
+
+fn make_auth(base_url: &str, token: serde_json::Value) -> (TempDir, Auth, std::path::PathBuf) {
+    let dir = tempdir().unwrap();
+    let token_path = common::write_token(dir.path(), &token);
+    let cfg = common::test_config(base_url, dir.path());
+    (dir, Auth::new(cfg), token_path)
+}
+
+#[tokio::test]
+async fn expired_token_is_refreshed() {
+    let mut server = mockito::Server::new_async().await;
+    let expires_at = chrono::Utc::now().timestamp() + 21600;
+    let refresh = server
+        .mock("POST", "/oauth/token")
+        .match_body(Matcher::AllOf(vec![
+            Matcher::UrlEncoded("grant_type".into(), "refresh_token".into()),
+            Matcher::UrlEncoded("refresh_token".into(), "r1".into()),
+        ]))
+        .with_body(json!({"access_token": "new", "expires_at": expires_at, "refresh_token": "r2"}).to_string())
+        .create_async()
+        .await;
+    let athlete = server
+        .mock("GET", "/athlete")
+        .match_header("authorization", "Bearer new")
+        .with_body(json!({"id": 7}).to_string())
+        .create_async()
+        .await;
+
+    let expired = json!({"access_token": "old", "expires_at": 0, "refresh_token": "r1"});
+    let (_dir, auth, token_path) = make_auth(&server.url(), expired);
+    let athlete_json: serde_json::Value = auth.get_json(&format!("{}/athlete", server.url())).await.unwrap();
+    assert_eq!(athlete_json["id"], 7);
+    refresh.assert_async().await;
+    athlete.assert_async().await;
+
+    let saved: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(token_path).unwrap()).unwrap();
+    assert_eq!(saved["access_token"], "new");
+    assert_eq!(saved["refresh_token"], "r2");
+    assert_eq!(saved["expires_at"], expires_at);
+}
+
+#[tokio::test]
+async fn unauthorized_response_triggers_refresh() {
+    let mut server = mockito::Server::new_async().await;
+    let rejected = server
+        .mock("GET", "/athlete")
+        .match_header("authorization", "Bearer revoked")
+        .with_status(401)
+        .create_async()
+        .await;
+    let refresh = server
+        .mock("POST", "/oauth/token")
+        .with_body(json!({"access_token": "new", "expires_at": chrono::Utc::now().timestamp() + 21600}).to_string())
+        .create_async()
+        .await;
+    let retried = server
+        .mock("GET", "/athlete")
+        .match_header("authorization", "Bearer new")
+        .with_body(json!({"id": 7}).to_string())
+        .create_async()
+        .await;
+
+    let valid = json!({"access_token": "revoked", "expires_at": chrono::Utc::now().timestamp() + 3600, "refresh_token": "r1", "athlete_id": 7});
+    let (_dir, auth, token_path) = make_auth(&server.url(), valid);
+    let athlete_json: serde_json::Value = auth.get_json(&format!("{}/athlete", server.url())).await.unwrap();
+    assert_eq!(athlete_json["id"], 7);
+    rejected.assert_async().await;
+    refresh.assert_async().await;
+    retried.assert_async().await;
+
+    // the refresh token is kept when Strava does not rotate it
+    let saved: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(token_path).unwrap()).unwrap();
+    assert_eq!(saved["refresh_token"], "r1");
+    assert_eq!(saved["athlete_id"], 7);
+}
+
+#[tokio::test]
+async fn concurrent_requests_share_one_refresh() {
+    let mut server = mockito::Server::new_async().await;
+    // the rotated refresh token only works once
+    let refresh = server
+        .mock("POST", "/oauth/token")
+        .match_body(Matcher::UrlEncoded("refresh_token".into(), "r1".into()))
+        .with_body(json!({"access_token": "new", "expires_at": chrono::Utc::now().timestamp() + 21600, "refresh_token": "r2"}).to_string())
+        .expect(1)
+        .create_async()
+        .await;
+    let athlete = server
+        .mock("GET", "/athlete")
+        .match_header("authorization", "Bearer new")
+        .with_body(json!({"id": 7}).to_string())
+        .expect(4)
+        .create_async()
+        .await;
+
+    let expired = json!({"access_token": "old", "expires_at": 0, "refresh_token": "r1"});
+    let (_dir, auth, _token_path) = make_auth(&server.url(), expired);
+    let url = format!("{}/athlete", server.url());
+    let requests = (0..4).map(|_| auth.get_json::<serde_json::Value>(&url));
+    for res in futures_util::future::join_all(requests).await {
+        assert_eq!(res.unwrap()["id"], 7);
+    }
+    refresh.assert_async().await;
+    athlete.assert_async().await;
+}
+
+#[tokio::test]
+async fn rejected_refresh_is_unauthorized_without_a_browser() {
+    let mut server = mockito::Server::new_async().await;
+    server.mock("POST", "/oauth/token").with_status(400).create_async().await;
+
+    let expired = json!({"access_token": "old", "expires_at": 0, "refresh_token": "r1"});
+    let (dir, auth, _token_path) = make_auth(&server.url(), expired);
+    let err = auth.get_json::<serde_json::Value>(&format!("{}/athlete", server.url())).await.unwrap_err();
+    assert!(matches!(err, StravaError::Unauthorized(_)), "{}", err);
+    assert!(err.to_string().contains("authorize binary"));
+
+    // neither without any refresh token
+    common::write_token(dir.path(), &json!({"access_token": "old", "expires_at": 0}));
+    let err = auth.get_json::<serde_json::Value>(&format!("{}/athlete", server.url())).await.unwrap_err();
+    assert!(matches!(err, StravaError::Unauthorized(_)), "{}", err);
+}
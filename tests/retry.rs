@@ -0,0 +1,136 @@
+use abcy_data::{auth::{Auth, RetryPolicy}, error::StravaError, fetch, storage::{FailureKind, Storage}};
+use mockito::Matcher;
+use serde_json::json;
+use std::time::Duration;
+use tempfile::{tempdir, TempDir};
+
+mod common;
+
+fn make_env(base_url: &str) -> (TempDir, Auth, Storage) {
+    let dir = tempdir().unwrap();
+    common::write_valid_token(dir.path());
+    let cfg = common::test_config(base_url, dir.path());
+    let storage = Storage::new(&cfg.storage);
+    let retry = RetryPolicy { max_retries: 2, base_delay: Duration::from_millis(1), max_delay: Duration::from_millis(5) };
+    (dir, Auth::new(cfg).with_retry_policy(retry), storage)
+}
+
+#[tokio::test]
+async fn transient_errors_are_retried() {
+    let mut server = mockito::Server::new_async().await;
+    let failing = server.mock("GET", "/athlete").with_status(503).expect(2).create_async().await;
+    let ok = server.mock("GET", "/athlete").with_body(r#"{"id": 7}"#).expect(1).create_async().await;
+    let (_dir, auth, _) = make_env(&server.url());
+    let v: serde_json::Value = auth.get_json(&format!("{}/athlete", server.url())).await.unwrap();
+    assert_eq!(v["id"], 7);
+    failing.assert_async().await;
+    ok.assert_async().await;
+}
+
+#[tokio::test]
+async fn errors_are_typed() {
+    let mut server = mockito::Server::new_async().await;
+    let missing = server.mock("GET", "/activities/1").with_status(404).expect(1).create_async().await;
+    server.mock("GET", "/activities/2").with_body("<html>oops</html>").create_async().await;
+    server.mock("GET", "/activities/3").with_status(500).expect(3).create_async().await;
+    let forbidden = server.mock("GET", "/activities/4").with_status(403).expect(1).create_async().await;
+    let (_dir, auth, _) = make_env(&server.url());
+
+    let err = auth.get_json::<serde_json::Value>(&format!("{}/activities/1", server.url())).await.unwrap_err();
+    assert!(matches!(err, StravaError::NotFound));
+    missing.assert_async().await;
+
+    let err = auth.get_json::<serde_json::Value>(&format!("{}/activities/2", server.url())).await.unwrap_err();
+    assert!(matches!(err, StravaError::Decode(_)));
+
+    let err = auth.get_json::<serde_json::Value>(&format!("{}/activities/3", server.url())).await.unwrap_err();
+    assert!(matches!(err, StravaError::Server(500)));
+
+    // a 403 is a permission problem of one resource, not of the token
+    let err = auth.get_json::<serde_json::Value>(&format!("{}/activities/4", server.url())).await.unwrap_err();
+    assert!(matches!(err, StravaError::Client(403)));
+    forbidden.assert_async().await;
+    // 429s are waited out when sending, so they are not retried again
+    assert!(!StravaError::RateLimited { until: None }.is_transient());
+}
+
+#[tokio::test]
+async fn failed_activity_is_skipped_and_recorded() {
+    let mut server = mockito::Server::new_async().await;
+    server
+        .mock("GET", "/athlete/activities")
+        .match_query(Matcher::Any)
+        .with_body(json!([
+            {"id": 1, "name": "broken", "start_date": "2024-01-02T00:00:00Z", "distance": 1.0},
+            {"id": 2, "name": "fine", "start_date": "2024-01-01T00:00:00Z", "distance": 1.0}
+        ]).to_string())
+        .create_async()
+        .await;
+    server.mock("GET", "/activities/1").with_status(502).create_async().await;
+    server
+        .mock("GET", "/activities/2")
+        .with_body(json!({"id": 2, "name": "fine", "start_date": "2024-01-01T00:00:00Z", "distance": 1.0}).to_string())
+        .create_async()
+        .await;
+    server
+        .mock("GET", "/activities/2/streams")
+        .match_query(Matcher::Any)
+        .with_body(json!({"time": {"data": [0, 1]}}).to_string())
+        .create_async()
+        .await;
+
+    let (_dir, auth, storage) = make_env(&server.url());
+    let report = fetch::download_latest(&auth, &storage, 2).await.unwrap();
+    assert_eq!(report.downloaded, 1);
+    assert_eq!(report.failed, vec![1]);
+    assert!(storage.activity_exists("2024", 2).await);
+    assert!(!storage.activity_exists("2024", 1).await);
+
+    let failures = storage.sync_failures().await.unwrap();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].id, 1);
+    assert!(failures[0].error.contains("502"));
+}
+
+#[tokio::test]
+async fn recorded_failures_are_retried() {
+    let mut server = mockito::Server::new_async().await;
+    server.mock("GET", "/athlete/activities").match_query(Matcher::Any).with_body("[]").create_async().await;
+    server
+        .mock("GET", "/activities/1")
+        .with_body(json!({"id": 1, "name": "late", "start_date": "2024-01-02T00:00:00Z", "distance": 1.0}).to_string())
+        .create_async()
+        .await;
+    server
+        .mock("GET", "/activities/1/streams")
+        .match_query(Matcher::Any)
+        .with_body(json!({"time": {"data": [0, 1]}}).to_string())
+        .create_async()
+        .await;
+    let forbidden = server.mock("GET", "/activities/2").with_status(403).expect(1).create_async().await;
+    let deleted = server.mock("GET", "/activities/3").with_status(404).expect(1).create_async().await;
+    let private = server.mock("GET", "/activities/4").expect(0).create_async().await;
+
+    let (_dir, auth, storage) = make_env(&server.url());
+    storage.record_sync_failure(1, FailureKind::Transient, "server error (status 502)").await.unwrap();
+    storage.record_sync_failure(2, FailureKind::Transient, "server error (status 502)").await.unwrap();
+    storage.record_sync_failure(3, FailureKind::Transient, "network error: timeout").await.unwrap();
+    storage.record_sync_failure(4, FailureKind::Permanent, "unexpected status 403").await.unwrap();
+    let report = fetch::download_latest(&auth, &storage, 2).await.unwrap();
+    assert_eq!((report.downloaded, report.failed), (1, vec![2, 3]));
+    assert!(storage.activity_exists("2024", 1).await);
+    // deleted activities are dropped, permanent failures kept but not retried
+    let failures = storage.sync_failures().await.unwrap();
+    assert_eq!(failures.iter().map(|f| (f.id, f.kind)).collect::<Vec<_>>(), vec![(4, FailureKind::Permanent), (2, FailureKind::Permanent)]);
+    assert_eq!(failures[1].error, "unexpected status 403");
+
+    let report = fetch::download_latest(&auth, &storage, 2).await.unwrap();
+    assert_eq!((report.downloaded, report.failed.len()), (0, 0));
+    forbidden.assert_async().await;
+    deleted.assert_async().await;
+    private.assert_async().await;
+
+    // failures recorded before the kind was stored are retried
+    let legacy: Vec<abcy_data::storage::SyncFailure> = serde_json::from_str(r#"[{"id": 5, "error": "x", "date": "2024-01-01"}]"#).unwrap();
+    assert_eq!(legacy[0].kind, FailureKind::Transient);
+}
@@ -76,3 +76,13 @@ async fn fitness_score_decay() {
     assert!((score - expected).abs() < 1e-6);
 }
 
+
+#[tokio::test]
+async fn fitness_score_without_recent_rides() {
+    let storage = make_storage();
+    assert_eq!(storage.update_fitness().await.unwrap(), 0.0);
+    // rides older than the four-week window do not count either
+    add_activity(&storage, 1, 40, 100000.0, 14400, 200.0).await;
+    assert_eq!(storage.update_fitness().await.unwrap(), 0.0);
+    assert_eq!(storage.fitness_history(None).await.unwrap().len(), 2);
+}
@@ -11,6 +11,7 @@ fn parse_time_stream() {
             time: vec![1, 2, 3],
             power: vec![],
             heartrate: vec![],
+            ..Default::default()
         }
     );
 }
@@ -22,3 +23,33 @@ fn parse_power_stream() {
     assert_eq!(parsed.power, vec![100, 200]);
     assert_eq!(parsed.heartrate, vec![90, 95]);
 }
+
+#[test]
+fn parse_extended_streams() {
+    let v = json!({
+        "time": {"data": [0, 1]},
+        "cadence": {"data": [85, 90]},
+        "velocity_smooth": {"data": [8.5, 9.0]},
+        "distance": {"data": [0.0, 9.0]},
+        "grade_smooth": {"data": [1.5, -0.5]},
+        "temp": {"data": [18, 19]},
+        "moving": {"data": [false, true]},
+        "altitude": {"data": [100.0, 100.4]},
+        "latlng": {"data": [[51.5, -0.12], [51.5001, -0.1201]]}
+    });
+    let parsed = parse_streams(&v).unwrap();
+    assert_eq!(parsed.cadence, Some(vec![85, 90]));
+    assert_eq!(parsed.velocity_smooth, Some(vec![8.5, 9.0]));
+    assert_eq!(parsed.distance, Some(vec![0.0, 9.0]));
+    assert_eq!(parsed.grade_smooth, Some(vec![1.5, -0.5]));
+    assert_eq!(parsed.temp, Some(vec![18, 19]));
+    assert_eq!(parsed.moving, Some(vec![false, true]));
+    assert_eq!(parsed.altitude, Some(vec![100.0, 100.4]));
+    assert_eq!(parsed.latlng, Some(vec![[51.5, -0.12], [51.5001, -0.1201]]));
+
+    // streams missing from the response stay absent
+    let parsed = parse_streams(&json!({"time": [0, 1], "heartrate": [90, 91]})).unwrap();
+    assert_eq!(parsed.heartrate, vec![90, 91]);
+    assert_eq!(parsed.cadence, None);
+    assert_eq!(parsed.latlng, None);
+}
@@ -0,0 +1,80 @@
+use abcy_data::{auth::Auth, storage::Storage, utils::Config, web, webhook};
+use actix_web::{test, App};
+use mockito::Matcher;
+use serde_json::json;
+use tempfile::{tempdir, TempDir};
+
+mod common;
+
+fn make_config(base_url: &str) -> (TempDir, Config) {
+    let dir = tempdir().unwrap();
+    let cfg = common::test_config_with(base_url, dir.path(), "[webhook]\nverify_token = \"secret\"\n");
+    (dir, cfg)
+}
+
+#[actix_rt::test]
+async fn challenge_is_echoed_for_matching_token() {
+    let (_dir, cfg) = make_config("http://localhost");
+    let storage = Storage::new(&cfg.storage);
+    let auth = Auth::new(cfg.clone());
+    let app = test::init_service(
+        App::new()
+            .app_data(actix_web::web::Data::new(cfg))
+            .app_data(actix_web::web::Data::new(auth))
+            .app_data(actix_web::web::Data::new(storage))
+            .configure(web::configure),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/webhook?hub.mode=subscribe&hub.challenge=15f7d1a91c1f40f8a748fd134752feb3&hub.verify_token=secret")
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body, json!({"hub.challenge": "15f7d1a91c1f40f8a748fd134752feb3"}));
+
+    let req = test::TestRequest::get()
+        .uri("/webhook?hub.mode=subscribe&hub.challenge=abc&hub.verify_token=wrong")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 403);
+}
+
+#[tokio::test]
+async fn subscriptions_are_managed() {
+    let mut server = mockito::Server::new_async().await;
+    let create = server
+        .mock("POST", "/push_subscriptions")
+        .match_body(Matcher::AllOf(vec![
+            Matcher::UrlEncoded("client_id".into(), "1".into()),
+            Matcher::UrlEncoded("callback_url".into(), "https://example.com/webhook".into()),
+            Matcher::UrlEncoded("verify_token".into(), "secret".into()),
+        ]))
+        .with_status(201)
+        .with_body(r#"{"id": 42}"#)
+        .create_async()
+        .await;
+    let list = server
+        .mock("GET", "/push_subscriptions")
+        .match_query(Matcher::UrlEncoded("client_secret".into(), "s".into()))
+        .with_body(json!([{"id": 42, "callback_url": "https://example.com/webhook", "created_at": "2024-01-01T00:00:00Z"}]).to_string())
+        .create_async()
+        .await;
+    let delete = server
+        .mock("DELETE", "/push_subscriptions/42")
+        .match_query(Matcher::Any)
+        .with_status(204)
+        .create_async()
+        .await;
+
+    let (_dir, cfg) = make_config(&server.url());
+    let client = reqwest::Client::new();
+    let id = webhook::create_subscription(&client, &cfg, "https://example.com/webhook").await.unwrap();
+    assert_eq!(id, 42);
+    let subs = webhook::list_subscriptions(&client, &cfg).await.unwrap();
+    assert_eq!(subs.len(), 1);
+    assert_eq!(subs[0].callback_url, "https://example.com/webhook");
+    webhook::delete_subscription(&client, &cfg, 42).await.unwrap();
+    create.assert_async().await;
+    list.assert_async().await;
+    delete.assert_async().await;
+}
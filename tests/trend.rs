@@ -8,6 +8,7 @@ fn make_storage() -> Storage {
     Storage::new(&cfg)
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn add_activity(storage: &Storage, id: u64, days_ago: i64, avg_speed: f64, max_speed: f64, tss: f64, intensity: f64, power: f64) {
     let dt = chrono::Utc::now().naive_utc().date() - chrono::Duration::days(days_ago);
     let date = dt.and_hms_opt(0, 0, 0).unwrap().format("%Y-%m-%dT%H:%M:%SZ").to_string();
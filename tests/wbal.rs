@@ -0,0 +1,127 @@
+use abcy_data::{
+    recompute::{recompute, MetricChange, RecomputeOptions},
+    schema::parse_streams,
+    storage::Storage,
+    utils::Storage as StorageCfg,
+    wbal::{matches_burned, wbal_metrics, wbal_stream, DEFAULT_W_PRIME},
+};
+use actix_web::{test::{call_and_read_body_json, call_service, init_service, TestRequest}, App};
+use serde_json::json;
+use tempfile::tempdir;
+
+fn make_storage(dir: &std::path::Path) -> Storage {
+    let cfg = StorageCfg { data_dir: dir.to_str().unwrap().into(), download_count: 1, user: "t".into() };
+    Storage::new(&cfg)
+}
+
+fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() < 1e-6
+}
+
+/// Three 1 min efforts at 450 W, each followed by 2 min at 100 W.
+fn intervals() -> serde_json::Value {
+    let watts: Vec<i64> = (0..3).flat_map(|_| [vec![450; 60], vec![100; 120]].concat()).collect();
+    json!({"time": (0..watts.len()).collect::<Vec<_>>(), "watts": watts})
+}
+
+#[test]
+fn skiba_balance_and_matches() {
+    let streams = json!({"time": (0..120).collect::<Vec<_>>(), "watts": ([vec![350; 60], vec![150; 60]].concat())});
+    let wbal = wbal_stream(&parse_streams(&streams).unwrap(), 250.0, 20000.0);
+    assert_eq!(wbal.len(), 120);
+    assert!(close(wbal[0], 19900.0));
+    assert!(close(wbal[59], 14000.0));
+    // recovery below CP is exponential towards W'
+    assert!(close(wbal[119], 20000.0 - 6000.0 * (-100.0 * 60.0 / 20000.0f64).exp()));
+
+    // a long recording gap is a pause
+    let paused = parse_streams(&json!({"time": [0, 1, 31], "watts": [400, 400, 400]})).unwrap();
+    let wbal = wbal_stream(&paused, 250.0, 20000.0);
+    assert!(close(wbal[1], 19700.0));
+    assert!(close(wbal[2], 20000.0 - 300.0 * (-250.0 * 30.0 / 20000.0f64).exp()));
+
+    let no_power = parse_streams(&json!({"time": [0, 1], "heartrate": [120, 121]})).unwrap();
+    assert!(wbal_stream(&no_power, 250.0, 20000.0).is_empty());
+    assert_eq!(wbal_metrics(&no_power, 250.0, 20000.0), None);
+
+    assert_eq!(matches_burned(&[20000.0, 17000.0, 17500.0, 19000.0, 18500.0, 16900.0, 16000.0, 16500.0]), 2);
+    // dips under 2 kJ and a slow recovery within one match do not count
+    assert_eq!(matches_burned(&[20000.0, 19000.0, 20000.0, 18500.0]), 0);
+    assert_eq!(matches_burned(&[20000.0, 17000.0, 17500.0, 15000.0, 15800.0, 13000.0]), 1);
+    let (min, matches) = wbal_metrics(&parse_streams(&intervals()).unwrap(), 250.0, 25200.0).unwrap();
+    assert_eq!(matches, 3);
+    assert!(min < 25200.0 - 12000.0);
+}
+
+#[actix_rt::test]
+async fn wbal_uses_the_athletes_cp() {
+    let dir = tempdir().unwrap();
+    let storage = make_storage(dir.path());
+    storage.add_ftp("2024-01-01", 240.0).await.unwrap();
+    // synced newest first, before the rides its CP is fitted to
+    storage.save(&json!({"id": 20, "name": "intervals", "start_date": "2024-03-20T07:00:00Z", "distance": 1.0}), &intervals()).await.unwrap();
+    // best efforts of P = 250 + 25200 / t in early March
+    for (i, secs) in [120, 180, 300, 420, 600, 900, 1200].into_iter().enumerate() {
+        let watts = 250 + 25200 / secs;
+        let streams = json!({"time": (0..secs).collect::<Vec<_>>(), "watts": vec![watts; secs as usize]});
+        let start = format!("2024-03-{:02}T07:00:00Z", i + 1);
+        storage.save(&json!({"id": i + 1, "name": "test", "start_date": start, "distance": 1.0}), &streams).await.unwrap();
+    }
+    // the first activity had no earlier efforts to fit
+    assert_eq!(storage.athlete_cp("2024-03-01T07:00:00Z").await.unwrap(), (240.0, DEFAULT_W_PRIME));
+    let (cp, w_prime) = storage.athlete_cp("2024-03-20T07:00:00Z").await.unwrap();
+    assert!(close(cp, 250.0) && (w_prime - 25200.0).abs() < 1e-3);
+    // efforts more than 90 days back are ignored
+    assert_eq!(storage.athlete_cp("2024-07-01T07:00:00Z").await.unwrap(), (240.0, DEFAULT_W_PRIME));
+
+    let wbal = storage.activity_wbal(20).await.unwrap();
+    assert_eq!(wbal.matches_burned, 3);
+    assert_eq!(wbal.wbal.len(), 540);
+    assert_eq!(wbal.time.len(), 540);
+    assert!(close(wbal.wbal[59], 25200.0 - 200.0 * 60.0));
+    // summaries carry the metrics once recompute has stored them
+    assert_eq!(storage.load_activity_summary(20).await.unwrap().matches_burned, None);
+    let indexed = storage.activity_summaries().await.unwrap();
+    assert_eq!(indexed.iter().find(|s| s.id == 20).unwrap().matches_burned, None);
+    let report = recompute(&storage, &RecomputeOptions::default()).await.unwrap();
+    let change = report.changes.iter().find(|c| c.id == 20).unwrap();
+    assert_eq!(change.matches_burned, Some(MetricChange { before: None, after: 3.0 }));
+    assert_eq!(change.min_wbal.as_ref().unwrap().after, wbal.min_wbal.unwrap());
+    let indexed = storage.activity_summaries().await.unwrap();
+    assert_eq!(indexed.iter().find(|s| s.id == 20).unwrap().matches_burned, Some(3));
+    assert_eq!(storage.load_activity(20).await.unwrap().meta["matches_burned"], 3);
+    let summary = storage.load_activity_summary(20).await.unwrap();
+    assert_eq!((summary.min_wbal, summary.matches_burned), (wbal.min_wbal, Some(3)));
+    let report = recompute(&storage, &RecomputeOptions::default()).await.unwrap();
+    assert!(report.changes.iter().all(|c| c.id != 20));
+
+    // edits from Strava keep them, a re-downloaded copy derives them again
+    storage.update_meta(&json!({"id": 20, "name": "renamed", "start_date": "2024-03-20T07:00:00Z", "distance": 1.0})).await.unwrap();
+    let indexed = storage.activity_summaries().await.unwrap();
+    let entry = indexed.iter().find(|s| s.id == 20).unwrap();
+    assert_eq!((entry.name.as_str(), entry.matches_burned), ("renamed", Some(3)));
+    storage.replace(&json!({"id": 20, "name": "again", "start_date": "2024-03-20T07:00:00Z", "distance": 1.0}), &intervals()).await.unwrap();
+    let indexed = storage.activity_summaries().await.unwrap();
+    assert_eq!(indexed.iter().find(|s| s.id == 20).unwrap().min_wbal, wbal.min_wbal);
+
+    storage.save(&json!({"id": 21, "name": "run", "start_date": "2024-03-21T07:00:00Z", "distance": 1.0}), &json!({"time": [0, 1], "heartrate": [120, 121]})).await.unwrap();
+    let summary = storage.load_activity_summary(21).await.unwrap();
+    assert_eq!((summary.min_wbal, summary.matches_burned), (None, None));
+
+    let app = init_service(
+        App::new()
+            .app_data(actix_web::web::Data::new(storage.clone()))
+            .configure(abcy_data::web::configure),
+    )
+    .await;
+    let req = TestRequest::get().uri("/activity/20/wbal").to_request();
+    let body: serde_json::Value = call_and_read_body_json(&app, req).await;
+    assert!(close(body["cp"].as_f64().unwrap(), 250.0));
+    assert_eq!(body["matches_burned"], 3);
+    assert_eq!(body["wbal"].as_array().unwrap().len(), 540);
+    let req = TestRequest::get().uri("/activity/20/summary").to_request();
+    let body: serde_json::Value = call_and_read_body_json(&app, req).await;
+    assert_eq!(body["matches_burned"], 3);
+    let req = TestRequest::get().uri("/activity/99/wbal").to_request();
+    assert_eq!(call_service(&app, req).await.status(), 404);
+}
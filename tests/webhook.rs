@@ -0,0 +1,218 @@
+use abcy_data::{
+    auth::Auth,
+    import::{synthetic_id, ImportFormat},
+    storage::Storage,
+    webhook::{handle_event, WebhookEvent},
+};
+use mockito::Matcher;
+use serde_json::json;
+use tempfile::{tempdir, TempDir};
+
+mod common;
+
+const ATHLETE: u64 = 9;
+const SUBSCRIPTION: u64 = 77;
+
+/// Storage with a registered subscription and a token authorized by [`ATHLETE`].
+async fn make_env(base_url: &str) -> (TempDir, Auth, Storage, std::path::PathBuf) {
+    let dir = tempdir().unwrap();
+    let token = json!({"access_token": "abc", "expires_at": chrono::Utc::now().timestamp() + 3600, "athlete_id": ATHLETE});
+    let token_path = common::write_token(dir.path(), &token);
+    let cfg = common::test_config(base_url, dir.path());
+    let storage = Storage::new(&cfg.storage);
+    storage.save_subscription_id(Some(SUBSCRIPTION)).await.unwrap();
+    (dir, Auth::new(cfg), storage, token_path)
+}
+
+/// Event of the registered subscription for [`ATHLETE`] unless `value` overrides them.
+fn event(value: serde_json::Value) -> WebhookEvent {
+    let mut ev = json!({"owner_id": ATHLETE, "subscription_id": SUBSCRIPTION});
+    ev.as_object_mut().unwrap().extend(value.as_object().unwrap().clone());
+    serde_json::from_value(ev).unwrap()
+}
+
+#[tokio::test]
+async fn create_downloads_event_activity() {
+    let mut server = mockito::Server::new_async().await;
+    let meta = server
+        .mock("GET", "/activities/5")
+        .with_body(json!({"id": 5, "name": "new ride", "start_date": "2024-05-01T00:00:00Z", "distance": 1.0}).to_string())
+        .create_async()
+        .await;
+    server
+        .mock("GET", "/activities/5/streams")
+        .match_query(Matcher::Any)
+        .with_body(json!({"time": {"data": [0, 1]}}).to_string())
+        .create_async()
+        .await;
+    let (_dir, auth, storage, _) = make_env(&server.url()).await;
+    let ev = event(json!({"object_type": "activity", "aspect_type": "create", "object_id": 5}));
+    handle_event(&auth, &storage, &ev).await.unwrap();
+    meta.assert_async().await;
+    assert!(storage.activity_exists("2024", 5).await);
+}
+
+#[tokio::test]
+async fn update_refreshes_metadata_and_keeps_streams() {
+    let mut server = mockito::Server::new_async().await;
+    server
+        .mock("GET", "/activities/5")
+        .with_body(json!({"id": 5, "name": "renamed", "type": "VirtualRide", "private": true, "start_date": "2024-05-01T00:00:00Z", "distance": 1.0}).to_string())
+        .create_async()
+        .await;
+    let streams_mock = server.mock("GET", "/activities/5/streams").match_query(Matcher::Any).expect(0).create_async().await;
+    let (_dir, auth, storage, _) = make_env(&server.url()).await;
+    let meta = json!({"id": 5, "name": "morning", "type": "Ride", "start_date": "2024-05-01T00:00:00Z", "distance": 1.0});
+    let streams = json!({"time": {"data": [0, 1, 2]}, "watts": {"data": [200, 210, 220]}});
+    storage.save(&meta, &streams).await.unwrap();
+
+    let ev = event(json!({"object_type": "activity", "aspect_type": "update", "object_id": 5, "updates": {"title": "renamed", "type": "VirtualRide", "private": "true"}}));
+    handle_event(&auth, &storage, &ev).await.unwrap();
+    streams_mock.assert_async().await;
+    let act = storage.load_activity(5).await.unwrap();
+    assert_eq!(act.meta["name"], "renamed");
+    assert_eq!(act.meta["type"], "VirtualRide");
+    assert_eq!(act.meta["private"], true);
+    assert!(act.meta["normalized_power"].as_f64().unwrap() > 0.0);
+    assert_eq!(act.streams.power, vec![200, 210, 220]);
+}
+
+#[tokio::test]
+async fn delete_removes_activity() {
+    let server = mockito::Server::new_async().await;
+    let (_dir, auth, storage, _) = make_env(&server.url()).await;
+    let meta = json!({"id": 5, "name": "ride", "start_date": "2024-05-01T00:00:00Z", "distance": 1.0});
+    storage.save(&meta, &json!({"time": [0, 1]})).await.unwrap();
+
+    let ev = event(json!({"object_type": "activity", "aspect_type": "delete", "object_id": 5}));
+    handle_event(&auth, &storage, &ev).await.unwrap();
+    assert!(!storage.activity_exists("2024", 5).await);
+    assert!(storage.load_activity(5).await.is_err());
+    // deleting again is a no-op
+    handle_event(&auth, &storage, &ev).await.unwrap();
+}
+
+#[tokio::test]
+async fn deauthorize_wipes_token() {
+    let server = mockito::Server::new_async().await;
+    let (_dir, auth, storage, token_path) = make_env(&server.url()).await;
+    let ev = event(json!({"object_type": "athlete", "aspect_type": "update", "object_id": 9, "updates": {"authorized": "false"}}));
+    handle_event(&auth, &storage, &ev).await.unwrap();
+    assert!(!token_path.exists());
+}
+
+#[tokio::test]
+async fn foreign_events_are_dropped() {
+    let server = mockito::Server::new_async().await;
+    let (_dir, auth, storage, token_path) = make_env(&server.url()).await;
+    let meta = json!({"id": 5, "name": "ride", "start_date": "2024-05-01T00:00:00Z", "distance": 1.0});
+    storage.save(&meta, &json!({"time": [0, 1]})).await.unwrap();
+
+    for ev in [
+        event(json!({"object_type": "activity", "aspect_type": "delete", "object_id": 5, "owner_id": 10})),
+        event(json!({"object_type": "activity", "aspect_type": "delete", "object_id": 5, "subscription_id": 1})),
+        event(json!({"object_type": "athlete", "aspect_type": "update", "object_id": 10, "owner_id": 10, "updates": {"authorized": "false"}})),
+    ] {
+        handle_event(&auth, &storage, &ev).await.unwrap();
+    }
+    assert!(storage.activity_exists("2024", 5).await);
+    assert!(token_path.exists());
+
+    // without a registered subscription the athlete alone decides
+    storage.save_subscription_id(None).await.unwrap();
+    let ev = event(json!({"object_type": "activity", "aspect_type": "delete", "object_id": 5, "owner_id": 10, "subscription_id": 1}));
+    handle_event(&auth, &storage, &ev).await.unwrap();
+    assert!(storage.activity_exists("2024", 5).await);
+    let ev = event(json!({"object_type": "activity", "aspect_type": "delete", "object_id": 5, "subscription_id": 1}));
+    handle_event(&auth, &storage, &ev).await.unwrap();
+    assert!(!storage.activity_exists("2024", 5).await);
+
+    // ids are required rather than defaulting to 0
+    let partial = json!({"object_type": "activity", "aspect_type": "delete", "object_id": 5});
+    assert!(serde_json::from_value::<WebhookEvent>(partial).is_err());
+}
+
+#[tokio::test]
+async fn imported_activities_are_not_deleted() {
+    let server = mockito::Server::new_async().await;
+    let (_dir, auth, storage, _) = make_env(&server.url()).await;
+    let id = synthetic_id(ImportFormat::Gpx, 1714521600);
+    let meta = json!({"id": id, "name": "import", "start_date": "2024-05-01T00:00:00Z", "distance": 1.0});
+    storage.save(&meta, &json!({"time": [0, 1]})).await.unwrap();
+
+    let ev = event(json!({"object_type": "activity", "aspect_type": "delete", "object_id": id}));
+    handle_event(&auth, &storage, &ev).await.unwrap();
+    assert!(storage.activity_exists("2024", id).await);
+}
+
+#[tokio::test]
+async fn configured_subscription_is_accepted() {
+    let server = mockito::Server::new_async().await;
+    let dir = tempdir().unwrap();
+    common::write_token(dir.path(), &json!({"access_token": "abc", "expires_at": chrono::Utc::now().timestamp() + 3600, "athlete_id": ATHLETE}));
+    // created by hand, so the subscriptions binary never recorded it
+    let cfg = common::test_config_with(&server.url(), dir.path(), "[webhook]\nsubscription_id = 78\n");
+    let storage = Storage::new(&cfg.storage);
+    storage.save_subscription_id(Some(SUBSCRIPTION)).await.unwrap();
+    let auth = Auth::new(cfg);
+    let meta = json!({"id": 5, "name": "ride", "start_date": "2024-05-01T00:00:00Z", "distance": 1.0});
+    storage.save(&meta, &json!({"time": [0, 1]})).await.unwrap();
+
+    let ev = event(json!({"object_type": "activity", "aspect_type": "delete", "object_id": 5}));
+    handle_event(&auth, &storage, &ev).await.unwrap();
+    assert!(storage.activity_exists("2024", 5).await);
+    let ev = event(json!({"object_type": "activity", "aspect_type": "delete", "object_id": 5, "subscription_id": 78}));
+    handle_event(&auth, &storage, &ev).await.unwrap();
+    assert!(!storage.activity_exists("2024", 5).await);
+}
+
+#[tokio::test]
+async fn events_never_ask_strava_for_the_athlete() {
+    let mut server = mockito::Server::new_async().await;
+    let athlete = server.mock("GET", "/athlete").with_status(401).expect(0).create_async().await;
+    let refresh = server.mock("POST", "/oauth/token").with_status(400).expect(0).create_async().await;
+    let (dir, auth, storage, token_path) = make_env(&server.url()).await;
+    // a revoked token stored before the athlete id was recorded
+    common::write_token(dir.path(), &json!({"access_token": "revoked", "expires_at": 0, "refresh_token": "r1"}));
+    let meta = json!({"id": 5, "name": "ride", "start_date": "2024-05-01T00:00:00Z", "distance": 1.0});
+    storage.save(&meta, &json!({"time": [0, 1]})).await.unwrap();
+
+    // the registered subscription vouches for the event
+    let ev = event(json!({"object_type": "activity", "aspect_type": "delete", "object_id": 5}));
+    handle_event(&auth, &storage, &ev).await.unwrap();
+    assert!(!storage.activity_exists("2024", 5).await);
+    let ev = event(json!({"object_type": "athlete", "aspect_type": "update", "object_id": ATHLETE, "updates": {"authorized": "false"}}));
+    handle_event(&auth, &storage, &ev).await.unwrap();
+    assert!(!token_path.exists());
+    athlete.assert_async().await;
+    refresh.assert_async().await;
+
+    // without a subscription either, nothing vouches for the event
+    storage.save_subscription_id(None).await.unwrap();
+    common::write_token(dir.path(), &json!({"access_token": "abc", "expires_at": chrono::Utc::now().timestamp() + 3600}));
+    storage.save(&meta, &json!({"time": [0, 1]})).await.unwrap();
+    let ev = event(json!({"object_type": "activity", "aspect_type": "delete", "object_id": 5}));
+    handle_event(&auth, &storage, &ev).await.unwrap();
+    assert!(storage.activity_exists("2024", 5).await);
+}
+
+#[tokio::test]
+async fn athlete_id_is_looked_up_for_older_tokens() {
+    let mut server = mockito::Server::new_async().await;
+    let athlete = server
+        .mock("GET", "/athlete")
+        .with_body(json!({"id": ATHLETE}).to_string())
+        .expect(1)
+        .create_async()
+        .await;
+    let (dir, auth, _storage, token_path) = make_env(&server.url()).await;
+    common::write_valid_token(dir.path());
+    assert_eq!(auth.stored_athlete_id().await, None);
+    assert_eq!(auth.athlete_id().await.unwrap(), ATHLETE);
+    let saved: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(token_path).unwrap()).unwrap();
+    assert_eq!(saved["athlete_id"], ATHLETE);
+    // the stored id is used from then on
+    assert_eq!(auth.athlete_id().await.unwrap(), ATHLETE);
+    assert_eq!(auth.stored_athlete_id().await, Some(ATHLETE));
+    athlete.assert_async().await;
+}
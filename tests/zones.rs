@@ -0,0 +1,129 @@
+use abcy_data::{
+    stats::Period,
+    storage::Storage,
+    utils::{Storage as StorageCfg, Zones},
+    zones::{time_in_zones, zone_of, ZoneBounds},
+};
+use actix_web::{test::{call_and_read_body_json, call_service, init_service, TestRequest}, App};
+use serde_json::json;
+use tempfile::tempdir;
+
+fn make_storage(dir: &std::path::Path) -> Storage {
+    let cfg = StorageCfg { data_dir: dir.to_str().unwrap().into(), download_count: 1, user: "t".into() };
+    Storage::new(&cfg)
+}
+
+#[test]
+fn zones_from_thresholds() {
+    assert_eq!((zone_of(100.0, &[100.0, 200.0]), zone_of(100.5, &[100.0, 200.0]), zone_of(900.0, &[100.0, 200.0])), (0, 1, 2));
+    assert_eq!(time_in_zones(&[0, 1, 2, 3], &[50, 100, 150, 250], &[100.0, 200.0]), Some(vec![2, 1, 1]));
+    // a sample counts for the time since the previous one, pauses for a second
+    assert_eq!(time_in_zones(&[0, 3, 20], &[50, 150, 150], &[100.0, 200.0]), Some(vec![1, 4, 0]));
+    assert_eq!(time_in_zones(&[0, 1], &[], &[100.0]), None);
+
+    let zones = Zones::default();
+    let coggan = ZoneBounds::new(&zones, 200.0, None, None);
+    assert_eq!(coggan.power, vec![110.0, 150.0, 180.0, 210.0, 240.0, 300.0]);
+    assert_eq!(coggan.heartrate, None);
+    let max_hr = ZoneBounds::new(&zones, 200.0, None, Some(200.0));
+    assert_eq!(max_hr.heartrate, Some(vec![120.0, 140.0, 160.0, 180.0]));
+    // LTHR takes precedence over max HR
+    let friel = ZoneBounds::new(&zones, 200.0, Some(150.0), Some(200.0));
+    assert_eq!(friel.heartrate.unwrap().len(), 6);
+}
+
+#[test]
+fn zone_bounds_are_validated() {
+    let zones: Zones = toml::from_str("power = [50, 100]").unwrap();
+    assert_eq!((zones.power, zones.lthr.len()), (vec![50.0, 100.0], 6));
+    for bad in ["power = []", "lthr = [90, 80]", "max_hr = [60, 60, 70]", "power = [50, nan]"] {
+        let err = toml::from_str::<Zones>(bad).unwrap_err().to_string();
+        assert!(err.contains("zone bounds"), "{}: {}", bad, err);
+    }
+}
+
+#[actix_rt::test]
+async fn time_in_zone_per_activity_and_period() {
+    let dir = tempdir().unwrap();
+    let storage = make_storage(dir.path());
+    storage.add_ftp("2024-01-01", 200.0).await.unwrap();
+    storage.add_max_hr("2024-01-01", 200.0).await.unwrap();
+    let ride = json!({"time": (0..10).collect::<Vec<_>>(), "watts": [100, 100, 100, 100, 250, 250, 250, 250, 250, 250], "heartrate": vec![150; 10]});
+    storage.save(&json!({"id": 1, "name": "a", "start_date": "2024-03-01T07:00:00Z", "distance": 1.0}), &ride).await.unwrap();
+    let sprint = json!({"time": (0..5).collect::<Vec<_>>(), "watts": vec![320; 5]});
+    storage.save(&json!({"id": 2, "name": "b", "start_date": "2024-03-02T07:00:00Z", "distance": 1.0}), &sprint).await.unwrap();
+    storage.save(&json!({"id": 3, "name": "c", "start_date": "2024-03-03T07:00:00Z", "distance": 1.0}), &json!({"time": [0, 1]})).await.unwrap();
+
+    let summary = storage.load_activity_summary(1).await.unwrap();
+    assert_eq!(summary.time_in_power_zones, Some(vec![4, 0, 0, 0, 0, 6, 0]));
+    assert_eq!(summary.time_in_hr_zones, Some(vec![0, 0, 10, 0, 0]));
+    let summary = storage.load_activity_summary(3).await.unwrap();
+    assert_eq!((summary.time_in_power_zones, summary.time_in_hr_zones), (None, None));
+
+    let stats = storage.activity_stats(Period::Month, None, None).await.unwrap();
+    assert_eq!(stats[0].time_in_power_zones, Some(vec![4, 0, 0, 0, 0, 6, 5]));
+    assert_eq!(stats[0].time_in_hr_zones, Some(vec![0, 0, 10, 0, 0]));
+
+    // with an LTHR the seven Friel zones apply, in the index too
+    storage.add_lthr("2024-02-01", 170.0).await.unwrap();
+    assert_eq!(storage.load_activity_summary(1).await.unwrap().time_in_hr_zones, Some(vec![0, 10, 0, 0, 0, 0, 0]));
+    let stats = storage.activity_stats(Period::Day, None, None).await.unwrap();
+    assert_eq!(stats[0].time_in_hr_zones, Some(vec![0, 10, 0, 0, 0, 0, 0]));
+    assert_eq!(stats[1].time_in_hr_zones, None);
+
+    // an FTP entry moves the power zones of the rides from its date on
+    storage.add_ftp("2024-03-02", 300.0).await.unwrap();
+    let stats = storage.activity_stats(Period::Day, None, None).await.unwrap();
+    assert_eq!(stats[0].time_in_power_zones, Some(vec![4, 0, 0, 0, 0, 6, 0]));
+    assert_eq!(stats[1].time_in_power_zones, Some(vec![0, 0, 0, 0, 5, 0, 0]));
+    // the earliest entry also applies to older rides
+    storage.update_ftp_entry("2024-01-01", 100.0).await.unwrap();
+    let stats = storage.activity_stats(Period::Day, None, None).await.unwrap();
+    assert_eq!(stats[0].time_in_power_zones, Some(vec![0, 0, 0, 4, 0, 0, 6]));
+    assert!(storage.delete_ftp_entry("2024-03-02").await.unwrap());
+    let stats = storage.activity_stats(Period::Day, None, None).await.unwrap();
+    assert_eq!(stats[1].time_in_power_zones, Some(vec![0, 0, 0, 0, 0, 0, 5]));
+
+    let custom = make_storage(dir.path()).with_zones(Zones { power: vec![100.0], ..Zones::default() });
+    assert_eq!(custom.load_activity_summary(1).await.unwrap().time_in_power_zones, Some(vec![4, 6]));
+}
+
+#[actix_rt::test]
+async fn zone_endpoints() {
+    let dir = tempdir().unwrap();
+    let storage = make_storage(dir.path());
+    storage.set_ftp(300.0).await.unwrap();
+    let app = init_service(
+        App::new()
+            .app_data(actix_web::web::Data::new(storage.clone()))
+            .configure(abcy_data::web::configure),
+    )
+    .await;
+
+    let req = TestRequest::get().uri("/zones").to_request();
+    let body: serde_json::Value = call_and_read_body_json(&app, req).await;
+    assert_eq!(body["ftp"], 300.0);
+    assert_eq!(body["power"], json!([165.0, 225.0, 270.0, 315.0, 360.0, 450.0]));
+    assert!(body["heartrate"].is_null() && body["lthr"].is_null());
+
+    let req = TestRequest::post().uri("/max-hr").set_json(json!({"max_hr": 190, "date": "2024-01-01"})).to_request();
+    assert_eq!(call_service(&app, req).await.status(), 200);
+    let req = TestRequest::post().uri("/lthr").set_json(json!({"lthr": 160})).to_request();
+    assert_eq!(call_service(&app, req).await.status(), 200);
+    let req = TestRequest::post().uri("/lthr").set_json(json!({"lthr": 160, "date": "01/02/2024"})).to_request();
+    assert_eq!(call_service(&app, req).await.status(), 400);
+
+    let req = TestRequest::get().uri("/lthr/history").to_request();
+    let body: serde_json::Value = call_and_read_body_json(&app, req).await;
+    assert_eq!(body.as_array().unwrap().len(), 1);
+    assert_eq!(body[0]["lthr"], 160.0);
+    let req = TestRequest::get().uri("/max-hr/history?count=1").to_request();
+    let body: serde_json::Value = call_and_read_body_json(&app, req).await;
+    assert_eq!(body, json!([{"date": "2024-01-01", "max_hr": 190.0}]));
+
+    let req = TestRequest::get().uri("/zones").to_request();
+    let body: serde_json::Value = call_and_read_body_json(&app, req).await;
+    assert_eq!((body["lthr"].clone(), body["max_hr"].clone()), (json!(160.0), json!(190.0)));
+    assert_eq!(body["heartrate"].as_array().unwrap().len(), 6);
+    assert!(dir.path().join("t/lthr.json").exists());
+}
@@ -21,6 +21,6 @@ async fn round_trip() {
     assert_eq!(act.meta["distance"], meta["distance"]);
     assert_eq!(
         act.streams,
-        ParsedStreams { time: vec![1,2,3], power: vec![10,20], heartrate: vec![80,81,82] }
+        ParsedStreams { time: vec![1,2,3], power: vec![10,20], heartrate: vec![80,81,82], ..Default::default() }
     );
 }